@@ -0,0 +1,427 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use core::time::Duration;
+
+use crate::{Error, Result};
+
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Read-only, hidden, system and volume ID together mark a long name slot.
+pub const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// The first name byte of a free slot.
+pub const DELETED: u8 = 0xe5;
+/// The first name byte of the slot after the last one in use.
+pub const END: u8 = 0x00;
+/// Stands for a real leading `0xe5`, which would read as deleted.
+const KANJI_E5: u8 = 0x05;
+
+/// `NTRes` bits, as Windows NT sets them for an all-lowercase base name or extension.
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXTENSION: u8 = 0x10;
+
+/// Set in the order of the last slot of a long name, which comes first on disk.
+const LONG_NAME_LAST: u8 = 0x40;
+const LONG_NAME_ORDER: u8 = 0x1f;
+/// UCS-2 characters per long name slot, at these offsets.
+const LONG_NAME_CHARS: usize = 13;
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The longest long name, in UCS-2 characters.
+const LONG_NAME_MAX: usize = 255;
+
+/// Characters a short name may hold besides uppercase letters and digits.
+const SHORT_NAME_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+/// Characters no name may hold.
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// A short, 8.3 directory entry: the one that describes the file.
+#[derive(Debug, Clone)]
+pub struct ShortEntry {
+    /// Base name and extension, padded with spaces.
+    pub name: [u8; 11],
+    pub attr: u8,
+    pub ntres: u8,
+    pub create_tenths: u8,
+    pub create_time: u16,
+    pub create_date: u16,
+    pub access_date: u16,
+    /// 0 for an empty file.
+    pub first_cluster: u32,
+    pub write_time: u16,
+    pub write_date: u16,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn new(name: [u8; 11], ntres: u8, attr: u8, now: Duration) -> ShortEntry {
+        let (date, time, tenths) = to_fat_time(now);
+        ShortEntry {
+            name,
+            attr,
+            ntres,
+            create_tenths: tenths,
+            create_time: time,
+            create_date: date,
+            access_date: date,
+            first_cluster: 0,
+            write_time: time,
+            write_date: date,
+            size: 0,
+        }
+    }
+
+    pub fn parse(raw: &[u8]) -> ShortEntry {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let mut name = [0; 11];
+        name.copy_from_slice(&raw[..11]);
+        ShortEntry {
+            name,
+            attr: raw[11],
+            ntres: raw[12],
+            create_tenths: raw[13],
+            create_time: u16_at(14),
+            create_date: u16_at(16),
+            access_date: u16_at(18),
+            first_cluster: (u16_at(20) as u32) << 16 | u16_at(26) as u32,
+            write_time: u16_at(22),
+            write_date: u16_at(24),
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut raw = [0; DIR_ENTRY_SIZE];
+        raw[..11].copy_from_slice(&self.name);
+        raw[11] = self.attr;
+        raw[12] = self.ntres;
+        raw[13] = self.create_tenths;
+        raw[14..16].copy_from_slice(&self.create_time.to_le_bytes());
+        raw[16..18].copy_from_slice(&self.create_date.to_le_bytes());
+        raw[18..20].copy_from_slice(&self.access_date.to_le_bytes());
+        raw[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        raw[22..24].copy_from_slice(&self.write_time.to_le_bytes());
+        raw[24..26].copy_from_slice(&self.write_date.to_le_bytes());
+        raw[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size.to_le_bytes());
+        raw
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// `.` or `..`, which the VFS answers itself.
+    pub fn is_dot(&self) -> bool {
+        &self.name == b".          " || &self.name == b"..         "
+    }
+
+    /// The name as `BASE.EXT`, lowercased as `NTRes` says.
+    pub fn display_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == KANJI_E5 {
+            name[0] = DELETED;
+        }
+        let decode = |bytes: &[u8], lowercase: bool| -> String {
+            bytes
+                .iter()
+                .take_while(|byte| **byte != b' ')
+                .map(|byte| match lowercase {
+                    true => byte.to_ascii_lowercase() as char,
+                    false => *byte as char,
+                })
+                .collect()
+        };
+        let base = decode(&name[..8], self.ntres & LOWERCASE_BASE != 0);
+        let extension = decode(&name[8..], self.ntres & LOWERCASE_EXTENSION != 0);
+        match extension.is_empty() {
+            true => base,
+            false => format!("{}.{}", base, extension),
+        }
+    }
+
+    pub fn set_modified(&mut self, now: Duration) {
+        let (date, time, _) = to_fat_time(now);
+        self.write_date = date;
+        self.write_time = time;
+        self.access_date = date;
+    }
+
+    pub fn modified(&self) -> Duration {
+        from_fat_time(self.write_date, self.write_time)
+    }
+
+    pub fn accessed(&self) -> Duration {
+        from_fat_time(self.access_date, 0)
+    }
+}
+
+/// The checksum of a short name that its long name slots carry.
+pub fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Whether a raw slot belongs to a long name.
+pub fn is_long_name(raw: &[u8]) -> bool {
+    raw[11] & 0x3f == ATTR_LONG_NAME
+}
+
+/// Collects the long name slots in front of a short entry.
+///
+/// - Slots out of order, or with a checksum other than the short entry's, are orphans left by
+///   a file system without long name support; the short name stands alone then.
+#[derive(Default)]
+pub struct LongName {
+    units: Vec<u16>,
+    /// The order of the slot read last; 1 once the name is complete.
+    order: u8,
+    checksum: u8,
+    /// Where the slots lie, in disk order.
+    pub positions: Vec<u64>,
+}
+
+impl LongName {
+    pub fn push(&mut self, raw: &[u8], position: u64) {
+        let order = raw[0] & LONG_NAME_ORDER;
+        if raw[0] & LONG_NAME_LAST != 0 {
+            self.units = vec![0xffff; order as usize * LONG_NAME_CHARS];
+            self.checksum = raw[13];
+            self.positions.clear();
+        } else if order == 0 || order + 1 != self.order || raw[13] != self.checksum {
+            self.clear();
+            return;
+        }
+        if order == 0 {
+            self.clear();
+            return;
+        }
+        self.order = order;
+        self.positions.push(position);
+        let start = (order as usize - 1) * LONG_NAME_CHARS;
+        for (i, offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+            self.units[start + i] = u16::from_le_bytes([raw[*offset], raw[offset + 1]]);
+        }
+    }
+
+    /// The long name of `entry`, if the slots collected make one; resets for the next entry.
+    pub fn take(&mut self, entry: &ShortEntry) -> Option<String> {
+        let name = (self.order == 1 && self.checksum == checksum(&entry.name)).then(|| {
+            let len = self
+                .units
+                .iter()
+                .position(|unit| *unit == 0)
+                .unwrap_or(self.units.len());
+            char::decode_utf16(self.units[..len].iter().copied())
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        });
+        if name.is_none() {
+            self.positions.clear();
+        }
+        self.order = 0;
+        name
+    }
+
+    pub fn clear(&mut self) {
+        self.order = 0;
+        self.positions.clear();
+    }
+}
+
+/// The long name slots of `name`, in disk order, to precede a short entry named `short_name`.
+pub fn long_name_slots(name: &str, short_name: &[u8; 11]) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LONG_NAME_CHARS);
+    // NUL-terminated unless it fills the last slot, then padded with 0xffff.
+    if units.len() < count * LONG_NAME_CHARS {
+        units.push(0);
+    }
+    units.resize(count * LONG_NAME_CHARS, 0xffff);
+    let checksum = checksum(short_name);
+    (1..=count)
+        .rev()
+        .map(|order| {
+            let mut raw = [0; DIR_ENTRY_SIZE];
+            raw[0] = order as u8 | if order == count { LONG_NAME_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = checksum;
+            let chars = &units[(order - 1) * LONG_NAME_CHARS..order * LONG_NAME_CHARS];
+            for (unit, offset) in chars.iter().zip(LONG_NAME_OFFSETS) {
+                raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
+
+/// Check that `name` may be stored as a long name.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Error::InvalidInput);
+    }
+    if name.encode_utf16().count() > LONG_NAME_MAX {
+        return Err(Error::NameTooLong);
+    }
+    // Windows drops trailing dots and spaces, so such a name could not be found again.
+    if name.ends_with(['.', ' '])
+        || name
+            .chars()
+            .any(|c| (c as u32) < 0x20 || INVALID_CHARS.contains(&c))
+    {
+        return Err(Error::InvalidInput);
+    }
+    Ok(())
+}
+
+fn is_short_name_byte(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_NAME_SPECIAL.contains(&byte)
+}
+
+/// The 8.3 form of `name` and the `NTRes` bits that restore its case, if it needs no long name.
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    let mut ntres = 0;
+    let (base_field, extension_field) = short_name.split_at_mut(8);
+    for (part, field, lowercase) in [
+        (base, base_field, LOWERCASE_BASE),
+        (extension, extension_field, LOWERCASE_EXTENSION),
+    ] {
+        let has_lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+        if has_lower && has_upper {
+            return None;
+        }
+        if has_lower {
+            ntres |= lowercase;
+        }
+        for (slot, byte) in field.iter_mut().zip(part.bytes()) {
+            let byte = byte.to_ascii_uppercase();
+            if !is_short_name_byte(byte) {
+                return None;
+            }
+            *slot = byte;
+        }
+    }
+    Some((short_name, ntres))
+}
+
+/// A `BASIS~N.EXT` short name for the long `name` that `is_taken` does not reject.
+pub fn generate_short_name(name: &str, is_taken: impl Fn(&[u8; 11]) -> bool) -> Result<[u8; 11]> {
+    let to_short = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| {
+                let byte = c.to_ascii_uppercase() as u32 as u8;
+                match c.is_ascii() && is_short_name_byte(byte) {
+                    true => byte,
+                    false => b'_',
+                }
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, extension) = match trimmed.rsplit_once('.') {
+        Some((base, extension)) => (to_short(base), to_short(extension)),
+        None => (to_short(trimmed), Vec::new()),
+    };
+    let base = match base.is_empty() {
+        true => vec![b'_'],
+        false => base,
+    };
+
+    let mut short_name = [b' '; 11];
+    for (slot, byte) in short_name[8..].iter_mut().zip(&extension) {
+        *slot = *byte;
+    }
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let base_len = base.len().min(8 - tail.len());
+        short_name[..8].fill(b' ');
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        if !is_taken(&short_name) {
+            return Ok(short_name);
+        }
+    }
+    Err(Error::NoSpace)
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// FAT dates count years from 1980 in 7 bits.
+const FIRST_YEAR: i64 = 1980;
+const LAST_YEAR: i64 = FIRST_YEAR + 127;
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The proleptic Gregorian date `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// A date, a time with 2-second resolution and the hundredths left over, taking UTC as local
+/// time and clamping to the years FAT can store.
+pub fn to_fat_time(since_epoch: Duration) -> (u16, u16, u8) {
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / SECONDS_PER_DAY) as i64);
+    if year < FIRST_YEAR {
+        return (1 << 5 | 1, 0, 0);
+    }
+    if year > LAST_YEAR {
+        return (127 << 9 | 12 << 5 | 31, 23 << 11 | 59 << 5 | 29, 199);
+    }
+    let date = ((year - FIRST_YEAR) as u16) << 9 | (month as u16) << 5 | day as u16;
+    let secs_of_day = secs % SECONDS_PER_DAY;
+    let (hours, minutes, seconds) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+    let time = (hours as u16) << 11 | (minutes as u16) << 5 | (seconds / 2) as u16;
+    let hundredths = (seconds % 2) as u8 * 100 + (since_epoch.subsec_millis() / 10) as u8;
+    (date, time, hundredths)
+}
+
+/// The time since the epoch of a FAT date and time; zero for an unset date.
+pub fn from_fat_time(date: u16, time: u16) -> Duration {
+    let (year, month, day) = (
+        FIRST_YEAR + (date >> 9) as i64,
+        (date >> 5 & 0xf) as u32,
+        (date & 0x1f) as u32,
+    );
+    if !(1..=12).contains(&month) || day == 0 {
+        return Duration::ZERO;
+    }
+    let days = days_from_civil(year, month, day) as u64;
+    let secs_of_day =
+        (time >> 11) as u64 * 3600 + (time >> 5 & 0x3f) as u64 * 60 + (time & 0x1f) as u64 * 2;
+    Duration::from_secs(days * SECONDS_PER_DAY + secs_of_day)
+}
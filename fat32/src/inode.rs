@@ -0,0 +1,448 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::mem;
+
+use spin::Mutex;
+
+use crate::{
+    dir::{
+        self, LongName, ShortEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, ATTR_VOLUME_ID,
+        DIR_ENTRY_SIZE,
+    },
+    DirEntry, Error, FileKind, Metadata, Result, Volume,
+};
+
+/// A directory holds at most this many slots, as a 16-bit index numbers them.
+const MAX_DIR_SLOTS: usize = 65536;
+
+struct State {
+    entry: ShortEntry,
+    clusters: Vec<u32>,
+    /// Removed from its directory while still in use; its clusters go when it does.
+    is_unlinked: bool,
+}
+
+/// A file or directory in a directory, with the slots it takes.
+struct Slot {
+    name: String,
+    entry: ShortEntry,
+    /// Where the short entry lies.
+    position: u64,
+    /// Where the long name slots and the short entry lie, in disk order.
+    positions: Vec<u64>,
+}
+
+impl Slot {
+    /// Names compare as Windows does, ignoring case, and either name of a file finds it.
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.entry.display_name().eq_ignore_ascii_case(name)
+    }
+}
+
+/// A file or directory of a [`crate::Fat32`] volume.
+pub struct FatInode {
+    volume: Arc<Volume>,
+    /// Where the short directory entry lies; `None` for the root directory.
+    position: Option<u64>,
+    state: Mutex<State>,
+}
+
+impl FatInode {
+    pub(crate) fn new(
+        volume: Arc<Volume>,
+        position: Option<u64>,
+        entry: ShortEntry,
+        clusters: Vec<u32>,
+    ) -> FatInode {
+        FatInode {
+            volume,
+            position,
+            state: Mutex::new(State {
+                entry,
+                clusters,
+                is_unlinked: false,
+            }),
+        }
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.volume.layout.cluster_size as u64
+    }
+
+    /// The root is 1; any other inode is numbered by its directory entry.
+    fn ino(&self) -> u64 {
+        self.position
+            .map_or(1, |position| position / DIR_ENTRY_SIZE as u64)
+    }
+
+    /// Write the directory entry back, unless there is none.
+    fn store(&self, state: &State) -> Result<()> {
+        match self.position {
+            Some(position) if !state.is_unlinked => {
+                self.volume.write(position, &state.entry.encode())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Where byte `offset` of the data lies on the volume.
+    fn data_position(&self, state: &State, offset: u64) -> u64 {
+        let cluster = state.clusters[(offset / self.cluster_size()) as usize];
+        self.volume.cluster_offset(cluster) + offset % self.cluster_size()
+    }
+
+    /// Read the data at `offset`, which the clusters must hold.
+    fn read_clusters(&self, state: &State, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let len = ((self.cluster_size() - position % self.cluster_size()) as usize)
+                .min(buf.len() - done);
+            self.volume.read(
+                self.data_position(state, position),
+                &mut buf[done..done + len],
+            )?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write the data at `offset`, which the clusters must hold.
+    fn write_clusters(&self, state: &State, offset: u64, data: &[u8]) -> Result<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let len = ((self.cluster_size() - position % self.cluster_size()) as usize)
+                .min(data.len() - done);
+            self.volume
+                .write(self.data_position(state, position), &data[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Append clusters until there are `count`.
+    fn reserve(&self, state: &mut State, count: usize) -> Result<()> {
+        while state.clusters.len() < count {
+            let cluster = self
+                .volume
+                .allocate_cluster(state.clusters.last().copied())?;
+            if state.clusters.is_empty() {
+                state.entry.first_cluster = cluster;
+            }
+            state.clusters.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Cut or zero-extend the file to `size` bytes.
+    fn resize(&self, state: &mut State, size: u64) -> Result<()> {
+        if size > u32::MAX as u64 {
+            return Err(Error::NoSpace);
+        }
+        let old_size = state.entry.size as u64;
+        let count = size.div_ceil(self.cluster_size()) as usize;
+        if size > old_size {
+            self.reserve(state, count)?;
+            // New clusters come zeroed, but the tail of the old last one may hold stale data.
+            let end = size.min(old_size.next_multiple_of(self.cluster_size()));
+            if end > old_size {
+                self.write_clusters(state, old_size, &vec![0; (end - old_size) as usize])?;
+            }
+        } else {
+            self.volume.truncate_chain(&mut state.clusters, count)?;
+            if count == 0 {
+                state.entry.first_cluster = 0;
+            }
+        }
+        state.entry.size = size as u32;
+        Ok(())
+    }
+
+    fn write_locked(&self, state: &mut State, offset: u64, data: &[u8]) -> Result<()> {
+        let end = offset + data.len() as u64;
+        if end > state.entry.size as u64 {
+            self.resize(state, end)?;
+        }
+        self.write_clusters(state, offset, data)
+    }
+
+    /// Visit each slot of the directory and where it lies, until `visit` returns `false`.
+    fn scan(&self, state: &State, mut visit: impl FnMut(&[u8], u64) -> bool) -> Result<()> {
+        let mut buf = vec![0; self.cluster_size() as usize];
+        for cluster in &state.clusters {
+            let offset = self.volume.cluster_offset(*cluster);
+            self.volume.read(offset, &mut buf)?;
+            for (i, raw) in buf.chunks(DIR_ENTRY_SIZE).enumerate() {
+                if !visit(raw, offset + (i * DIR_ENTRY_SIZE) as u64) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The files and directories in the directory, without `.`, `..` and the volume label.
+    fn slots(&self, state: &State) -> Result<Vec<Slot>> {
+        let mut slots = Vec::new();
+        let mut long_name = LongName::default();
+        self.scan(state, |raw, position| {
+            match raw[0] {
+                dir::END => return false,
+                dir::DELETED => long_name.clear(),
+                _ if dir::is_long_name(raw) => long_name.push(raw, position),
+                _ => {
+                    let entry = ShortEntry::parse(raw);
+                    let name = long_name.take(&entry);
+                    let mut positions = mem::take(&mut long_name.positions);
+                    if entry.attr & ATTR_VOLUME_ID == 0 && !entry.is_dot() {
+                        positions.push(position);
+                        slots.push(Slot {
+                            name: name.unwrap_or_else(|| entry.display_name()),
+                            entry,
+                            position,
+                            positions,
+                        });
+                    }
+                }
+            }
+            true
+        })?;
+        Ok(slots)
+    }
+
+    /// Where `count` consecutive free slots lie, growing the directory if need be.
+    fn free_slots(&self, state: &mut State, count: usize) -> Result<Vec<u64>> {
+        let mut run = Vec::new();
+        self.scan(state, |raw, position| {
+            match raw[0] {
+                dir::END | dir::DELETED => run.push(position),
+                _ => run.clear(),
+            }
+            run.len() < count
+        })?;
+        let slots_per_cluster = self.cluster_size() as usize / DIR_ENTRY_SIZE;
+        while run.len() < count {
+            if (state.clusters.len() + 1) * slots_per_cluster > MAX_DIR_SLOTS {
+                return Err(Error::NoSpace);
+            }
+            self.reserve(state, state.clusters.len() + 1)?;
+            let offset = self.volume.cluster_offset(*state.clusters.last().unwrap());
+            let needed = (count - run.len()).min(slots_per_cluster);
+            run.extend((0..needed).map(|i| offset + (i * DIR_ENTRY_SIZE) as u64));
+        }
+        Ok(run)
+    }
+
+    fn find(&self, state: &State, name: &str) -> Result<Slot> {
+        if !state.entry.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        self.slots(state)?
+            .into_iter()
+            .find(|slot| slot.matches(name))
+            .ok_or(Error::NotFound)
+    }
+
+    /// Remove the file, or with `is_dir` the empty directory, `name`.
+    fn remove(&self, name: &str, is_dir: bool) -> Result<()> {
+        let mut state = self.state.lock();
+        let slot = self.find(&state, name)?;
+        match (is_dir, slot.entry.is_dir()) {
+            (false, true) => return Err(Error::IsADirectory),
+            (true, false) => return Err(Error::NotADirectory),
+            (true, true) => {
+                let child = self.volume.inode(slot.position, slot.entry.clone())?;
+                let child_state = child.state.lock();
+                if !child.slots(&child_state)?.is_empty() {
+                    return Err(Error::DirectoryNotEmpty);
+                }
+            }
+            (false, false) => (),
+        }
+
+        for position in &slot.positions {
+            self.volume.write(*position, &[dir::DELETED])?;
+        }
+        match self.volume.forget_inode(slot.position) {
+            Some(inode) => inode.state.lock().is_unlinked = true,
+            None => {
+                let clusters = self.volume.chain(slot.entry.first_cluster)?;
+                self.volume.free_clusters_of(&clusters)?;
+            }
+        }
+        state.entry.set_modified((self.volume.now)());
+        self.store(&state)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let entry = &state.entry;
+        let allocated = state.clusters.len() as u64 * self.cluster_size();
+        Metadata {
+            ino: self.ino(),
+            kind: match entry.is_dir() {
+                true => FileKind::Directory,
+                false => FileKind::Regular,
+            },
+            is_read_only: entry.attr & ATTR_READ_ONLY != 0,
+            // Directory entries record no size for directories.
+            size: match entry.is_dir() {
+                true => allocated,
+                false => entry.size as u64,
+            },
+            allocated,
+            cluster_size: self.cluster_size() as u32,
+            accessed: entry.accessed(),
+            modified: entry.modified(),
+        }
+    }
+
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let state = self.state.lock();
+        if state.entry.is_dir() {
+            return Err(Error::IsADirectory);
+        }
+        let size = state.entry.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        self.read_clusters(&state, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        if state.entry.is_dir() {
+            return Err(Error::IsADirectory);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let result = self.write_locked(&mut state, offset, data);
+        // Clusters may have been added even if the write failed.
+        state.entry.set_modified((self.volume.now)());
+        self.store(&state)?;
+        result.map(|()| data.len())
+    }
+
+    pub fn truncate(&self, size: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.entry.is_dir() {
+            return Err(Error::IsADirectory);
+        }
+        let result = self.resize(&mut state, size);
+        state.entry.set_modified((self.volume.now)());
+        self.store(&state)?;
+        result
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<Arc<FatInode>> {
+        let state = self.state.lock();
+        let slot = self.find(&state, name)?;
+        self.volume.inode(slot.position, slot.entry)
+    }
+
+    /// Create `name` in the directory, with `ATTR_READ_ONLY` if `is_read_only`.
+    pub fn create(&self, name: &str, kind: FileKind, is_read_only: bool) -> Result<Arc<FatInode>> {
+        dir::validate_name(name)?;
+        let mut state = self.state.lock();
+        if !state.entry.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        if state.is_unlinked {
+            return Err(Error::NotFound);
+        }
+        let slots = self.slots(&state)?;
+        if slots.iter().any(|slot| slot.matches(name)) {
+            return Err(Error::AlreadyExists);
+        }
+
+        // A long name only if the short name cannot hold the name as it is.
+        let is_taken =
+            |short_name: &[u8; 11]| slots.iter().any(|slot| &slot.entry.name == short_name);
+        let (short_name, ntres, mut raw_slots) = match dir::exact_short_name(name) {
+            Some((short_name, ntres)) if !is_taken(&short_name) => (short_name, ntres, Vec::new()),
+            _ => {
+                let short_name = dir::generate_short_name(name, is_taken)?;
+                (short_name, 0, dir::long_name_slots(name, &short_name))
+            }
+        };
+        let now = (self.volume.now)();
+        let mut attr = match kind {
+            FileKind::Directory => ATTR_DIRECTORY,
+            FileKind::Regular => ATTR_ARCHIVE,
+        };
+        if is_read_only {
+            attr |= ATTR_READ_ONLY;
+        }
+        let mut entry = ShortEntry::new(short_name, ntres, attr, now);
+        let positions = self.free_slots(&mut state, raw_slots.len() + 1)?;
+
+        if kind == FileKind::Directory {
+            let cluster = self.volume.allocate_cluster(None)?;
+            entry.first_cluster = cluster;
+            let mut dot = ShortEntry::new(*b".          ", 0, ATTR_DIRECTORY, now);
+            dot.first_cluster = cluster;
+            let mut dot_dot = ShortEntry::new(*b"..         ", 0, ATTR_DIRECTORY, now);
+            // `..` says 0 for the root, whatever its cluster.
+            if self.position.is_some() {
+                dot_dot.first_cluster = state.entry.first_cluster;
+            }
+            let mut raw = [0; 2 * DIR_ENTRY_SIZE];
+            raw[..DIR_ENTRY_SIZE].copy_from_slice(&dot.encode());
+            raw[DIR_ENTRY_SIZE..].copy_from_slice(&dot_dot.encode());
+            self.volume
+                .write(self.volume.cluster_offset(cluster), &raw)?;
+        }
+
+        raw_slots.push(entry.encode());
+        for (raw, position) in raw_slots.iter().zip(&positions) {
+            self.volume.write(*position, raw)?;
+        }
+        state.entry.set_modified(now);
+        self.store(&state)?;
+        self.volume.inode(*positions.last().unwrap(), entry)
+    }
+
+    pub fn unlink(&self, name: &str) -> Result<()> {
+        self.remove(name, false)
+    }
+
+    pub fn rmdir(&self, name: &str) -> Result<()> {
+        self.remove(name, true)
+    }
+
+    pub fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let state = self.state.lock();
+        if !state.entry.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        Ok(self
+            .slots(&state)?
+            .into_iter()
+            .nth(index)
+            .map(|slot| DirEntry {
+                name: slot.name,
+                ino: slot.position / DIR_ENTRY_SIZE as u64,
+                kind: match slot.entry.is_dir() {
+                    true => FileKind::Directory,
+                    false => FileKind::Regular,
+                },
+            }))
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.volume.sync()
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        if state.is_unlinked {
+            // Nothing is left to report a failure to; the clusters stay lost until fsck.
+            let _ = self.volume.free_clusters_of(&state.clusters);
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! FAT32 with long file names, as `mkfs.vfat -F 32` makes it.
+//!
+//! - It knows nothing of the kernel: all I/O goes to a [`BlockDevice`], which the kernel backs
+//!   with its block cache and the tests with an image file on the host.
+//! - `cargo test` from this directory runs it on the host against images `mkfs.vfat` makes.
+//! - FAT has no inodes: a file is its short directory entry, and its inode number is where
+//!   that entry lies on the volume.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::time::Duration;
+
+use spin::Mutex;
+
+mod dir;
+mod inode;
+
+use dir::{ShortEntry, ATTR_DIRECTORY};
+pub use inode::FatInode;
+
+/// Storage addressed by byte, such as a disk or an image file.
+pub trait BlockDevice: Send + Sync {
+    /// Size in bytes.
+    fn size(&self) -> u64;
+
+    /// Fill `buf` from `offset`.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Write `data` at `offset`.
+    fn write(&self, offset: u64, data: &[u8]) -> Result<()>;
+
+    /// Make all writes so far durable.
+    fn flush(&self) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidInput,
+    NameTooLong,
+    Unsupported,
+    NoSpace,
+    /// The device cannot be written.
+    ReadOnly,
+    /// The volume is malformed.
+    Corrupted,
+    Io,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Regular,
+    Directory,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// Unique within the volume.
+    pub ino: u64,
+    pub kind: FileKind,
+    /// `ATTR_READ_ONLY`.
+    pub is_read_only: bool,
+    /// Directories record no size, so theirs is what they take.
+    pub size: u64,
+    /// Bytes taken on the volume.
+    pub allocated: u64,
+    pub cluster_size: u32,
+    /// Times since the Unix epoch.
+    pub accessed: Duration,
+    pub modified: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileKind,
+}
+
+/// The current time since the Unix epoch, for timestamps.
+pub type Clock = fn() -> Duration;
+
+const SECTOR_SIZE: usize = 512;
+const BOOT_SIGNATURE: u16 = 0xaa55;
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+/// FSInfo's way of saying it does not know.
+const UNKNOWN: u32 = 0xffff_ffff;
+
+/// FAT32 entries are 28 bits; the top 4 are reserved and kept as found.
+const ENTRY_MASK: u32 = 0x0fff_ffff;
+const FREE: u32 = 0;
+const BAD_CLUSTER: u32 = 0x0fff_fff7;
+/// Any entry from here on ends a chain.
+const END_OF_CHAIN: u32 = 0x0fff_fff8;
+/// The first data cluster; 0 and 1 are reserved.
+const FIRST_CLUSTER: u32 = 2;
+
+/// `BPB_ExtFlags`: only the active FAT is written.
+const NO_MIRRORING: u16 = 1 << 7;
+
+/// Where things lie on the volume, from the boot sector.
+#[derive(Debug, Clone)]
+struct Layout {
+    cluster_size: usize,
+    /// Byte offsets.
+    fat_offset: u64,
+    fat_size: u64,
+    fat_count: u8,
+    /// The FAT that is read; with mirroring, every FAT is written.
+    active_fat: u8,
+    is_mirrored: bool,
+    data_offset: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    fs_info_offset: Option<u64>,
+}
+
+impl Layout {
+    fn parse(boot: &[u8], device_size: u64) -> Result<Layout> {
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                boot[offset],
+                boot[offset + 1],
+                boot[offset + 2],
+                boot[offset + 3],
+            ])
+        };
+        if u16_at(510) != BOOT_SIGNATURE {
+            return Err(Error::Corrupted);
+        }
+        let bytes_per_sector = u16_at(11) as u64;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = u16_at(14) as u64;
+        let fat_count = boot[16];
+        let root_entries = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32) as u64,
+            sectors => sectors as u64,
+        };
+        let fat_sectors_16 = u16_at(22);
+        let fat_sectors = u32_at(36) as u64;
+        let ext_flags = u16_at(40);
+        let root_cluster = u32_at(44);
+        let fs_info_sector = u16_at(48) as u64;
+
+        if !bytes_per_sector.is_power_of_two()
+            || !(SECTOR_SIZE as u64..=4096).contains(&bytes_per_sector)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+        {
+            return Err(Error::Corrupted);
+        }
+        // FAT12 and FAT16 have a fixed root directory and a 16-bit FAT size; FAT32 neither.
+        if root_entries != 0 || fat_sectors_16 != 0 || fat_sectors == 0 {
+            return Err(Error::Unsupported);
+        }
+
+        let fat_offset = reserved_sectors * bytes_per_sector;
+        let fat_size = fat_sectors * bytes_per_sector;
+        let data_offset = fat_offset + fat_count as u64 * fat_size;
+        let cluster_size = sectors_per_cluster * bytes_per_sector;
+        let total_size = (total_sectors * bytes_per_sector).min(device_size);
+        let cluster_count = (total_size.saturating_sub(data_offset) / cluster_size)
+            // Each cluster needs a FAT entry, and entries past 0x0ffffff6 are reserved.
+            .min(fat_size / 4 - FIRST_CLUSTER as u64)
+            .min((BAD_CLUSTER - FIRST_CLUSTER) as u64) as u32;
+        if cluster_count == 0 {
+            return Err(Error::Corrupted);
+        }
+        let is_mirrored = ext_flags & NO_MIRRORING == 0;
+        let active_fat = match is_mirrored {
+            true => 0,
+            false => (ext_flags & 0xf) as u8,
+        };
+        if active_fat >= fat_count {
+            return Err(Error::Corrupted);
+        }
+        let layout = Layout {
+            cluster_size: cluster_size as usize,
+            fat_offset,
+            fat_size,
+            fat_count,
+            active_fat,
+            is_mirrored,
+            data_offset,
+            cluster_count,
+            root_cluster,
+            fs_info_offset: (1..reserved_sectors)
+                .contains(&fs_info_sector)
+                .then_some(fs_info_sector * bytes_per_sector),
+        };
+        if !layout.is_data_cluster(root_cluster) {
+            return Err(Error::Corrupted);
+        }
+        Ok(layout)
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..FIRST_CLUSTER + self.cluster_count).contains(&cluster)
+    }
+}
+
+/// The free cluster hints FSInfo keeps.
+struct Allocation {
+    /// `None` if FSInfo does not know.
+    free_count: Option<u32>,
+    /// Where the search for a free cluster starts.
+    next_free: u32,
+    is_dirty: bool,
+}
+
+/// The volume the inodes of a [`Fat32`] share.
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    now: Clock,
+    layout: Layout,
+    allocation: Mutex<Allocation>,
+    /// The inodes in use, by where their directory entry lies, so that a file opened twice is
+    /// one inode.
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl Volume {
+    /// Read the boot sector and FSInfo of `device`.
+    fn open(device: Arc<dyn BlockDevice>, now: Clock) -> Result<Volume> {
+        let mut boot = [0; SECTOR_SIZE];
+        device.read(0, &mut boot)?;
+        let layout = Layout::parse(&boot, device.size())?;
+
+        let mut allocation = Allocation {
+            free_count: None,
+            next_free: FIRST_CLUSTER,
+            is_dirty: false,
+        };
+        if let Some(offset) = layout.fs_info_offset {
+            let mut fs_info = [0; SECTOR_SIZE];
+            device.read(offset, &mut fs_info)?;
+            let u32_at = |offset: usize| {
+                u32::from_le_bytes([
+                    fs_info[offset],
+                    fs_info[offset + 1],
+                    fs_info[offset + 2],
+                    fs_info[offset + 3],
+                ])
+            };
+            if u32_at(0) == FS_INFO_LEAD_SIGNATURE
+                && u32_at(484) == FS_INFO_STRUCT_SIGNATURE
+                && u32_at(508) == FS_INFO_TRAIL_SIGNATURE
+            {
+                let (free_count, next_free) = (u32_at(488), u32_at(492));
+                allocation.free_count = (free_count <= layout.cluster_count).then_some(free_count);
+                if next_free != UNKNOWN && layout.is_data_cluster(next_free) {
+                    allocation.next_free = next_free;
+                }
+            }
+        }
+
+        Ok(Volume {
+            device,
+            now,
+            layout,
+            allocation: Mutex::new(allocation),
+            inodes: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.device.read(offset, buf)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<()> {
+        self.device.write(offset, data)
+    }
+
+    /// The byte offset of a data cluster.
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.layout.data_offset + (cluster - FIRST_CLUSTER) as u64 * self.layout.cluster_size as u64
+    }
+
+    fn fat_entry_offset(&self, fat: u8, cluster: u32) -> u64 {
+        self.layout.fat_offset + fat as u64 * self.layout.fat_size + cluster as u64 * 4
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let mut raw = [0; 4];
+        self.read(
+            self.fat_entry_offset(self.layout.active_fat, cluster),
+            &mut raw,
+        )?;
+        Ok(u32::from_le_bytes(raw) & ENTRY_MASK)
+    }
+
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        let mut raw = [0; 4];
+        self.read(
+            self.fat_entry_offset(self.layout.active_fat, cluster),
+            &mut raw,
+        )?;
+        let value = u32::from_le_bytes(raw) & !ENTRY_MASK | value & ENTRY_MASK;
+        let fats = match self.layout.is_mirrored {
+            true => 0..self.layout.fat_count,
+            false => self.layout.active_fat..self.layout.active_fat + 1,
+        };
+        for fat in fats {
+            self.write(self.fat_entry_offset(fat, cluster), &value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The clusters of the chain starting at `first`; empty for 0.
+    fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != FREE && cluster < END_OF_CHAIN {
+            // A chain can neither leave the data area nor be longer than it, or it loops.
+            if !self.layout.is_data_cluster(cluster)
+                || clusters.len() >= self.layout.cluster_count as usize
+            {
+                return Err(Error::Corrupted);
+            }
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Take a free cluster, zero it, and link it after `previous`.
+    fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32> {
+        let mut allocation = self.allocation.lock();
+        let count = self.layout.cluster_count;
+        let start = allocation
+            .next_free
+            .clamp(FIRST_CLUSTER, FIRST_CLUSTER + count - 1);
+        let mut found = None;
+        for i in 0..count {
+            let cluster = FIRST_CLUSTER + (start - FIRST_CLUSTER + i) % count;
+            if self.fat_entry(cluster)? == FREE {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(Error::NoSpace)?;
+
+        self.write(
+            self.cluster_offset(cluster),
+            &vec![0; self.layout.cluster_size],
+        )?;
+        self.set_fat_entry(cluster, END_OF_CHAIN)?;
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, cluster)?;
+        }
+        allocation.next_free = cluster + 1;
+        allocation.free_count = allocation.free_count.map(|free| free.saturating_sub(1));
+        allocation.is_dirty = true;
+        Ok(cluster)
+    }
+
+    /// Return `clusters` to the free pool.
+    fn free_clusters_of(&self, clusters: &[u32]) -> Result<()> {
+        let mut allocation = self.allocation.lock();
+        for cluster in clusters {
+            self.set_fat_entry(*cluster, FREE)?;
+            allocation.free_count = allocation.free_count.map(|free| free + 1);
+            allocation.next_free = allocation.next_free.min(*cluster);
+        }
+        allocation.is_dirty = true;
+        Ok(())
+    }
+
+    /// Cut the chain `clusters` to its first `keep` clusters.
+    fn truncate_chain(&self, clusters: &mut Vec<u32>, keep: usize) -> Result<()> {
+        if keep >= clusters.len() {
+            return Ok(());
+        }
+        if keep > 0 {
+            self.set_fat_entry(clusters[keep - 1], END_OF_CHAIN)?;
+        }
+        self.free_clusters_of(&clusters[keep..])?;
+        clusters.truncate(keep);
+        Ok(())
+    }
+
+    fn write_fs_info(&self) -> Result<()> {
+        let mut allocation = self.allocation.lock();
+        let Some(offset) = self.layout.fs_info_offset else {
+            return Ok(());
+        };
+        if !allocation.is_dirty {
+            return Ok(());
+        }
+        let mut hints = [0; 8];
+        hints[..4].copy_from_slice(&allocation.free_count.unwrap_or(UNKNOWN).to_le_bytes());
+        hints[4..].copy_from_slice(&allocation.next_free.to_le_bytes());
+        self.write(offset + 488, &hints)?;
+        allocation.is_dirty = false;
+        Ok(())
+    }
+
+    /// Write FSInfo and flush the device.
+    fn sync(&self) -> Result<()> {
+        self.write_fs_info()?;
+        self.device.flush()
+    }
+
+    /// The inode of the directory entry at `position`.
+    fn inode(self: &Arc<Self>, position: u64, entry: ShortEntry) -> Result<Arc<FatInode>> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&position).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let clusters = self.chain(entry.first_cluster)?;
+        let inode = Arc::new(FatInode::new(self.clone(), Some(position), entry, clusters));
+        inodes.insert(position, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    /// Forget the inode of the directory entry at `position`, which is being removed.
+    ///
+    /// - Returns it if it is still in use, so that its clusters are freed once it is not.
+    fn forget_inode(&self, position: u64) -> Option<Arc<FatInode>> {
+        self.inodes.lock().remove(&position)?.upgrade()
+    }
+}
+
+/// A mounted FAT32 volume.
+pub struct Fat32 {
+    volume: Arc<Volume>,
+    /// The root directory, which has no directory entry of its own.
+    root: Arc<FatInode>,
+}
+
+impl Fat32 {
+    /// Mount the FAT32 volume on `device`, stamping files with the time `now` gives.
+    ///
+    /// - Fails with [`Error::Unsupported`] for FAT12 and FAT16 volumes.
+    pub fn mount(device: Arc<dyn BlockDevice>, now: Clock) -> Result<Fat32> {
+        let volume = Arc::new(Volume::open(device, now)?);
+        let mut entry = ShortEntry::new([b' '; 11], 0, ATTR_DIRECTORY, Duration::ZERO);
+        entry.first_cluster = volume.layout.root_cluster;
+        let clusters = volume.chain(entry.first_cluster)?;
+        let root = Arc::new(FatInode::new(volume.clone(), None, entry, clusters));
+        Ok(Fat32 { volume, root })
+    }
+
+    pub fn root(&self) -> &Arc<FatInode> {
+        &self.root
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.volume.layout.cluster_size
+    }
+
+    /// Free clusters, if FSInfo kept count.
+    pub fn free_clusters(&self) -> Option<u32> {
+        self.volume.allocation.lock().free_count
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.volume.sync()
+    }
+}
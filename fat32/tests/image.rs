@@ -0,0 +1,236 @@
+//! The crate against image files that `mkfs.vfat` makes, checked with `fsck.fat` when present.
+//!
+//! - Each test is skipped, with a note, where `mkfs.vfat` is not installed.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fat32::{BlockDevice, Error, Fat32, FileKind};
+
+/// Small enough to stay sparse, large enough for FAT32 with 512-byte clusters.
+const IMAGE_KIB: u64 = 66 * 1024;
+
+/// An image file as a block device.
+struct FileDevice(File);
+
+impl BlockDevice for FileDevice {
+    fn size(&self) -> u64 {
+        self.0.metadata().unwrap().len()
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> fat32::Result<()> {
+        self.0.read_exact_at(buf, offset).map_err(|_| Error::Io)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> fat32::Result<()> {
+        self.0.write_all_at(data, offset).map_err(|_| Error::Io)
+    }
+
+    fn flush(&self) -> fat32::Result<()> {
+        self.0.sync_all().map_err(|_| Error::Io)
+    }
+}
+
+/// A fresh image, removed when dropped.
+struct Image(PathBuf);
+
+impl Image {
+    /// Make one with `mkfs.vfat`, or `None` if it is not installed.
+    fn new(name: &str) -> Option<Image> {
+        let path = std::env::temp_dir().join(format!("fat32-{}-{}.img", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let status = Command::new("mkfs.vfat")
+            .args(["-F", "32", "-s", "1", "-C"])
+            .arg(&path)
+            .arg(IMAGE_KIB.to_string())
+            .output();
+        match status {
+            Ok(output) if output.status.success() => Some(Image(path)),
+            Ok(output) => panic!("mkfs.vfat: {}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("mkfs.vfat not found; skipping");
+                None
+            }
+        }
+    }
+
+    fn mount(&self) -> Fat32 {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.0)
+            .unwrap();
+        Fat32::mount(Arc::new(FileDevice(file)), now).unwrap()
+    }
+
+    /// Have `fsck.fat` check the image, if it is installed.
+    fn check(&self) {
+        check(&self.0);
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+}
+
+fn check(path: &Path) {
+    let Ok(output) = Command::new("fsck.fat").arg("-n").arg(path).output() else {
+        return;
+    };
+    assert!(
+        output.status.success(),
+        "fsck.fat: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+fn names(volume: &Fat32) -> Vec<String> {
+    let mut names = Vec::new();
+    while let Some(entry) = volume.root().read_dir(names.len()).unwrap() {
+        names.push(entry.name);
+    }
+    names
+}
+
+#[test]
+fn mounts_an_empty_volume() {
+    let Some(image) = Image::new("empty") else {
+        return;
+    };
+    let volume = image.mount();
+    assert_eq!(volume.cluster_size(), 512);
+    assert!(names(&volume).is_empty());
+    let metadata = volume.root().metadata();
+    assert_eq!(metadata.kind, FileKind::Directory);
+    assert_eq!(volume.root().lookup("missing").err(), Some(Error::NotFound));
+}
+
+#[test]
+fn long_names_and_data_survive_a_remount() {
+    let Some(image) = Image::new("remount") else {
+        return;
+    };
+    let name = "A file with a long name.text";
+    // Spans several clusters and ends inside one.
+    let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    {
+        let volume = image.mount();
+        let file = volume
+            .root()
+            .create(name, FileKind::Regular, false)
+            .unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        assert_eq!(file.write_at(100, b"patched").unwrap(), 7);
+        volume.sync().unwrap();
+    }
+    image.check();
+
+    let volume = image.mount();
+    assert_eq!(names(&volume), [name]);
+    // Case does not matter, and the generated short name finds the file too.
+    let file = volume.root().lookup(&name.to_uppercase()).unwrap();
+    assert!(Arc::ptr_eq(
+        &file,
+        &volume.root().lookup("AFILEW~1.TEX").unwrap()
+    ));
+    let mut expected = data.clone();
+    expected[100..107].copy_from_slice(b"patched");
+    let mut buf = vec![0; data.len() + 10];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert_eq!(&buf[..data.len()], expected);
+    assert_eq!(file.metadata().size, data.len() as u64);
+}
+
+#[test]
+fn short_names_keep_their_case() {
+    let Some(image) = Image::new("case") else {
+        return;
+    };
+    {
+        let volume = image.mount();
+        for name in ["lower.txt", "UPPER.TXT", "Mixed.txt"] {
+            volume
+                .root()
+                .create(name, FileKind::Regular, false)
+                .unwrap();
+        }
+        volume.sync().unwrap();
+    }
+    image.check();
+    let volume = image.mount();
+    assert_eq!(names(&volume), ["lower.txt", "UPPER.TXT", "Mixed.txt"]);
+    assert_eq!(
+        volume
+            .root()
+            .create("LOWER.TXT", FileKind::Regular, false)
+            .err(),
+        Some(Error::AlreadyExists)
+    );
+}
+
+#[test]
+fn removing_files_and_directories_frees_their_clusters() {
+    let Some(image) = Image::new("remove") else {
+        return;
+    };
+    {
+        let volume = image.mount();
+        volume.sync().unwrap();
+        let free = volume.free_clusters().unwrap();
+
+        let dir = volume
+            .root()
+            .create("dir", FileKind::Directory, false)
+            .unwrap();
+        let file = dir.create("file", FileKind::Regular, false).unwrap();
+        file.write_at(0, &[1; 2000]).unwrap();
+        drop(file);
+        assert!(volume.free_clusters().unwrap() < free);
+        assert_eq!(
+            volume.root().rmdir("dir").err(),
+            Some(Error::DirectoryNotEmpty)
+        );
+        assert_eq!(volume.root().unlink("dir").err(), Some(Error::IsADirectory));
+        assert_eq!(dir.rmdir("file").err(), Some(Error::NotADirectory));
+
+        dir.unlink("file").unwrap();
+        drop(dir);
+        volume.root().rmdir("dir").unwrap();
+        assert_eq!(volume.free_clusters(), Some(free));
+        assert!(names(&volume).is_empty());
+        volume.sync().unwrap();
+    }
+    image.check();
+}
+
+#[test]
+fn truncation_zeroes_what_it_extends() {
+    let Some(image) = Image::new("truncate") else {
+        return;
+    };
+    let volume = image.mount();
+    let file = volume
+        .root()
+        .create("file", FileKind::Regular, false)
+        .unwrap();
+    file.write_at(0, &[7; 700]).unwrap();
+    file.truncate(10).unwrap();
+    file.truncate(1500).unwrap();
+    let mut buf = vec![0xff; 1500];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 1500);
+    assert_eq!(&buf[..10], [7; 10]);
+    assert!(buf[10..].iter().all(|byte| *byte == 0));
+    assert_eq!(file.metadata().allocated, 3 * 512);
+}
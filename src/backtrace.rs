@@ -0,0 +1,100 @@
+use core::{arch::asm, str};
+
+use crate::{mm::MEMORY_END, supervisor_print, supervisor_println};
+
+const MAX_DEPTH: usize = 32;
+
+/// The embedded symbol table.
+///
+/// - One `<address in hex> <demangled name>` line per function, sorted by address.
+/// - Empty until `symbols` in `scripts.nu` patches it into the kernel ELF.
+fn symbol_table() -> &'static str {
+    extern "C" {
+        fn sksymtab();
+        fn eksymtab();
+    }
+    let start = sksymtab as *const () as usize;
+    let end = eksymtab as *const () as usize;
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    let len = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+/// The function containing `addr` and the offset of `addr` into it.
+pub fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    let mut found = None;
+    for line in symbol_table().lines() {
+        let Some((address, name)) = line.split_once(' ') else {
+            continue;
+        };
+        let Ok(address) = usize::from_str_radix(address, 16) else {
+            continue;
+        };
+        if address > addr {
+            break;
+        }
+        found = Some((name, addr - address));
+    }
+    found
+}
+
+fn print_frame(depth: usize, pc: usize, is_return_address: bool) {
+    // Return addresses point after the call, which may be past the end of the caller.
+    let lookup = pc - is_return_address as usize;
+    match symbolize(lookup) {
+        Some((name, offset)) => {
+            let offset = offset + is_return_address as usize;
+            supervisor_println!("  #{:<2} {:#x} {}+{:#x}", depth, pc, name, offset)
+        }
+        None => supervisor_println!("  #{:<2} {:#x} ?", depth, pc),
+    }
+}
+
+fn is_frame_pointer(fp: usize) -> bool {
+    extern "C" {
+        fn start();
+    }
+    fp.is_multiple_of(8) && start as *const () as usize <= fp && fp <= MEMORY_END
+}
+
+/// Walk the frame-pointer chain from `fp`.
+///
+/// - With `-Cforce-frame-pointers=yes`, `fp` points just above the frame record: the return
+///   address is at `fp - 8` and the caller's `fp` at `fp - 16`.
+/// - Stacks grow down, so every caller's frame must sit above its callee's.
+fn walk(mut fp: usize, mut depth: usize) {
+    while depth < MAX_DEPTH && is_frame_pointer(fp) {
+        let ra = unsafe { *((fp - 8) as *const usize) };
+        let caller_fp = unsafe { *((fp - 16) as *const usize) };
+        if ra == 0 {
+            break;
+        }
+        print_frame(depth, ra, true);
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+        depth += 1;
+    }
+}
+
+/// Print the return addresses of the calling kernel stack.
+#[inline(never)]
+pub fn print_backtrace() {
+    let fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    supervisor_println!("Backtrace:");
+    walk(fp, 0);
+}
+
+/// Print the stack of interrupted kernel code from its `pc` and `s0`.
+pub fn print_backtrace_from(pc: usize, fp: usize) {
+    supervisor_println!("Backtrace of the interrupted code:");
+    print_frame(0, pc, false);
+    walk(fp, 1);
+}
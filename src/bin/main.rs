@@ -1,31 +1,106 @@
 #![no_std] // don't link the Rust standard library
 #![no_main] // disable all Rust-level entry points
 
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
 use core::arch::asm;
 use core::arch::global_asm;
+use core::time::Duration;
 
+use os::boot_options;
+use os::clock;
+use os::console;
+use os::device_tree;
+use os::drivers;
+use os::drivers::block;
+use os::drivers::block::cache;
+use os::drivers::virtio;
 use os::exception::enable_supervisor_interrupt;
 use os::exception::setup_supervisor_exception_handler;
-use os::sbi_call;
+use os::fs;
+use os::fs::initramfs;
+use os::fs::{Ext2, Fat32, FileSystem, FileType, FsError};
+use os::gdb;
+use os::mm;
+use os::mm::address_space::{AddressSpace, USER_END};
+use os::mm::page_table::PteFlags;
+use os::monitor;
+use os::panicking::{self, PanicPolicy};
 use os::supervisor_print;
 use os::supervisor_println;
+use os::task;
+use os::task::elf::{self, ElfError};
+use os::task::Task;
+use os::timer;
 use os::user_print;
 use os::user_println;
 use os::Sstatus;
 
 static HELLO: &str = "Hello World!";
 
+/// The first user program, which adopts orphaned processes.
+const INIT_PATH: &str = "/init";
+
+const DEMO_TEXT: usize = 0x4000_0000;
+const DEMO_HEAP: usize = 0x4010_0000;
+
+const WRITE_BACK_INTERVAL: Duration = Duration::from_secs(5);
+
 // Entry point of the kernel.
 global_asm!(include_str!("_start.asm"));
+global_asm!(include_str!("page_fault_demo.asm"));
 
 /// - `no_mangle` ensures the Rust compiler really outputs a function with the name `_start`.
 /// - `extern "C"` ensures the Rust compiler uses the C calling convention for this function.
+/// - The firmware passes the hart ID in `a0` and the device tree in `a1`, which `_start` keeps.
 #[no_mangle]
-pub extern "C" fn main() {
+pub extern "C" fn main(hart_id: usize, dtb: usize) {
     setup_supervisor_exception_handler();
+    panicking::boot_hart_started(hart_id);
+    mm::init();
+    // Parse the device tree before the frame allocator hands out the frames it lives in.
+    let device_tree = device_tree::init(dtb);
+    apply_boot_options();
+    initramfs::reserve_initrd();
+    clock::init();
+    timer::init();
+    drivers::init(hart_id);
+    task::init();
+    attach_gdb();
 
     supervisor_println!();
     supervisor_println!("{}", HELLO);
+    if let Err(error) = device_tree {
+        supervisor_println!("No device tree: {:?}", error);
+    }
+    supervisor_println!("Console: {:?}", console::backend());
+    supervisor_println!("Timebase: {} Hz", clock::timebase_frequency());
+    supervisor_println!("Wall clock: {:?} since the epoch", clock::realtime());
+    for device in block::devices() {
+        supervisor_println!(
+            "Block device {}: {} sectors{}",
+            device.name(),
+            device.capacity(),
+            if device.is_read_only() {
+                ", read-only"
+            } else {
+                ""
+            }
+        );
+    }
+    match initramfs::init() {
+        Ok(stats) => supervisor_println!("Initramfs at /: {:?}", stats),
+        Err(error) => supervisor_println!("No root file system: {:?}", error),
+    }
+    mount_disks();
+    supervisor_println!(
+        "Timer comparator: {:?}, {:?} per tick",
+        timer::comparator(),
+        timer::measure_overhead()
+    );
 
     // We are at supervisor mode now.
     let sstatus: usize;
@@ -51,25 +126,153 @@ pub extern "C" fn main() {
         asm!("csrr {}, sie", out(reg) sie_after);
     }
     supervisor_println!("sie: {:#x} -> {:#x}", sie_before, sie_after);
+    enable_supervisor_interrupt(os::exception::Interrupt::SupervisorExternal);
+    // Kernel threads yield through software interrupts, which no tick papers over once idle.
+    enable_supervisor_interrupt(os::exception::Interrupt::SupervisorSoftware);
+    console::init();
 
-    // Trigger timer interrupt.
-    sbi_call::set_timer(0).expect("Failed to set timer");
+    spawn_init();
+    task::spawn(Task::new_flat(user_pit as *const () as usize));
+    spawn_page_fault_demo();
+    task::spawn(Task::new_kernel(echo_console));
+    cache::start_write_back(WRITE_BACK_INTERVAL);
 
-    // Set sepc to the pit.
+    // Become the idle task.
+    // The pending yield switches to the other tasks right away.
+    task::yield_now();
     unsafe {
-        asm!(
-            "csrw sepc, {}",
-            in (reg) user_pit,
-        );
+        asm!("csrsi sstatus, 1 << 1");
     }
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
 
-    // Go to user mode.
-    // Send the PC to the pit.
-    unsafe {
-        asm!("sret");
+/// Mount the first ext2 or FAT32 volume found at `/mnt`.
+fn mount_disks() {
+    let Ok(root) = fs::root() else {
+        return;
+    };
+    for device in block::devices() {
+        let volume: Arc<dyn FileSystem> = match Ext2::mount(device.clone()) {
+            Ok(volume) => volume,
+            Err(_) => match Fat32::mount(device.clone()) {
+                Ok(volume) => volume,
+                Err(_) => continue,
+            },
+        };
+        let res = match root.inode().create("mnt", FileType::Directory, 0o755) {
+            Ok(_) | Err(FsError::AlreadyExists) => fs::mount("/mnt", volume.clone()),
+            Err(error) => Err(error),
+        };
+        match res {
+            Ok(()) => supervisor_println!("Mounted {} at /mnt: {}", device.name(), volume.name()),
+            Err(error) => supervisor_println!("Failed to mount {}: {:?}", device.name(), error),
+        }
+        return;
+    }
+}
+
+/// Run [`INIT_PATH`] from the root file system as the init process, if it is there.
+fn spawn_init() {
+    let argv = [INIT_PATH.as_bytes().to_vec()];
+    let image = match elf::load(None, INIT_PATH, &argv, &[]) {
+        Ok(image) => image,
+        Err(ElfError::Fs(FsError::NotFound)) => return,
+        Err(error) => {
+            supervisor_println!("Failed to run {}: {:?}", INIT_PATH, error);
+            return;
+        }
+    };
+    let id = task::spawn(Task::new_user(image.entry, image.sp, image.address_space));
+    task::set_init(id);
+    supervisor_println!("Init: {:?}", id);
+}
+
+/// Act on the kernel command line in `/chosen/bootargs`.
+///
+/// - `panic=shutdown|reboot|spin|monitor` picks what a panic ends in.
+/// - `monitor` enters the debug monitor on `ebreak`, starting with the one below in `main`.
+fn apply_boot_options() {
+    if let Some(name) = boot_options::get("panic") {
+        match PanicPolicy::from_name(name) {
+            Some(policy) => panicking::set_policy(policy),
+            None => supervisor_println!("Unknown panic policy: {}", name),
+        }
+    }
+    if boot_options::get("monitor").is_some() {
+        monitor::set_break_on_ebreak(true);
+    }
+}
+
+/// Hand `ebreak` to a GDB stub if the `gdb=uart|virtio|sbi` boot option asks for one.
+///
+/// - `uart` is an NS16550A besides the console, `virtio` a virtio console, e.g. QEMU's
+///   `-device virtio-serial-device -device virtconsole,chardev=...`, and `sbi` the console
+///   shared with the kernel log.
+/// - GDB finds the kernel stopped at the `ebreak` in `main`.
+fn attach_gdb() {
+    let Some(name) = boot_options::get("gdb") else {
+        return;
+    };
+    let transport: Box<dyn gdb::Transport> = match name {
+        "uart" => match drivers::uart::find_spare() {
+            Some(uart) => Box::new(uart),
+            None => {
+                supervisor_println!("GDB: no spare UART");
+                return;
+            }
+        },
+        "virtio" => match virtio::console::take() {
+            Some(console) => Box::new(console),
+            None => {
+                supervisor_println!("GDB: no virtio console");
+                return;
+            }
+        },
+        "sbi" => Box::new(gdb::SbiConsole),
+        name => {
+            supervisor_println!("GDB: unknown transport {}", name);
+            return;
+        }
+    };
+    gdb::attach(transport);
+    supervisor_println!("GDB: waiting on {}", name);
+}
+
+/// Run `page_fault_demo.asm` in an address space with a lazily populated heap and stack.
+fn spawn_page_fault_demo() {
+    extern "C" {
+        fn page_fault_demo_start();
+        fn page_fault_demo_end();
     }
+    let start = page_fault_demo_start as *const () as usize;
+    let end = page_fault_demo_end as *const () as usize;
+    let text = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
 
-    panic!("Should not reach here");
+    let mut space = AddressSpace::new().expect("Out of memory");
+    space
+        .map_data(DEMO_TEXT, text, PteFlags::R | PteFlags::X)
+        .expect("Failed to map text");
+    space
+        .map_anonymous(DEMO_HEAP, 4096 * 4, PteFlags::R | PteFlags::W)
+        .expect("Failed to map heap");
+    space
+        .map_stack(USER_END, 4096, 4096 * 16)
+        .expect("Failed to map stack");
+    task::spawn(Task::new_user(DEMO_TEXT, USER_END, space));
+}
+
+/// A kernel thread that sleeps until a line is typed and echoes it back.
+fn echo_console() -> ! {
+    let mut line = String::new();
+    loop {
+        if console::read_line(&mut line).is_ok() {
+            supervisor_println!("echo: {}", line);
+        }
+    }
 }
 
 #[no_mangle]
@@ -81,5 +284,8 @@ pub extern "C" fn user_pit() -> ! {
         asm!("ebreak");
     }
 
-    loop {}
+    // exit(0)
+    unsafe {
+        asm!("ecall", in("a0") 0, in("a7") 93, options(noreturn));
+    }
 }
@@ -0,0 +1,22 @@
+# A position-independent user program that `main` copies into its own address space.
+    .section .rodata
+    .globl page_fault_demo_start
+    .globl page_fault_demo_end
+    .p2align 2
+page_fault_demo_start:
+    # Demand paging: the first touch allocates a zeroed page of the anonymous VMA at `DEMO_HEAP`.
+    li      t0, 0x40100000
+    ld      t1, 0(t0)
+    sd      t1, 8(t0)
+
+    # Stack growth: touch two pages below the initial stack page.
+    li      t0, 8192
+    sub     t0, sp, t0
+    sd      zero, 0(t0)
+
+    # Segmentation fault: no VMA covers address zero, so only this task is terminated.
+    sd      zero, 0(zero)
+
+1:
+    j       1b
+page_fault_demo_end:
@@ -0,0 +1,14 @@
+use crate::device_tree;
+
+/// The value of `key=value` on the kernel command line, or `""` for a bare `key`.
+///
+/// - Words are separated by whitespace; the first match wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    let bootargs = device_tree::get()?.bootargs()?;
+    bootargs
+        .split_whitespace()
+        .find_map(|word| match word.split_once('=') {
+            Some((name, value)) => (name == key).then_some(value),
+            None => (word == key).then_some(""),
+        })
+}
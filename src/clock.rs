@@ -0,0 +1,137 @@
+use core::{
+    arch::asm,
+    ops::{Add, AddAssign, Sub},
+    time::Duration,
+};
+
+use spin::Once;
+
+use crate::{device_tree, drivers::rtc, task};
+
+/// QEMU `virt` counts `time` at 10 MHz when the device tree does not say.
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+static TIMEBASE_FREQUENCY: Once<u64> = Once::new();
+
+/// A point on the monotonic clock: the `time` CSR, which counts from boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        let time: u64;
+        unsafe {
+            asm!("csrr {}, time", out(reg) time);
+        }
+        Instant(time)
+    }
+
+    pub const fn from_ticks(ticks: u64) -> Instant {
+        Instant(ticks)
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// The time since boot.
+    pub fn since_boot(&self) -> Duration {
+        duration(self.0)
+    }
+
+    /// Zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(ticks(duration)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    /// Saturates, so an overlong timeout means "never".
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(ticks(duration)))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Read the timebase frequency from the device tree.
+pub fn init() {
+    let frequency = device_tree::get()
+        .and_then(|tree| {
+            let cpus = tree.find_path("/cpus")?;
+            // Usually on `/cpus`, but a device tree may put it on each `cpu` node instead.
+            cpus.property_usize("timebase-frequency").or_else(|| {
+                cpus.children()
+                    .find_map(|cpu| cpu.property_usize("timebase-frequency"))
+            })
+        })
+        .map_or(DEFAULT_TIMEBASE_FREQUENCY, |frequency| frequency as u64);
+    TIMEBASE_FREQUENCY.call_once(|| frequency);
+}
+
+/// Ticks of the `time` CSR per second.
+pub fn timebase_frequency() -> u64 {
+    *TIMEBASE_FREQUENCY
+        .get()
+        .unwrap_or(&DEFAULT_TIMEBASE_FREQUENCY)
+}
+
+/// `duration` in ticks, rounded up so a deadline is never early.
+pub fn ticks(duration: Duration) -> u64 {
+    let ticks = (duration.as_nanos() * timebase_frequency() as u128).div_ceil(NANOS_PER_SEC);
+    ticks.min(u64::MAX as u128) as u64
+}
+
+/// `ticks` as a duration, rounded down.
+pub fn duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SEC / timebase_frequency() as u128;
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+/// The wall-clock time since the Unix epoch, from the RTC.
+///
+/// - Without an RTC, the clock starts at the epoch on boot.
+pub fn realtime() -> Duration {
+    match rtc::get() {
+        Some(rtc) => rtc.now(),
+        None => Instant::now().since_boot(),
+    }
+}
+
+/// Put the current kernel thread to sleep for at least `duration`.
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration);
+}
+
+/// Put the current kernel thread to sleep until `deadline`.
+///
+/// - The hart is free for other tasks in the meantime, or idles in `wfi`.
+pub fn sleep_until(deadline: Instant) {
+    while Instant::now() < deadline {
+        task::block_until(deadline);
+        task::yield_now();
+    }
+}
@@ -3,7 +3,14 @@ use core::fmt::{self, Write};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
-use crate::sbi_call;
+use crate::{drivers::uart, exception::without_interrupts, sbi_call};
+
+mod input;
+
+pub use input::{
+    block_for_input, init, poll, poll_byte, read_byte, read_line, receive, try_read_byte,
+    ReadLineError,
+};
 
 pub fn sbi_print(s: &str) -> Result<(), isize> {
     for ch in s.bytes() {
@@ -33,18 +40,88 @@ impl Write for Writer {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The firmware's console calls.
+    Sbi,
+    /// The NS16550A named by the device tree, driven directly.
+    Uart,
+}
+
+/// Where the kernel console goes, decided at boot by `drivers::uart::init`.
+pub fn backend() -> Backend {
+    match uart::console() {
+        Some(_) => Backend::Uart,
+        None => Backend::Sbi,
+    }
+}
+
+/// The kernel console.
+///
+/// - User tasks print through [`Writer`] instead, as they cannot touch the UART; the kernel
+///   forwards their firmware calls here.
+pub struct ConsoleWriter;
+
+impl ConsoleWriter {
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), isize> {
+        let Some(uart) = uart::console() else {
+            return bytes.iter().try_for_each(|ch| {
+                sbi_call::legacy_sbi_call(&sbi_call::LegacyExtension::ConsolePutChar { ch: *ch })
+                    .map(|_| ())
+            });
+        };
+        // Like the firmware, start a new line at the left margin.
+        for (i, line) in bytes.split(|byte| *byte == b'\n').enumerate() {
+            if i > 0 {
+                uart.write_bytes(b"\r\n");
+            }
+            uart.write_bytes(line);
+        }
+        Ok(())
+    }
+}
+
+impl Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
 // Why two writers: to avoid deadlock
 lazy_static! {
     pub static ref USER_WRITER: Mutex<Writer> = Mutex::new(Writer::new());
-    pub static ref SUPERVISOR_WRITER: Mutex<Writer> = Mutex::new(Writer::new());
+    pub static ref SUPERVISOR_WRITER: Mutex<ConsoleWriter> = Mutex::new(ConsoleWriter);
 }
 
 pub fn user_fmt_print(args: fmt::Arguments) {
     USER_WRITER.lock().write_fmt(args).unwrap();
 }
 
+/// - Interrupt handlers print too, so kernel threads hold the writer with interrupts off.
 pub fn supervisor_fmt_print(args: fmt::Arguments) {
-    SUPERVISOR_WRITER.lock().write_fmt(args).unwrap();
+    without_interrupts(|| SUPERVISOR_WRITER.lock().write_fmt(args).unwrap());
+}
+
+/// Print raw bytes on behalf of a user task.
+pub fn write_bytes(bytes: &[u8]) {
+    without_interrupts(|| {
+        let _ = SUPERVISOR_WRITER.lock().write_bytes(bytes);
+    });
+}
+
+/// Print without taking any lock, for when the lock holder may never release it.
+pub fn emergency_fmt_print(args: fmt::Arguments) {
+    let _ = ConsoleWriter.write_fmt(args);
+}
+
+/// Release the writers held by code that will never run again.
+///
+/// # Safety
+///
+/// Every other hart must be stopped, and the interrupted code on this hart must never resume.
+pub unsafe fn force_unlock() {
+    USER_WRITER.force_unlock();
+    SUPERVISOR_WRITER.force_unlock();
 }
 
 #[macro_export]
@@ -68,3 +145,8 @@ macro_rules! supervisor_println {
     () => (supervisor_print!("\n"));
     ($($arg:tt)*) => (supervisor_print!("{}\n", format_args!($($arg)*)));
 }
+
+#[macro_export]
+macro_rules! emergency_println {
+    ($($arg:tt)*) => ($crate::console::emergency_fmt_print(format_args!("{}\n", format_args!($($arg)*))));
+}
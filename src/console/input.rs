@@ -0,0 +1,167 @@
+use alloc::{collections::VecDeque, string::String};
+use core::{str, time::Duration};
+
+use spin::Mutex;
+
+use crate::{
+    drivers::uart, exception::without_interrupts, sbi_call, supervisor_print, supervisor_println,
+    task::WaitQueue, timer::Timer,
+};
+
+/// Bytes received but not read yet; more are dropped.
+const INPUT_CAPACITY: usize = 256;
+/// Bytes `read_line` accepts; more are ignored until the line ends.
+const LINE_CAPACITY: usize = 1024;
+
+/// How often a console without an input interrupt is polled.
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const DEL: u8 = 0x7f;
+
+static INPUT: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+static READERS: WaitQueue = WaitQueue::new();
+
+/// Queue a byte that arrived at the console and wake the readers.
+///
+/// - Called from interrupt handlers.
+pub fn receive(byte: u8) {
+    let is_queued = without_interrupts(|| {
+        let mut input = INPUT.lock();
+        if input.len() == INPUT_CAPACITY {
+            return false;
+        }
+        input.push_back(byte);
+        true
+    });
+    if is_queued {
+        READERS.wake_all();
+    }
+}
+
+/// Poll the console on a timer unless it interrupts on input.
+///
+/// - The firmware console raises no interrupt, nor does a UART without a PLIC.
+pub fn init() {
+    if !uart::console().is_some_and(|uart| uart.is_rx_interrupt_enabled()) {
+        Timer::every(POLL_PERIOD, poll);
+    }
+}
+
+/// Move the bytes waiting in the console into the input buffer.
+pub fn poll() {
+    while let Some(byte) = poll_byte() {
+        receive(byte);
+    }
+}
+
+/// Read a byte straight from the console device, bypassing the input buffer.
+///
+/// - For code that runs with interrupts off, like the monitor.
+pub fn poll_byte() -> Option<u8> {
+    match uart::console() {
+        Some(uart) => uart.read_byte(),
+        None => sbi_call::console_getchar(),
+    }
+}
+
+pub fn try_read_byte() -> Option<u8> {
+    without_interrupts(|| INPUT.lock().pop_front())
+}
+
+/// Wait for the next byte of input.
+///
+/// - Only kernel threads may call this, as the caller sleeps until a byte arrives.
+pub fn read_byte() -> u8 {
+    loop {
+        if let Some(byte) = try_read_byte() {
+            return byte;
+        }
+        READERS.sleep_if(|| INPUT.lock().is_empty());
+    }
+}
+
+/// Block the current task until input arrives, from a system call that restarts once woken.
+///
+/// - Returns `false` if input arrived already.
+pub fn block_for_input() -> bool {
+    READERS.block_if(|| INPUT.lock().is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadLineError {
+    /// Ctrl-C was pressed.
+    Interrupted,
+}
+
+/// Erase the last character on the terminal, assuming it takes one column.
+fn erase() {
+    supervisor_print!("\x08 \x08");
+}
+
+/// Read a line into `line` without its terminator, echoing it and handling line editing.
+///
+/// - Backspace and DEL erase a character, Ctrl-U erases the line and Ctrl-C abandons it.
+/// - Input is decoded as UTF-8; invalid sequences are dropped, but not the byte that ends one
+///   early.
+/// - Only kernel threads may call this, like [`read_byte`].
+pub fn read_line(line: &mut String) -> Result<(), ReadLineError> {
+    line.clear();
+    // A UTF-8 sequence that is not complete yet.
+    let mut pending = [0; 4];
+    let mut pending_len = 0;
+    loop {
+        let byte = read_byte();
+        match byte {
+            b'\r' | b'\n' => {
+                supervisor_println!();
+                return Ok(());
+            }
+            CTRL_C => {
+                supervisor_println!("^C");
+                line.clear();
+                return Err(ReadLineError::Interrupted);
+            }
+            CTRL_U => {
+                line.chars().for_each(|_| erase());
+                line.clear();
+                pending_len = 0;
+            }
+            BACKSPACE | DEL => {
+                if line.pop().is_some() {
+                    erase();
+                }
+                pending_len = 0;
+            }
+            // Other control characters
+            0x00..=0x1f => {}
+            _ if line.len() + pending_len >= LINE_CAPACITY => {}
+            _ => {
+                pending[pending_len] = byte;
+                pending_len += 1;
+                loop {
+                    match str::from_utf8(&pending[..pending_len]) {
+                        Ok(ch) => {
+                            line.push_str(ch);
+                            supervisor_print!("{}", ch);
+                            pending_len = 0;
+                        }
+                        // Incomplete, wait for the rest.
+                        Err(error) if error.error_len().is_none() => {}
+                        // This byte cut the sequence before it short: drop that, and try the
+                        // byte as the start of a new one.
+                        Err(_) if pending_len > 1 => {
+                            pending[0] = byte;
+                            pending_len = 1;
+                            continue;
+                        }
+                        Err(_) => pending_len = 0,
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use alloc::{vec, vec::Vec};
+use core::{
+    arch::asm,
+    ptr::{read_volatile, write_volatile},
+};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{
+    exception::{
+        disassemble::{sign_extend, Disassembly},
+        ExceptionMutContext,
+    },
+    mm::{page_table, MEMORY_END, MEMORY_START},
+};
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u32 = 0x9002;
+
+/// An `ebreak` patched into memory, with the bytes it replaced.
+struct Breakpoint {
+    addr: usize,
+    physical: usize,
+    original: [u8; 4],
+    len: usize,
+}
+
+impl Breakpoint {
+    /// Patch `addr` with an `ebreak` of `len` bytes, 2 being `c.ebreak`.
+    fn insert(addr: usize, len: usize) -> Option<Breakpoint> {
+        if !addr.is_multiple_of(2) || (addr..addr + len).any(|addr| translate(addr).is_none()) {
+            return None;
+        }
+        let mut original = [0; 4];
+        for (i, byte) in original.iter_mut().take(len).enumerate() {
+            *byte = read_byte(addr + i)?;
+        }
+        let ebreak = if len == 2 { C_EBREAK } else { EBREAK };
+        for (i, byte) in ebreak.to_le_bytes().iter().take(len).enumerate() {
+            write_byte(addr + i, *byte)?;
+        }
+        fence_i();
+        Some(Breakpoint {
+            addr,
+            physical: translate(addr)?,
+            original,
+            len,
+        })
+    }
+
+    /// Put the original bytes back through the physical address, whichever table is active.
+    fn remove(&self) {
+        for (i, byte) in self.original.iter().take(self.len).enumerate() {
+            unsafe { write_volatile((self.physical + i) as *mut u8, *byte) };
+        }
+        fence_i();
+    }
+}
+
+lazy_static! {
+    /// Breakpoints planted on the instructions that may follow a single-stepped one.
+    static ref STEP_BREAKPOINTS: Mutex<Vec<Breakpoint>> = Mutex::new(Vec::new());
+    /// Breakpoints set by the debugger, which stay until it removes them.
+    static ref BREAKPOINTS: Mutex<Vec<Breakpoint>> = Mutex::new(Vec::new());
+}
+
+/// Translate `addr` through the live `satp` to a physical address the debugger may touch.
+///
+/// - Only RAM is accessible, so reads never have MMIO side effects and never fault.
+pub fn translate(addr: usize) -> Option<usize> {
+    let satp = page_table::current_satp();
+    let physical = match satp {
+        0 => addr,
+        satp => page_table::translate(satp, addr)?,
+    };
+    (MEMORY_START..MEMORY_END)
+        .contains(&physical)
+        .then_some(physical)
+}
+
+pub fn read_byte(addr: usize) -> Option<u8> {
+    let physical = translate(addr)?;
+    Some(unsafe { read_volatile(physical as *const u8) })
+}
+
+/// Write through the identity mapping of RAM, so read-only and executable pages can be patched.
+pub fn write_byte(addr: usize, value: u8) -> Option<()> {
+    let physical = translate(addr)?;
+    unsafe { write_volatile(physical as *mut u8, value) };
+    Some(())
+}
+
+/// Make patched code visible to instruction fetch.
+pub fn fence_i() {
+    unsafe {
+        asm!("fence.i");
+    }
+}
+
+fn read_half(addr: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([read_byte(addr)?, read_byte(addr + 1)?]))
+}
+
+/// The instruction at `pc`.
+pub fn read_instruction(pc: usize) -> Option<Disassembly> {
+    let low = read_half(pc)? as u32;
+    if low & 0b11 != 0b11 {
+        return Some(Disassembly::new(low));
+    }
+    let high = read_half(pc + 2)? as u32;
+    Some(Disassembly::new(low | high << 16))
+}
+
+/// The length of the `ebreak` or `c.ebreak` at `pc` that is part of the code, as opposed to a
+/// breakpoint set by the debugger.
+///
+/// - Resuming past such an instruction must skip it, or it traps again.
+pub fn compiled_ebreak_len(pc: usize) -> Option<usize> {
+    if BREAKPOINTS
+        .lock()
+        .iter()
+        .any(|breakpoint| breakpoint.addr == pc)
+    {
+        return None;
+    }
+    let instruction = read_instruction(pc)?;
+    matches!(instruction.instruction, EBREAK | C_EBREAK).then_some(instruction.len)
+}
+
+/// Set a breakpoint of `len` bytes at `addr`; setting one twice is fine.
+pub fn insert_breakpoint(addr: usize, len: usize) -> Option<()> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().any(|breakpoint| breakpoint.addr == addr) {
+        return Some(());
+    }
+    breakpoints.push(Breakpoint::insert(addr, len)?);
+    Some(())
+}
+
+pub fn remove_breakpoint(addr: usize) -> Option<()> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    let index = breakpoints
+        .iter()
+        .position(|breakpoint| breakpoint.addr == addr)?;
+    breakpoints.remove(index).remove();
+    Some(())
+}
+
+pub fn remove_all_breakpoints() {
+    for breakpoint in BREAKPOINTS.lock().drain(..) {
+        breakpoint.remove();
+    }
+}
+
+/// Put back every step breakpoint, returning whether one of them was at `pc`.
+pub fn remove_step_breakpoints(pc: usize) -> bool {
+    let physical_pc = translate(pc);
+    let mut is_hit = false;
+    for breakpoint in STEP_BREAKPOINTS.lock().drain(..) {
+        breakpoint.remove();
+        is_hit |= Some(breakpoint.physical) == physical_pc;
+    }
+    is_hit
+}
+
+/// Where execution may continue after `instruction` at `pc`.
+fn next_pcs(mut_context: &ExceptionMutContext, pc: usize, instruction: Disassembly) -> Vec<usize> {
+    let ins = instruction.instruction;
+    let x = |index: u32| mut_context.register_context.get(index as usize & 0x1f);
+    let fall_through = pc.wrapping_add(instruction.len);
+    let offset = |imm: i32| pc.wrapping_add_signed(imm as isize);
+
+    if instruction.len == 4 {
+        let rs1 = ins >> 15;
+        return match ins & 0x7f {
+            // jal
+            0x6f => vec![offset(sign_extend(
+                (ins >> 31) << 20
+                    | ((ins >> 12) & 0xff) << 12
+                    | ((ins >> 20) & 1) << 11
+                    | ((ins >> 21) & 0x3ff) << 1,
+                21,
+            ))],
+            // jalr
+            0x67 => vec![x(rs1).wrapping_add_signed(sign_extend(ins >> 20, 12) as isize) & !1],
+            // Branches
+            0x63 => vec![
+                fall_through,
+                offset(sign_extend(
+                    (ins >> 31) << 12
+                        | ((ins >> 7) & 1) << 11
+                        | ((ins >> 25) & 0x3f) << 5
+                        | ((ins >> 8) & 0xf) << 1,
+                    13,
+                )),
+            ],
+            _ => vec![fall_through],
+        };
+    }
+
+    let quadrant = ins & 0b11;
+    let funct3 = (ins >> 13) & 0b111;
+    match (quadrant, funct3) {
+        // c.j
+        (1, 0b101) => vec![offset(sign_extend(
+            ((ins >> 12) & 1) << 11
+                | ((ins >> 11) & 1) << 4
+                | ((ins >> 9) & 0b11) << 8
+                | ((ins >> 8) & 1) << 10
+                | ((ins >> 7) & 1) << 6
+                | ((ins >> 6) & 1) << 7
+                | ((ins >> 3) & 0b111) << 1
+                | ((ins >> 2) & 1) << 5,
+            12,
+        ))],
+        // c.beqz and c.bnez
+        (1, 0b110 | 0b111) => vec![
+            fall_through,
+            offset(sign_extend(
+                ((ins >> 12) & 1) << 8
+                    | ((ins >> 10) & 0b11) << 3
+                    | ((ins >> 5) & 0b11) << 6
+                    | ((ins >> 3) & 0b11) << 1
+                    | ((ins >> 2) & 1) << 5,
+                9,
+            )),
+        ],
+        // c.jr and c.jalr
+        (2, 0b100) if (ins >> 2) & 0x1f == 0 && (ins >> 7) & 0x1f != 0 => {
+            vec![x(ins >> 7) & !1]
+        }
+        _ => vec![fall_through],
+    }
+}
+
+/// Plant a `c.ebreak` on every instruction that may follow the one at `sepc`, so the next
+/// breakpoint trap comes after executing exactly one instruction.
+///
+/// - There is no hardware single-step in S-mode.
+/// - A step breakpoint in code shared by several tasks stops whichever task reaches it first.
+/// - Fails with the address that cannot be patched.
+pub fn plant_step_breakpoints(mut_context: &ExceptionMutContext) -> Result<(), usize> {
+    let pc = mut_context.sepc;
+    let instruction = read_instruction(pc).ok_or(pc)?;
+    let mut breakpoints = STEP_BREAKPOINTS.lock();
+    for target in next_pcs(mut_context, pc, instruction) {
+        if breakpoints.iter().any(|planted| planted.addr == target) {
+            continue;
+        }
+        match Breakpoint::insert(target, 2) {
+            Some(breakpoint) => breakpoints.push(breakpoint),
+            None => {
+                for breakpoint in breakpoints.drain(..) {
+                    breakpoint.remove();
+                }
+                return Err(target);
+            }
+        }
+    }
+    Ok(())
+}
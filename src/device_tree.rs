@@ -0,0 +1,343 @@
+use alloc::{string::String, vec::Vec};
+use core::str;
+
+use spin::Once;
+
+/// Big-endian `0xd00dfeed` at the start of every flattened device tree.
+const MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Cell counts assumed when a parent does not specify them.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+static DEVICE_TREE: Once<DeviceTree> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceTreeError {
+    BadMagic,
+    Truncated,
+    BadToken(u32),
+}
+
+struct Property {
+    name: String,
+    value: Vec<u8>,
+}
+
+struct NodeData {
+    name: String,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    properties: Vec<Property>,
+}
+
+/// A device tree parsed out of the blob the firmware passes in `a1`.
+///
+/// - The tree is copied onto the heap, as the blob sits in RAM the frame allocator hands out.
+pub struct DeviceTree {
+    /// Root first.
+    nodes: Vec<NodeData>,
+}
+
+fn read_u32(blob: &[u8], offset: usize) -> Result<u32, DeviceTreeError> {
+    let bytes = blob
+        .get(offset..offset + 4)
+        .ok_or(DeviceTreeError::Truncated)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_c_str(blob: &[u8], offset: usize) -> Result<&str, DeviceTreeError> {
+    let bytes = blob.get(offset..).ok_or(DeviceTreeError::Truncated)?;
+    let len = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or(DeviceTreeError::Truncated)?;
+    Ok(str::from_utf8(&bytes[..len]).unwrap_or(""))
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+impl DeviceTree {
+    /// Parse the flattened device tree in `blob`.
+    pub fn parse(blob: &[u8]) -> Result<DeviceTree, DeviceTreeError> {
+        if read_u32(blob, 0)? != MAGIC {
+            return Err(DeviceTreeError::BadMagic);
+        }
+        let struct_offset = read_u32(blob, 8)? as usize;
+        let strings_offset = read_u32(blob, 12)? as usize;
+
+        let mut nodes: Vec<NodeData> = Vec::new();
+        let mut current: Option<usize> = None;
+        let mut offset = struct_offset;
+        loop {
+            let token = read_u32(blob, offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_c_str(blob, offset)?;
+                    offset = align4(offset + name.len() + 1);
+                    let index = nodes.len();
+                    nodes.push(NodeData {
+                        name: String::from(name),
+                        parent: current,
+                        children: Vec::new(),
+                        properties: Vec::new(),
+                    });
+                    if let Some(parent) = current {
+                        nodes[parent].children.push(index);
+                    }
+                    current = Some(index);
+                }
+                FDT_END_NODE => {
+                    let node = current.ok_or(DeviceTreeError::BadToken(token))?;
+                    current = nodes[node].parent;
+                }
+                FDT_PROP => {
+                    let len = read_u32(blob, offset)? as usize;
+                    let name_offset = read_u32(blob, offset + 4)? as usize;
+                    offset += 8;
+                    let value = blob
+                        .get(offset..offset + len)
+                        .ok_or(DeviceTreeError::Truncated)?;
+                    offset = align4(offset + len);
+                    let node = current.ok_or(DeviceTreeError::BadToken(token))?;
+                    nodes[node].properties.push(Property {
+                        name: String::from(read_c_str(blob, strings_offset + name_offset)?),
+                        value: Vec::from(value),
+                    });
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                token => return Err(DeviceTreeError::BadToken(token)),
+            }
+        }
+        if nodes.is_empty() {
+            return Err(DeviceTreeError::Truncated);
+        }
+        Ok(DeviceTree { nodes })
+    }
+
+    pub fn root(&self) -> Node<'_> {
+        Node {
+            tree: self,
+            index: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = Node<'_>> {
+        (0..self.nodes.len()).map(|index| Node { tree: self, index })
+    }
+
+    /// Nodes whose `compatible` list contains `compatible`.
+    pub fn find_compatible<'tree>(
+        &'tree self,
+        compatible: &'tree str,
+    ) -> impl Iterator<Item = Node<'tree>> {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+
+    /// Look up an absolute path such as `/soc/serial@10000000`.
+    ///
+    /// - A component without a unit address matches any unit address, like `/cpus/cpu`.
+    pub fn find_path(&self, path: &str) -> Option<Node<'_>> {
+        let mut node = self.root();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            node = node.children().find(|child| {
+                let name = child.name();
+                name == component
+                    || (!component.contains('@') && name.split('@').next() == Some(component))
+            })?;
+        }
+        Some(node)
+    }
+
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'_>> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// `/chosen/bootargs`: the kernel command line.
+    pub fn bootargs(&self) -> Option<&str> {
+        self.find_path("/chosen")?.property_str("bootargs")
+    }
+
+    /// The node `/chosen/stdout-path` points to, through `/aliases` if needed.
+    pub fn stdout(&self) -> Option<Node<'_>> {
+        let path = self.find_path("/chosen")?.property_str("stdout-path")?;
+        // Options such as the baud rate follow a `:`.
+        let path = path.split(':').next()?;
+        if path.starts_with('/') {
+            return self.find_path(path);
+        }
+        let path = self.find_path("/aliases")?.property_str(path)?;
+        self.find_path(path)
+    }
+}
+
+/// A node of a [`DeviceTree`].
+#[derive(Clone, Copy)]
+pub struct Node<'tree> {
+    tree: &'tree DeviceTree,
+    index: usize,
+}
+
+impl<'tree> Node<'tree> {
+    fn data(&self) -> &'tree NodeData {
+        &self.tree.nodes[self.index]
+    }
+
+    /// The name with its unit address, like `serial@10000000`; the root's is empty.
+    pub fn name(&self) -> &'tree str {
+        &self.data().name
+    }
+
+    pub fn parent(&self) -> Option<Node<'tree>> {
+        self.data().parent.map(|index| Node {
+            tree: self.tree,
+            index,
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = Node<'tree>> {
+        let tree = self.tree;
+        self.data().children.iter().map(move |index| Node {
+            tree,
+            index: *index,
+        })
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'tree [u8]> {
+        self.data()
+            .properties
+            .iter()
+            .find(|property| property.name == name)
+            .map(|property| property.value.as_slice())
+    }
+
+    /// A property as a list of big-endian 32-bit cells.
+    pub fn cells(&self, name: &str) -> Option<impl Iterator<Item = u32> + 'tree> {
+        let value = self.property(name)?;
+        Some(
+            value
+                .chunks_exact(4)
+                .map(|cell| u32::from_be_bytes(cell.try_into().unwrap())),
+        )
+    }
+
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        self.cells(name)?.next()
+    }
+
+    /// A 32- or 64-bit integer property, as `timebase-frequency` may be either.
+    pub fn property_usize(&self, name: &str) -> Option<usize> {
+        let value = self.property(name)?;
+        match value.len() {
+            4 => Some(u32::from_be_bytes(value.try_into().unwrap()) as usize),
+            8 => Some(u64::from_be_bytes(value.try_into().unwrap()) as usize),
+            _ => None,
+        }
+    }
+
+    pub fn property_str(&self, name: &str) -> Option<&'tree str> {
+        self.property_strings(name)?.next()
+    }
+
+    /// A property holding NUL-separated strings, like `compatible`.
+    pub fn property_strings(&self, name: &str) -> Option<impl Iterator<Item = &'tree str>> {
+        let value = self.property(name)?;
+        let value = value.strip_suffix(&[0]).unwrap_or(value);
+        Some(
+            value
+                .split(|byte| *byte == 0)
+                .filter_map(|string| str::from_utf8(string).ok()),
+        )
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property_strings("compatible")
+            .is_some_and(|mut strings| strings.any(|string| string == compatible))
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property_u32("phandle")
+            .or_else(|| self.property_u32("linux,phandle"))
+    }
+
+    fn cell_counts(&self) -> (u32, u32) {
+        (
+            self.property_u32("#address-cells")
+                .unwrap_or(DEFAULT_ADDRESS_CELLS),
+            self.property_u32("#size-cells")
+                .unwrap_or(DEFAULT_SIZE_CELLS),
+        )
+    }
+
+    /// `(address, size)` pairs of `reg`, sized by the parent's `#address-cells` and
+    /// `#size-cells`.
+    ///
+    /// - Addresses are not translated through `ranges`; QEMU `virt` maps buses one-to-one.
+    /// - Empty if both counts are zero, which leaves an entry no cells.
+    pub fn reg(&self) -> Vec<(usize, usize)> {
+        let (address_cells, size_cells) = self
+            .parent()
+            .map_or((DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS), |parent| {
+                parent.cell_counts()
+            });
+        let entry_cells = (address_cells + size_cells) as usize;
+        let Some(cells) = self.cells("reg").filter(|_| entry_cells != 0) else {
+            return Vec::new();
+        };
+        let cells: Vec<u32> = cells.collect();
+        let combine = |cells: &[u32]| {
+            cells
+                .iter()
+                .fold(0usize, |value, cell| value << 32 | *cell as usize)
+        };
+        cells
+            .chunks_exact(entry_cells)
+            .map(|entry| {
+                let (address, size) = entry.split_at(address_cells as usize);
+                (combine(address), combine(size))
+            })
+            .collect()
+    }
+
+    /// The `interrupts` cells, one per source for single-cell controllers like the PLIC.
+    pub fn interrupts(&self) -> Vec<u32> {
+        self.cells("interrupts")
+            .map(|cells| cells.collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Parse the device tree the firmware left at `dtb` and keep it for [`get`].
+///
+/// - Must run before the frame allocator hands out the frames the blob lives in.
+pub fn init(dtb: usize) -> Result<(), DeviceTreeError> {
+    if dtb == 0 {
+        return Err(DeviceTreeError::BadMagic);
+    }
+    let header = unsafe { core::slice::from_raw_parts(dtb as *const u8, HEADER_SIZE) };
+    if read_u32(header, 0)? != MAGIC {
+        return Err(DeviceTreeError::BadMagic);
+    }
+    let total_size = read_u32(header, 4)? as usize;
+    let blob = unsafe { core::slice::from_raw_parts(dtb as *const u8, total_size) };
+    let tree = DeviceTree::parse(blob)?;
+    DEVICE_TREE.call_once(|| tree);
+    Ok(())
+}
+
+/// The device tree, if the firmware passed a valid one.
+pub fn get() -> Option<&'static DeviceTree> {
+    DEVICE_TREE.get()
+}
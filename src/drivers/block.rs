@@ -0,0 +1,78 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use crate::exception::without_interrupts;
+
+pub mod cache;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The sectors lie past the end of the device.
+    OutOfRange,
+    /// The buffer is not a whole number of sectors.
+    Misaligned,
+    ReadOnly,
+    /// The device does not implement the operation, like discard.
+    Unsupported,
+    /// The device reported a failure.
+    Io,
+    OutOfMemory,
+}
+
+/// A disk addressed in sectors of [`SECTOR_SIZE`] bytes.
+///
+/// - Calls return once the device is done. A kernel thread sleeps meanwhile; the idle task and
+///   trap handlers poll, as they cannot sleep.
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The size in sectors.
+    fn capacity(&self) -> u64;
+
+    fn is_read_only(&self) -> bool;
+
+    /// Read `buf.len() / SECTOR_SIZE` sectors starting at `sector`.
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write `buf.len() / SECTOR_SIZE` sectors starting at `sector`.
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Make the writes done so far durable.
+    fn flush(&self) -> Result<(), BlockError>;
+
+    /// Tell the device `count` sectors starting at `sector` no longer hold data.
+    fn discard(&self, sector: u64, count: u64) -> Result<(), BlockError>;
+}
+
+/// Check that `len` bytes at `sector` are whole sectors inside a device of `capacity` sectors.
+pub fn check_range(capacity: u64, sector: u64, len: usize) -> Result<(), BlockError> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::Misaligned);
+    }
+    let end = sector
+        .checked_add((len / SECTOR_SIZE) as u64)
+        .ok_or(BlockError::OutOfRange)?;
+    if end > capacity {
+        return Err(BlockError::OutOfRange);
+    }
+    Ok(())
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+/// Make `device` available to file systems.
+pub fn register(device: Arc<dyn BlockDevice>) {
+    without_interrupts(|| DEVICES.lock().push(device));
+}
+
+/// Every block device, in the order the drivers found them.
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    without_interrupts(|| DEVICES.lock().clone())
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    devices().into_iter().find(|device| device.name() == name)
+}
@@ -0,0 +1,402 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+
+use spin::{Mutex, Once};
+
+use crate::{
+    clock,
+    exception::without_interrupts,
+    task::{self, Task},
+};
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// The unit of caching, a multiple of the sector size.
+pub const BLOCK_SIZE: usize = 4096;
+const SECTORS_PER_BLOCK: u64 = (BLOCK_SIZE / SECTOR_SIZE) as u64;
+
+pub const DEFAULT_CACHE_SIZE: usize = 1024 * 1024;
+
+/// Identifies a device by the address of its `Arc`.
+type DeviceKey = usize;
+
+fn device_key(device: &Arc<dyn BlockDevice>) -> DeviceKey {
+    Arc::as_ptr(device) as *const () as usize
+}
+
+struct Entry {
+    device: Arc<dyn BlockDevice>,
+    data: Vec<u8>,
+    is_dirty: bool,
+    /// Live [`PinnedBlock`]s; a pinned block is never evicted.
+    pins: usize,
+    /// When the block was last touched, for LRU eviction.
+    last_used: u64,
+}
+
+impl Entry {
+    /// The sectors of `block` that lie on the device; the last block may be short.
+    fn sectors(&self, block: u64) -> Result<(u64, usize), BlockError> {
+        let sector = block * SECTORS_PER_BLOCK;
+        let capacity = self.device.capacity();
+        if sector >= capacity {
+            return Err(BlockError::OutOfRange);
+        }
+        Ok((sector, (capacity - sector).min(SECTORS_PER_BLOCK) as usize))
+    }
+
+    fn load(&mut self, block: u64) -> Result<(), BlockError> {
+        let (sector, count) = self.sectors(block)?;
+        self.device
+            .read(sector, &mut self.data[..count * SECTOR_SIZE])
+    }
+
+    fn write_back(&mut self, block: u64) -> Result<(), BlockError> {
+        if !self.is_dirty {
+            return Ok(());
+        }
+        let (sector, count) = self.sectors(block)?;
+        self.device
+            .write(sector, &self.data[..count * SECTOR_SIZE])?;
+        self.is_dirty = false;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Dirty blocks written to their device.
+    pub write_backs: u64,
+    /// Blocks the cache may hold.
+    pub capacity: usize,
+    pub resident: usize,
+    pub dirty: usize,
+    pub pinned: usize,
+}
+
+struct CacheInner {
+    /// In blocks.
+    capacity: usize,
+    entries: BTreeMap<(DeviceKey, u64), Entry>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl CacheInner {
+    /// Evict least recently used blocks until at most `len` remain.
+    fn evict_to(&mut self, len: usize) -> Result<(), BlockError> {
+        while self.entries.len() > len {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.pins == 0)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+                .ok_or(BlockError::OutOfMemory)?;
+            let entry = self.entries.get_mut(&victim).unwrap();
+            if entry.is_dirty {
+                entry.write_back(victim.1)?;
+                self.stats.write_backs += 1;
+            }
+            self.entries.remove(&victim);
+            self.stats.evictions += 1;
+        }
+        Ok(())
+    }
+
+    /// The entry of `block`, read from the device on a miss unless it is about to be
+    /// overwritten whole.
+    fn entry(
+        &mut self,
+        device: &Arc<dyn BlockDevice>,
+        block: u64,
+        will_overwrite: bool,
+    ) -> Result<&mut Entry, BlockError> {
+        let key = (device_key(device), block);
+        self.clock += 1;
+        if self.entries.contains_key(&key) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            self.evict_to(self.capacity - 1)?;
+            let mut entry = Entry {
+                device: device.clone(),
+                data: vec![0; BLOCK_SIZE],
+                is_dirty: false,
+                pins: 0,
+                last_used: 0,
+            };
+            if !will_overwrite {
+                entry.load(block)?;
+            }
+            self.entries.insert(key, entry);
+        }
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.last_used = self.clock;
+        Ok(entry)
+    }
+}
+
+/// A write-back cache of [`BLOCK_SIZE`] blocks, shared by every file system.
+///
+/// - The cache runs with interrupts off throughout, device I/O included, so that a trap
+///   handler never finds its lock held by a preempted kernel thread. Device requests then
+///   poll for completion.
+pub struct BlockCache {
+    inner: Mutex<CacheInner>,
+}
+
+impl BlockCache {
+    /// A cache of `size` bytes, rounded down to whole blocks, at least one.
+    pub fn new(size: usize) -> BlockCache {
+        let capacity = (size / BLOCK_SIZE).max(1);
+        BlockCache {
+            inner: Mutex::new(CacheInner {
+                capacity,
+                entries: BTreeMap::new(),
+                clock: 0,
+                stats: CacheStats {
+                    capacity,
+                    ..CacheStats::default()
+                },
+            }),
+        }
+    }
+
+    /// Check that `len` bytes at byte `offset` lie on `device`.
+    fn check_range(
+        device: &Arc<dyn BlockDevice>,
+        offset: u64,
+        len: usize,
+    ) -> Result<(), BlockError> {
+        let end = offset
+            .checked_add(len as u64)
+            .ok_or(BlockError::OutOfRange)?;
+        if end > device.capacity() * SECTOR_SIZE as u64 {
+            return Err(BlockError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    fn with_inner<R>(&self, f: impl FnOnce(&mut CacheInner) -> R) -> R {
+        without_interrupts(|| f(&mut self.inner.lock()))
+    }
+
+    /// Resize the cache to `size` bytes, evicting blocks if it shrinks.
+    pub fn set_size(&self, size: usize) -> Result<(), BlockError> {
+        self.with_inner(|inner| {
+            inner.capacity = (size / BLOCK_SIZE).max(1);
+            inner.stats.capacity = inner.capacity;
+            let capacity = inner.capacity;
+            inner.evict_to(capacity)
+        })
+    }
+
+    /// Read `buf.len()` bytes from `device` at byte `offset`.
+    pub fn read(
+        &self,
+        device: &Arc<dyn BlockDevice>,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), BlockError> {
+        BlockCache::check_range(device, offset, buf.len())?;
+        self.with_inner(|inner| {
+            let mut done = 0;
+            while done < buf.len() {
+                let position = offset + done as u64;
+                let block = position / BLOCK_SIZE as u64;
+                let start = (position % BLOCK_SIZE as u64) as usize;
+                let len = (BLOCK_SIZE - start).min(buf.len() - done);
+                let entry = inner.entry(device, block, false)?;
+                buf[done..done + len].copy_from_slice(&entry.data[start..start + len]);
+                done += len;
+            }
+            Ok(())
+        })
+    }
+
+    /// Write `data` to `device` at byte `offset`; it reaches the device on write-back.
+    pub fn write(
+        &self,
+        device: &Arc<dyn BlockDevice>,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), BlockError> {
+        if device.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        BlockCache::check_range(device, offset, data.len())?;
+        self.with_inner(|inner| {
+            let mut done = 0;
+            while done < data.len() {
+                let position = offset + done as u64;
+                let block = position / BLOCK_SIZE as u64;
+                let start = (position % BLOCK_SIZE as u64) as usize;
+                let len = (BLOCK_SIZE - start).min(data.len() - done);
+                let entry = inner.entry(device, block, len == BLOCK_SIZE)?;
+                entry.data[start..start + len].copy_from_slice(&data[done..done + len]);
+                entry.is_dirty = true;
+                done += len;
+            }
+            Ok(())
+        })
+    }
+
+    /// Keep `block` of `device` resident until the returned handle is dropped.
+    pub fn pin(
+        &self,
+        device: &Arc<dyn BlockDevice>,
+        block: u64,
+    ) -> Result<PinnedBlock<'_>, BlockError> {
+        self.with_inner(|inner| {
+            inner.entry(device, block, false)?.pins += 1;
+            Ok(PinnedBlock {
+                cache: self,
+                key: (device_key(device), block),
+            })
+        })
+    }
+
+    /// Write the dirty blocks of every device, or only of `device`, back.
+    fn write_back_matching(&self, device: Option<DeviceKey>) -> Result<(), BlockError> {
+        self.with_inner(|inner| {
+            let mut res = Ok(());
+            for ((key, block), entry) in inner.entries.iter_mut() {
+                if entry.is_dirty && device.is_none_or(|device| device == *key) {
+                    match entry.write_back(*block) {
+                        Ok(()) => inner.stats.write_backs += 1,
+                        Err(error) => res = Err(error),
+                    }
+                }
+            }
+            res
+        })
+    }
+
+    /// Write every dirty block back, without flushing the devices' own caches.
+    pub fn write_back(&self) -> Result<(), BlockError> {
+        self.write_back_matching(None)
+    }
+
+    /// Write every dirty block back and flush every device that had cached blocks.
+    pub fn sync(&self) -> Result<(), BlockError> {
+        self.write_back()?;
+        let devices = self.with_inner(|inner| {
+            let mut devices: BTreeMap<DeviceKey, Arc<dyn BlockDevice>> = BTreeMap::new();
+            for ((key, _), entry) in &inner.entries {
+                devices.entry(*key).or_insert_with(|| entry.device.clone());
+            }
+            devices
+        });
+        devices.values().try_for_each(|device| device.flush())
+    }
+
+    /// Write back and flush the blocks of `device` only.
+    pub fn sync_device(&self, device: &Arc<dyn BlockDevice>) -> Result<(), BlockError> {
+        self.write_back_matching(Some(device_key(device)))?;
+        device.flush()
+    }
+
+    /// Drop the clean, unpinned blocks of `device`, e.g. before it goes away.
+    pub fn invalidate(&self, device: &Arc<dyn BlockDevice>) {
+        let device = device_key(device);
+        self.with_inner(|inner| {
+            inner
+                .entries
+                .retain(|(key, _), entry| *key != device || entry.is_dirty || entry.pins != 0)
+        });
+    }
+
+    /// - `None` while the cache is locked, e.g. by the code a panic or a breakpoint interrupted.
+    pub fn stats(&self) -> Option<CacheStats> {
+        without_interrupts(|| {
+            let inner = self.inner.try_lock()?;
+            Some(CacheStats {
+                resident: inner.entries.len(),
+                dirty: inner
+                    .entries
+                    .values()
+                    .filter(|entry| entry.is_dirty)
+                    .count(),
+                pinned: inner
+                    .entries
+                    .values()
+                    .filter(|entry| entry.pins != 0)
+                    .count(),
+                ..inner.stats
+            })
+        })
+    }
+}
+
+/// A block held resident in the cache, for metadata a file system keeps going back to.
+pub struct PinnedBlock<'cache> {
+    cache: &'cache BlockCache,
+    key: (DeviceKey, u64),
+}
+
+impl PinnedBlock<'_> {
+    pub fn block(&self) -> u64 {
+        self.key.1
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        self.cache
+            .with_inner(|inner| f(&inner.entries[&self.key].data))
+    }
+
+    /// Modify the block, marking it dirty.
+    pub fn write<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        self.cache.with_inner(|inner| {
+            let entry = inner.entries.get_mut(&self.key).unwrap();
+            entry.is_dirty = true;
+            f(&mut entry.data)
+        })
+    }
+}
+
+impl Drop for PinnedBlock<'_> {
+    fn drop(&mut self) {
+        self.cache
+            .with_inner(|inner| inner.entries.get_mut(&self.key).unwrap().pins -= 1);
+    }
+}
+
+static CACHE: Once<BlockCache> = Once::new();
+static WRITE_BACK_INTERVAL: Once<Duration> = Once::new();
+
+/// Create the shared cache with `size` bytes; later calls keep the first size.
+pub fn init(size: usize) -> &'static BlockCache {
+    CACHE.call_once(|| BlockCache::new(size))
+}
+
+/// The shared cache, [`DEFAULT_CACHE_SIZE`] unless [`init`] said otherwise.
+pub fn get() -> &'static BlockCache {
+    init(DEFAULT_CACHE_SIZE)
+}
+
+/// The statistics of the shared cache, if it exists and is not locked.
+pub fn stats() -> Option<CacheStats> {
+    CACHE.get()?.stats()
+}
+
+/// Write dirty blocks back every `interval` from a kernel thread.
+pub fn start_write_back(interval: Duration) {
+    if WRITE_BACK_INTERVAL.is_completed() {
+        return;
+    }
+    WRITE_BACK_INTERVAL.call_once(|| interval);
+    task::spawn(Task::new_kernel(write_back_thread));
+}
+
+fn write_back_thread() -> ! {
+    let interval = *WRITE_BACK_INTERVAL.get().unwrap();
+    loop {
+        clock::sleep(interval);
+        // A failing device keeps its blocks dirty, to be retried next time.
+        let _ = get().write_back();
+    }
+}
@@ -0,0 +1,18 @@
+pub mod block;
+pub mod plic;
+pub mod rtc;
+pub mod uart;
+pub mod virtio;
+
+/// Bring up the interrupt controller and the devices found in the device tree.
+pub fn init(hart_id: usize) {
+    plic::init(hart_id);
+    uart::init();
+    rtc::init();
+    virtio::init();
+}
+
+/// Service a supervisor external interrupt.
+pub fn handle_external_interrupt() {
+    plic::handle_interrupt();
+}
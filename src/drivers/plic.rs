@@ -0,0 +1,194 @@
+use alloc::collections::BTreeMap;
+use core::ptr::{read_volatile, write_volatile};
+
+use spin::{Mutex, Once};
+
+use crate::{device_tree, exception::without_interrupts, supervisor_print, supervisor_println};
+
+const PRIORITY: usize = 0x0;
+const PENDING: usize = 0x1000;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM: usize = 0x4;
+
+/// Cause of the supervisor external interrupt in a hart's `interrupts-extended` entries.
+const SUPERVISOR_EXTERNAL: u32 = 9;
+/// The most sources a PLIC can have; source 0 does not exist.
+const MAX_SOURCES: u32 = 1024;
+pub const DEFAULT_PRIORITY: u32 = 1;
+
+/// Service the device behind a claimed interrupt source.
+pub type InterruptHandler = fn(source: u32);
+
+/// The platform-level interrupt controller, as seen from this hart's S-mode context.
+pub struct Plic {
+    base: usize,
+    context: usize,
+    /// `riscv,ndev`: sources are numbered `1..=sources`.
+    sources: u32,
+}
+
+static PLIC: Once<Plic> = Once::new();
+static HANDLERS: Mutex<BTreeMap<u32, InterruptHandler>> = Mutex::new(BTreeMap::new());
+
+impl Plic {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    fn context_offset(&self) -> usize {
+        CONTEXT + CONTEXT_STRIDE * self.context
+    }
+
+    fn enable_offset(&self, source: u32) -> usize {
+        ENABLE + ENABLE_STRIDE * self.context + 4 * (source as usize / 32)
+    }
+
+    pub fn is_valid(&self, source: u32) -> bool {
+        (1..=self.sources).contains(&source)
+    }
+
+    /// Priority 0 never interrupts; higher ones are claimed first.
+    pub fn set_priority(&self, source: u32, priority: u32) {
+        self.write(PRIORITY + 4 * source as usize, priority);
+    }
+
+    pub fn priority(&self, source: u32) -> u32 {
+        self.read(PRIORITY + 4 * source as usize)
+    }
+
+    pub fn is_pending(&self, source: u32) -> bool {
+        self.read(PENDING + 4 * (source as usize / 32)) & 1 << (source % 32) != 0
+    }
+
+    /// Let `source` interrupt this hart's S-mode context.
+    pub fn enable(&self, source: u32) {
+        let offset = self.enable_offset(source);
+        self.write(offset, self.read(offset) | 1 << (source % 32));
+    }
+
+    pub fn disable(&self, source: u32) {
+        let offset = self.enable_offset(source);
+        self.write(offset, self.read(offset) & !(1 << (source % 32)));
+    }
+
+    /// Mask sources whose priority is not above `threshold`.
+    pub fn set_threshold(&self, threshold: u32) {
+        self.write(self.context_offset() + THRESHOLD, threshold);
+    }
+
+    /// Take the highest-priority pending source, which stays masked until [`Plic::complete`].
+    pub fn claim(&self) -> Option<u32> {
+        match self.read(self.context_offset() + CLAIM) {
+            0 => None,
+            source => Some(source),
+        }
+    }
+
+    pub fn complete(&self, source: u32) {
+        self.write(self.context_offset() + CLAIM, source);
+    }
+}
+
+/// The index of the context wired to `hart_id`'s supervisor external interrupt.
+///
+/// - `interrupts-extended` lists one `<interrupt controller, cause>` pair per context; the
+///   controller is the one inside the hart's `cpu` node.
+fn find_context(
+    tree: &device_tree::DeviceTree,
+    plic: device_tree::Node,
+    hart_id: usize,
+) -> Option<usize> {
+    let cpu = tree
+        .find_path("/cpus")?
+        .children()
+        .find(|cpu| cpu.reg().first().map(|(id, _)| *id) == Some(hart_id))?;
+    let controller = cpu
+        .children()
+        .find(|child| child.property("interrupt-controller").is_some())?
+        .phandle()?;
+    let cells: alloc::vec::Vec<u32> = plic.cells("interrupts-extended")?.collect();
+    cells
+        .chunks_exact(2)
+        .position(|pair| pair == [controller, SUPERVISOR_EXTERNAL])
+}
+
+/// Find the PLIC in the device tree and accept every priority on this hart's S-mode context.
+pub fn init(hart_id: usize) {
+    let Some(tree) = device_tree::get() else {
+        return;
+    };
+    let Some(node) = tree
+        .find_compatible("riscv,plic0")
+        .chain(tree.find_compatible("sifive,plic-1.0.0"))
+        .next()
+    else {
+        return;
+    };
+    let Some((base, _)) = node.reg().first().copied() else {
+        return;
+    };
+    // QEMU `virt` gives each hart an M-mode and an S-mode context, in that order.
+    let context = find_context(tree, node, hart_id).unwrap_or(2 * hart_id + 1);
+    let sources = node.property_u32("riscv,ndev").unwrap_or(MAX_SOURCES - 1);
+    let plic = PLIC.call_once(|| Plic {
+        base,
+        context,
+        sources,
+    });
+    for source in 1..=plic.sources {
+        plic.disable(source);
+    }
+    plic.set_threshold(0);
+}
+
+pub fn get() -> Option<&'static Plic> {
+    PLIC.get()
+}
+
+/// Route `source` to `handler` at `priority`, and enable it.
+///
+/// - Returns `false` if there is no PLIC or no such source.
+pub fn register_handler(source: u32, priority: u32, handler: InterruptHandler) -> bool {
+    let Some(plic) = get().filter(|plic| plic.is_valid(source)) else {
+        return false;
+    };
+    without_interrupts(|| HANDLERS.lock().insert(source, handler));
+    plic.set_priority(source, priority);
+    plic.enable(source);
+    true
+}
+
+pub fn unregister_handler(source: u32) {
+    if let Some(plic) = get() {
+        plic.disable(source);
+    }
+    without_interrupts(|| HANDLERS.lock().remove(&source));
+}
+
+/// Claim every pending source and run its handler.
+///
+/// - Sources without a handler are disabled, so they do not interrupt again.
+pub fn handle_interrupt() {
+    let Some(plic) = get() else {
+        return;
+    };
+    while let Some(source) = plic.claim() {
+        let handler = HANDLERS.lock().get(&source).copied();
+        match handler {
+            Some(handler) => handler(source),
+            None => {
+                supervisor_println!("Unhandled external interrupt {}", source);
+                plic.disable(source);
+            }
+        }
+        plic.complete(source);
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::boxed::Box;
+use core::{
+    ptr::{read_volatile, write_volatile},
+    time::Duration,
+};
+
+use spin::{Mutex, Once};
+
+use crate::{device_tree, exception::without_interrupts};
+
+use super::plic;
+
+/// Nanoseconds since the epoch; reading the low half latches the high half.
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+/// Writing the low half arms the alarm, so the high half goes first.
+const ALARM_LOW: usize = 0x08;
+const ALARM_HIGH: usize = 0x0c;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_ALARM: usize = 0x14;
+const ALARM_STATUS: usize = 0x18;
+const CLEAR_INTERRUPT: usize = 0x1c;
+
+type AlarmCallback = Box<dyn FnOnce() + Send>;
+
+/// The Google Goldfish real-time clock, which QEMU `virt` backs with the host's clock.
+pub struct GoldfishRtc {
+    base: usize,
+    /// The PLIC source, if the alarm can interrupt.
+    pub irq: Option<u32>,
+}
+
+static RTC: Once<GoldfishRtc> = Once::new();
+static ALARM: Mutex<Option<AlarmCallback>> = Mutex::new(None);
+
+impl GoldfishRtc {
+    pub const fn new(base: usize, irq: Option<u32>) -> Self {
+        GoldfishRtc { base, irq }
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { read_volatile((self.base + register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { write_volatile((self.base + register) as *mut u32, value) }
+    }
+
+    /// The time since the Unix epoch.
+    pub fn now(&self) -> Duration {
+        let low = self.read(TIME_LOW) as u64;
+        let high = self.read(TIME_HIGH) as u64;
+        Duration::from_nanos(high << 32 | low)
+    }
+
+    /// Raise the interrupt at `at` since the epoch, replacing any alarm set before.
+    pub fn set_alarm(&self, at: Duration) {
+        let nanos = at.as_nanos().min(u64::MAX as u128) as u64;
+        self.write(IRQ_ENABLED, 1);
+        self.write(ALARM_HIGH, (nanos >> 32) as u32);
+        self.write(ALARM_LOW, nanos as u32);
+    }
+
+    pub fn clear_alarm(&self) {
+        self.write(CLEAR_ALARM, 1);
+    }
+
+    pub fn is_alarm_armed(&self) -> bool {
+        self.read(ALARM_STATUS) != 0
+    }
+
+    fn clear_interrupt(&self) {
+        self.write(CLEAR_INTERRUPT, 1);
+    }
+}
+
+/// Find the RTC in the device tree and route its alarm through the PLIC.
+pub fn init() {
+    let Some(node) =
+        device_tree::get().and_then(|tree| tree.find_compatible("google,goldfish-rtc").next())
+    else {
+        return;
+    };
+    let Some((base, _)) = node.reg().first().copied() else {
+        return;
+    };
+    let irq = node.interrupts().first().copied();
+    let rtc = RTC.call_once(|| GoldfishRtc::new(base, irq));
+    rtc.clear_alarm();
+    rtc.clear_interrupt();
+    if let Some(irq) = irq {
+        plic::register_handler(irq, plic::DEFAULT_PRIORITY, handle_alarm);
+    }
+}
+
+pub fn get() -> Option<&'static GoldfishRtc> {
+    RTC.get()
+}
+
+/// Run `callback` once the wall clock reaches `at` since the epoch.
+///
+/// - Only one alarm is pending at a time; a new one replaces the old one.
+/// - `callback` runs in the interrupt handler, like a timer callback.
+/// - Returns `false` if there is no RTC or its alarm cannot interrupt.
+pub fn set_alarm(at: Duration, callback: impl FnOnce() + Send + 'static) -> bool {
+    let Some(rtc) = get().filter(|rtc| rtc.irq.is_some()) else {
+        return false;
+    };
+    without_interrupts(|| {
+        *ALARM.lock() = Some(Box::new(callback));
+        rtc.set_alarm(at);
+    });
+    true
+}
+
+pub fn cancel_alarm() {
+    if let Some(rtc) = get() {
+        without_interrupts(|| {
+            rtc.clear_alarm();
+            ALARM.lock().take();
+        });
+    }
+}
+
+fn handle_alarm(_source: u32) {
+    let Some(rtc) = get() else {
+        return;
+    };
+    rtc.clear_interrupt();
+    let callback = ALARM.lock().take();
+    if let Some(callback) = callback {
+        callback();
+    }
+}
@@ -0,0 +1,201 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use spin::Once;
+
+use crate::{console, device_tree};
+
+use super::plic;
+
+/// Receive buffer (read) and transmit holding register (write).
+const RBR_THR: usize = 0;
+const IER: usize = 1;
+/// FIFO control (write).
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+/// Divisor latch, low and high byte, while `LCR.DLAB` is set.
+const DLL: usize = 0;
+const DLM: usize = 1;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+/// Interrupt when the receive FIFO holds 8 bytes, or after a character time without more.
+const FCR_RX_TRIGGER_8: u8 = 0b10 << 6;
+const LCR_DLAB: u8 = 1 << 7;
+/// DTR, RTS and OUT2, which gates the interrupt line on PC-style boards.
+const MCR_DTR_RTS_OUT2: u8 = 0b1011;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+const FIFO_DEPTH: usize = 16;
+
+/// QEMU's NS16550A runs from a 3.6864 MHz clock when the device tree does not say.
+const DEFAULT_CLOCK: u32 = 3_686_400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// Baud rate and framing.
+#[derive(Debug, Clone, Copy)]
+pub struct LineConfig {
+    pub baud: u32,
+    /// 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2.
+    pub stop_bits: u8,
+}
+
+impl Default for LineConfig {
+    /// 115200 8N1
+    fn default() -> Self {
+        LineConfig {
+            baud: 115_200,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+        }
+    }
+}
+
+/// An NS16550A-compatible UART with one-byte registers.
+pub struct Uart {
+    base: usize,
+    clock: u32,
+    /// The PLIC source, if the UART is wired to one.
+    pub irq: Option<u32>,
+}
+
+static CONSOLE: Once<Uart> = Once::new();
+
+impl Uart {
+    pub const fn new(base: usize, clock: u32, irq: Option<u32>) -> Self {
+        Uart { base, clock, irq }
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        unsafe { read_volatile((self.base + register) as *const u8) }
+    }
+
+    fn write(&self, register: usize, value: u8) {
+        unsafe { write_volatile((self.base + register) as *mut u8, value) }
+    }
+
+    /// Program the baud rate and framing, and reset both FIFOs.
+    ///
+    /// - Interrupts stay off until [`Uart::set_rx_interrupt`].
+    pub fn configure(&self, config: &LineConfig) {
+        self.write(IER, 0);
+
+        let divisor = (self.clock / (16 * config.baud)).clamp(1, u16::MAX as u32) as u16;
+        self.write(LCR, LCR_DLAB);
+        self.write(DLL, divisor as u8);
+        self.write(DLM, (divisor >> 8) as u8);
+
+        let data_bits = config.data_bits.clamp(5, 8) - 5;
+        let stop_bits = if config.stop_bits > 1 { 1 << 2 } else { 0 };
+        let parity = match config.parity {
+            Parity::None => 0,
+            Parity::Odd => 1 << 3,
+            Parity::Even => 0b11 << 3,
+        };
+        self.write(LCR, data_bits | stop_bits | parity);
+
+        self.write(
+            FCR,
+            FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX | FCR_RX_TRIGGER_8,
+        );
+        self.write(MCR, MCR_DTR_RTS_OUT2);
+    }
+
+    pub fn set_rx_interrupt(&self, enabled: bool) {
+        self.write(IER, if enabled { IER_RX_AVAILABLE } else { 0 });
+    }
+
+    pub fn is_rx_interrupt_enabled(&self) -> bool {
+        self.read(IER) & IER_RX_AVAILABLE != 0
+    }
+
+    /// Send `bytes`, filling the transmit FIFO whenever it drains.
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        for chunk in bytes.chunks(FIFO_DEPTH) {
+            while self.read(LSR) & LSR_THR_EMPTY == 0 {}
+            for byte in chunk {
+                self.write(RBR_THR, *byte);
+            }
+        }
+    }
+
+    pub fn read_byte(&self) -> Option<u8> {
+        if self.read(LSR) & LSR_DATA_READY == 0 {
+            return None;
+        }
+        Some(self.read(RBR_THR))
+    }
+}
+
+/// Take over the UART that `/chosen/stdout-path` names as the kernel console.
+pub fn init() {
+    let Some(node) = device_tree::get().and_then(|tree| tree.stdout()) else {
+        return;
+    };
+    if !node.is_compatible("ns16550a") {
+        return;
+    }
+    let Some((base, _)) = node.reg().first().copied() else {
+        return;
+    };
+    let clock = node
+        .property_u32("clock-frequency")
+        .unwrap_or(DEFAULT_CLOCK);
+    let irq = node.interrupts().first().copied();
+    let uart = CONSOLE.call_once(|| Uart::new(base, clock, irq));
+    uart.configure(&LineConfig::default());
+
+    if let Some(irq) = irq {
+        if plic::register_handler(irq, plic::DEFAULT_PRIORITY, |_| receive_pending()) {
+            uart.set_rx_interrupt(true);
+        }
+    }
+}
+
+/// An NS16550A in the device tree besides the console, configured for polling, e.g. for a
+/// debugger.
+///
+/// - Every call finds the same one.
+pub fn find_spare() -> Option<Uart> {
+    let tree = device_tree::get()?;
+    let console = console().map(|uart| uart.base);
+    let (base, clock) = tree.find_compatible("ns16550a").find_map(|node| {
+        let (base, _) = node.reg().first().copied()?;
+        let clock = node
+            .property_u32("clock-frequency")
+            .unwrap_or(DEFAULT_CLOCK);
+        (Some(base) != console).then_some((base, clock))
+    })?;
+    let uart = Uart::new(base, clock, None);
+    uart.configure(&LineConfig::default());
+    Some(uart)
+}
+
+/// The UART backing the kernel console, once [`init`] found one.
+pub fn console() -> Option<&'static Uart> {
+    CONSOLE.get()
+}
+
+/// Drain the receive FIFO into the console input.
+pub fn receive_pending() {
+    let Some(uart) = console() else {
+        return;
+    };
+    while let Some(byte) = uart.read_byte() {
+        console::receive(byte);
+    }
+}
@@ -0,0 +1,330 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    hint::spin_loop,
+    mem::size_of,
+    ptr::read_volatile,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::{
+    drivers::{
+        block::{self, check_range, BlockDevice, BlockError, SECTOR_SIZE},
+        plic,
+    },
+    exception::without_interrupts,
+    task::{self, WaitQueue},
+};
+
+use super::{Buffer, MmioTransport, VirtQueue, VirtioError};
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+const REQUEST_DISCARD: u32 = 11;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+const FEATURE_RO: u64 = 1 << 5;
+const FEATURE_FLUSH: u64 = 1 << 9;
+const FEATURE_DISCARD: u64 = 1 << 13;
+
+/// `capacity` in the device configuration, in sectors.
+const CONFIG_CAPACITY: usize = 0;
+
+const QUEUE_SIZE: u16 = 64;
+/// Header, data and status.
+const MAX_DESCRIPTORS_PER_REQUEST: u16 = 3;
+
+/// The request header the device reads first.
+#[repr(C)]
+struct Header {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// The memory of a request, which the device reads and writes until it completes.
+struct RequestBuffers {
+    header: Header,
+    data: Vec<u8>,
+    status: u8,
+}
+
+/// A request in flight, completed by the interrupt handler.
+pub struct Request {
+    buffers: Mutex<RequestBuffers>,
+    is_done: AtomicBool,
+    waiters: WaitQueue,
+}
+
+impl Request {
+    fn new(kind: u32, sector: u64, data: Vec<u8>) -> Request {
+        Request {
+            buffers: Mutex::new(RequestBuffers {
+                header: Header {
+                    kind,
+                    reserved: 0,
+                    sector,
+                },
+                data,
+                status: u8::MAX,
+            }),
+            is_done: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.is_done.load(Ordering::Acquire)
+    }
+
+    /// The outcome of a completed request, with its data buffer.
+    fn take_result(&self) -> Result<Vec<u8>, BlockError> {
+        let mut buffers = self.buffers.lock();
+        match unsafe { read_volatile(&buffers.status) } {
+            STATUS_OK => Ok(core::mem::take(&mut buffers.data)),
+            STATUS_UNSUPPORTED => Err(BlockError::Unsupported),
+            _ => Err(BlockError::Io),
+        }
+    }
+}
+
+/// A virtio block device with one request queue.
+pub struct VirtioBlk {
+    name: String,
+    transport: MmioTransport,
+    /// Whether completions raise an interrupt; otherwise every waiter polls.
+    is_interrupt_driven: bool,
+    features: u64,
+    capacity: u64,
+    queue: Mutex<VirtQueue>,
+    /// Requests by the head descriptor of their chain.
+    in_flight: Mutex<BTreeMap<u16, Arc<Request>>>,
+    /// Submitters waiting for free descriptors.
+    slot_waiters: WaitQueue,
+}
+
+/// Devices whose interrupts [`handle_interrupt`] serves, by PLIC source.
+static DEVICES: Mutex<Vec<(u32, Arc<VirtioBlk>)>> = Mutex::new(Vec::new());
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+impl VirtioBlk {
+    /// Offer a request to the device without waiting for it to complete.
+    ///
+    /// - `data` is read by the device, or written for [`REQUEST_IN`].
+    /// - Waits for descriptors if the queue is full.
+    fn submit(&self, kind: u32, sector: u64, data: Vec<u8>) -> Arc<Request> {
+        let request = Arc::new(Request::new(kind, sector, data));
+        loop {
+            let is_added = without_interrupts(|| {
+                let mut queue = self.queue.lock();
+                let mut buffers = request.buffers.lock();
+                let mut chain = vec![Buffer {
+                    addr: &buffers.header as *const Header as usize,
+                    len: size_of::<Header>() as u32,
+                    is_writable: false,
+                }];
+                if !buffers.data.is_empty() {
+                    chain.push(match kind {
+                        REQUEST_IN => Buffer::writable(&mut buffers.data),
+                        _ => Buffer::readable(&buffers.data),
+                    });
+                }
+                chain.push(Buffer::writable(core::slice::from_mut(&mut buffers.status)));
+                let Some(head) = queue.add(&chain) else {
+                    return false;
+                };
+                self.in_flight.lock().insert(head, request.clone());
+                self.transport.notify(queue.index());
+                true
+            });
+            if is_added {
+                return request;
+            }
+            self.wait_until(&self.slot_waiters, || {
+                self.queue.lock().num_free() >= MAX_DESCRIPTORS_PER_REQUEST
+            });
+        }
+    }
+
+    /// Read `sectors` sectors at `sector` in the background.
+    pub fn submit_read(&self, sector: u64, sectors: usize) -> Result<Arc<Request>, BlockError> {
+        check_range(self.capacity, sector, sectors * SECTOR_SIZE)?;
+        Ok(self.submit(REQUEST_IN, sector, vec![0; sectors * SECTOR_SIZE]))
+    }
+
+    /// Write `data` at `sector` in the background.
+    pub fn submit_write(&self, sector: u64, data: Vec<u8>) -> Result<Arc<Request>, BlockError> {
+        if self.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        check_range(self.capacity, sector, data.len())?;
+        Ok(self.submit(REQUEST_OUT, sector, data))
+    }
+
+    /// Wait for `request` to complete and take back its data buffer.
+    pub fn wait(&self, request: &Request) -> Result<Vec<u8>, BlockError> {
+        self.wait_until(&request.waiters, || request.is_done());
+        request.take_result()
+    }
+
+    /// Sleep on `waiters` until `condition` holds, or poll the queue if the caller cannot sleep.
+    fn wait_until(&self, waiters: &WaitQueue, condition: impl Fn() -> bool) {
+        while !condition() {
+            if self.is_interrupt_driven && task::can_sleep() {
+                waiters.sleep_if(|| !condition());
+            } else {
+                self.complete_requests();
+                spin_loop();
+            }
+        }
+    }
+
+    /// Complete the requests the device has returned and wake their waiters.
+    fn complete_requests(&self) {
+        let completed = without_interrupts(|| {
+            self.transport.ack_interrupt();
+            let mut queue = self.queue.lock();
+            let mut in_flight = self.in_flight.lock();
+            let mut completed = Vec::new();
+            while let Some((head, _)) = queue.pop_used() {
+                completed.extend(in_flight.remove(&head));
+            }
+            completed
+        });
+        if completed.is_empty() {
+            return;
+        }
+        for request in completed {
+            request.is_done.store(true, Ordering::Release);
+            request.waiters.wake_all();
+        }
+        self.slot_waiters.wake_all();
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.features & FEATURE_RO != 0
+    }
+
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_range(self.capacity, sector, buf.len())?;
+        let request = self.submit_read(sector, buf.len() / SECTOR_SIZE)?;
+        let data = self.wait(&request)?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let request = self.submit_write(sector, buf.to_vec())?;
+        self.wait(&request).map(|_| ())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        // Without the feature, the device writes through.
+        if self.features & FEATURE_FLUSH == 0 {
+            return Ok(());
+        }
+        let request = self.submit(REQUEST_FLUSH, 0, Vec::new());
+        self.wait(&request).map(|_| ())
+    }
+
+    fn discard(&self, sector: u64, count: u64) -> Result<(), BlockError> {
+        if self.features & FEATURE_DISCARD == 0 {
+            return Err(BlockError::Unsupported);
+        }
+        if self.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        let end = sector.checked_add(count).ok_or(BlockError::OutOfRange)?;
+        if end > self.capacity {
+            return Err(BlockError::OutOfRange);
+        }
+        let mut sector = sector;
+        while sector < end {
+            let num_sectors = (end - sector).min(u32::MAX as u64) as u32;
+            // One segment: the sector, the number of sectors and flags.
+            let mut data = Vec::with_capacity(16);
+            data.extend_from_slice(&sector.to_le_bytes());
+            data.extend_from_slice(&num_sectors.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            let request = self.submit(REQUEST_DISCARD, 0, data);
+            self.wait(&request)?;
+            sector += num_sectors as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Bind a virtio block device, named `vda`, `vdb` and so on in probe order.
+pub fn probe(transport: MmioTransport, irq: Option<u32>) -> Result<(), VirtioError> {
+    let features = transport.negotiate(FEATURE_RO | FEATURE_FLUSH | FEATURE_DISCARD)?;
+    let max = transport.max_queue_size(0).min(QUEUE_SIZE);
+    if max == 0 {
+        transport.fail();
+        return Err(VirtioError::QueueUnavailable(0));
+    }
+    // Queue sizes are powers of two.
+    let size = 1 << (u16::BITS - 1 - max.leading_zeros());
+    let Some(queue) = VirtQueue::new(0, size) else {
+        transport.fail();
+        return Err(VirtioError::OutOfMemory);
+    };
+    if let Err(error) = transport.setup_queue(&queue) {
+        transport.fail();
+        return Err(error);
+    }
+    let capacity = transport.config_u64(CONFIG_CAPACITY);
+    transport.finish_init();
+
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+    let is_interrupt_driven =
+        irq.is_some_and(|irq| plic::get().is_some_and(|plic| plic.is_valid(irq)));
+    let device = Arc::new(VirtioBlk {
+        name: disk_name(index),
+        transport,
+        is_interrupt_driven,
+        features,
+        capacity,
+        queue: Mutex::new(queue),
+        in_flight: Mutex::new(BTreeMap::new()),
+        slot_waiters: WaitQueue::new(),
+    });
+    if let (Some(irq), true) = (irq, is_interrupt_driven) {
+        without_interrupts(|| DEVICES.lock().push((irq, device.clone())));
+        plic::register_handler(irq, plic::DEFAULT_PRIORITY, handle_interrupt);
+    }
+    block::register(device);
+    Ok(())
+}
+
+/// `vda` to `vdz`, then `vdaa` on, as Linux names them.
+fn disk_name(index: usize) -> String {
+    let mut name = String::from("vd");
+    let mut rest = index + 1;
+    while rest > 0 {
+        rest -= 1;
+        name.insert(2, (b'a' + (rest % 26) as u8) as char);
+        rest /= 26;
+    }
+    name
+}
+
+fn handle_interrupt(source: u32) {
+    let devices = DEVICES.lock();
+    for (_, device) in devices.iter().filter(|(irq, _)| *irq == source) {
+        device.complete_requests();
+    }
+}
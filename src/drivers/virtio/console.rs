@@ -0,0 +1,109 @@
+use alloc::{boxed::Box, collections::BTreeMap, collections::VecDeque, vec, vec::Vec};
+use core::hint::spin_loop;
+
+use spin::Mutex;
+
+use super::{Buffer, MmioTransport, VirtQueue, VirtioError};
+
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 16;
+const RECEIVE_BUFFER_SIZE: usize = 64;
+
+/// A virtio console, driven by polling: its user, the GDB stub, runs with interrupts off.
+///
+/// - `VIRTIO_CONSOLE_F_MULTIPORT` is not negotiated, so only port 0 exists; on QEMU that is the
+///   `virtconsole` of a `virtio-serial-device`.
+pub struct VirtioConsole {
+    transport: MmioTransport,
+    receive: VirtQueue,
+    transmit: VirtQueue,
+    /// The buffers the device fills, by the descriptor they sit in.
+    receive_buffers: BTreeMap<u16, Box<[u8]>>,
+    /// Bytes the device returned that nobody read yet.
+    received: VecDeque<u8>,
+}
+
+/// Bound consoles not yet claimed by [`take`].
+static CONSOLES: Mutex<Vec<VirtioConsole>> = Mutex::new(Vec::new());
+
+impl VirtioConsole {
+    /// Hand `buffer` to the device to receive into.
+    fn offer(&mut self, mut buffer: Box<[u8]>) {
+        if let Some(head) = self.receive.add(&[Buffer::writable(&mut buffer)]) {
+            self.receive_buffers.insert(head, buffer);
+            self.transport.notify(RECEIVE_QUEUE);
+        }
+    }
+
+    /// The next byte from the other end, if one has arrived.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        while let Some((head, len)) = self.receive.pop_used() {
+            let buffer = self.receive_buffers.remove(&head).unwrap();
+            self.received.extend(&buffer[..len as usize]);
+            self.offer(buffer);
+        }
+        self.received.pop_front()
+    }
+
+    /// Send `bytes`, waiting until the device has taken them.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let data = bytes.to_vec();
+        if self.transmit.add(&[Buffer::readable(&data)]).is_none() {
+            return;
+        }
+        self.transport.notify(TRANSMIT_QUEUE);
+        while self.transmit.pop_used().is_none() {
+            spin_loop();
+        }
+    }
+}
+
+/// Bind a virtio console for [`take`] to claim.
+///
+/// - Its interrupt is left unused.
+pub fn probe(transport: MmioTransport, _irq: Option<u32>) -> Result<(), VirtioError> {
+    transport.negotiate(0)?;
+    let mut queues = Vec::new();
+    for index in [RECEIVE_QUEUE, TRANSMIT_QUEUE] {
+        let size = transport.max_queue_size(index).min(QUEUE_SIZE);
+        let Some(queue) = (size != 0)
+            .then(|| 1 << (u16::BITS - 1 - size.leading_zeros()))
+            .and_then(|size| VirtQueue::new(index, size))
+        else {
+            transport.fail();
+            return Err(match size {
+                0 => VirtioError::QueueUnavailable(index),
+                _ => VirtioError::OutOfMemory,
+            });
+        };
+        if let Err(error) = transport.setup_queue(&queue) {
+            transport.fail();
+            return Err(error);
+        }
+        queues.push(queue);
+    }
+    transport.finish_init();
+
+    let transmit = queues.pop().unwrap();
+    let receive = queues.pop().unwrap();
+    let size = receive.size();
+    let mut console = VirtioConsole {
+        transport,
+        receive,
+        transmit,
+        receive_buffers: BTreeMap::new(),
+        received: VecDeque::new(),
+    };
+    for _ in 0..size {
+        console.offer(vec![0; RECEIVE_BUFFER_SIZE].into_boxed_slice());
+    }
+    CONSOLES.lock().push(console);
+    Ok(())
+}
+
+/// Claim the first bound console that nothing else uses.
+pub fn take() -> Option<VirtioConsole> {
+    let mut consoles = CONSOLES.lock();
+    (!consoles.is_empty()).then(|| consoles.remove(0))
+}
@@ -0,0 +1,245 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::mm::{PAGE_SIZE, PAGE_SIZE_BITS};
+
+use super::{queue::VirtQueue, DeviceId, VirtioError};
+
+/// `"virt"` in little endian.
+const MAGIC: u32 = 0x7472_6976;
+const LEGACY_VERSION: u32 = 1;
+const MODERN_VERSION: u32 = 2;
+
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const VENDOR_ID: usize = 0x00c;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+/// Legacy only.
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+/// Legacy only.
+const QUEUE_ALIGN: usize = 0x03c;
+/// Legacy only.
+const QUEUE_PFN: usize = 0x040;
+/// Modern only, like the queue addresses below.
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG: usize = 0x100;
+
+const STATUS_ACKNOWLEDGE: u32 = 1 << 0;
+const STATUS_DRIVER: u32 = 1 << 1;
+const STATUS_DRIVER_OK: u32 = 1 << 2;
+const STATUS_FEATURES_OK: u32 = 1 << 3;
+const STATUS_FAILED: u32 = 1 << 7;
+
+/// The device follows the virtio 1.0 spec rather than the legacy interface.
+pub const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// `InterruptStatus`: a used ring was updated.
+pub const INTERRUPT_USED_BUFFER: u32 = 1 << 0;
+/// `InterruptStatus`: the device configuration changed.
+pub const INTERRUPT_CONFIG_CHANGE: u32 = 1 << 1;
+
+/// The registers of one virtio-mmio slot, in either the legacy (version 1) or the modern
+/// (version 2) layout.
+#[derive(Debug)]
+pub struct MmioTransport {
+    base: usize,
+    version: u32,
+}
+
+impl MmioTransport {
+    /// Check the magic value and version at `base`.
+    pub fn new(base: usize) -> Result<MmioTransport, VirtioError> {
+        let transport = MmioTransport { base, version: 0 };
+        if transport.read(MAGIC_VALUE) != MAGIC {
+            return Err(VirtioError::BadMagic);
+        }
+        let version = transport.read(VERSION);
+        if version != LEGACY_VERSION && version != MODERN_VERSION {
+            return Err(VirtioError::UnsupportedVersion(version));
+        }
+        Ok(MmioTransport { base, version })
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { read_volatile((self.base + register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { write_volatile((self.base + register) as *mut u32, value) }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == LEGACY_VERSION
+    }
+
+    /// `None` for an empty slot.
+    pub fn device_id(&self) -> Option<DeviceId> {
+        match self.read(DEVICE_ID) {
+            0 => None,
+            id => Some(DeviceId::from(id)),
+        }
+    }
+
+    pub fn vendor_id(&self) -> u32 {
+        self.read(VENDOR_ID)
+    }
+
+    fn add_status(&self, status: u32) {
+        self.write(STATUS, self.read(STATUS) | status);
+    }
+
+    pub fn reset(&self) {
+        self.write(STATUS, 0);
+    }
+
+    /// Reset the device and agree on the features both sides support.
+    ///
+    /// - `VIRTIO_F_VERSION_1` is required of modern devices and added to `driver_features`.
+    /// - Returns the negotiated features; queues are set up next, then [`MmioTransport::finish_init`].
+    pub fn negotiate(&self, driver_features: u64) -> Result<u64, VirtioError> {
+        self.reset();
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+
+        let mut device_features = 0;
+        // Legacy devices only have the low 32 bits.
+        let words = if self.is_legacy() { 1 } else { 2 };
+        for word in 0..words {
+            self.write(DEVICE_FEATURES_SEL, word);
+            device_features |= (self.read(DEVICE_FEATURES) as u64) << (32 * word);
+        }
+        let driver_features = match self.is_legacy() {
+            true => driver_features & !FEATURE_VERSION_1,
+            false => driver_features | FEATURE_VERSION_1,
+        };
+        let features = device_features & driver_features;
+        if !self.is_legacy() && features & FEATURE_VERSION_1 == 0 {
+            self.fail();
+            return Err(VirtioError::FeaturesRejected);
+        }
+        for word in 0..words {
+            self.write(DRIVER_FEATURES_SEL, word);
+            self.write(DRIVER_FEATURES, (features >> (32 * word)) as u32);
+        }
+
+        if self.is_legacy() {
+            self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            self.add_status(STATUS_FEATURES_OK);
+            if self.read(STATUS) & STATUS_FEATURES_OK == 0 {
+                self.fail();
+                return Err(VirtioError::FeaturesRejected);
+            }
+        }
+        Ok(features)
+    }
+
+    /// Tell the device the driver is ready; it may use the queues from now on.
+    pub fn finish_init(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Give up on the device.
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    /// The most descriptors queue `index` takes; 0 if it does not exist.
+    pub fn max_queue_size(&self, index: u16) -> u16 {
+        self.write(QUEUE_SEL, index as u32);
+        self.read(QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    /// Hand `queue`'s rings to the device.
+    pub fn setup_queue(&self, queue: &VirtQueue) -> Result<(), VirtioError> {
+        let max = self.max_queue_size(queue.index());
+        if max == 0 {
+            return Err(VirtioError::QueueUnavailable(queue.index()));
+        }
+        if queue.size() > max {
+            return Err(VirtioError::QueueTooLarge(queue.index()));
+        }
+        self.write(QUEUE_NUM, queue.size() as u32);
+        if self.is_legacy() {
+            // The legacy layout is the one `VirtQueue` always uses: the rings are contiguous,
+            // with the used ring on the next page boundary.
+            self.write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(QUEUE_PFN, (queue.desc_address() >> PAGE_SIZE_BITS) as u32);
+        } else {
+            let write_u64 = |low: usize, high: usize, value: usize| {
+                self.write(low, value as u32);
+                self.write(high, (value as u64 >> 32) as u32);
+            };
+            write_u64(QUEUE_DESC_LOW, QUEUE_DESC_HIGH, queue.desc_address());
+            write_u64(QUEUE_DRIVER_LOW, QUEUE_DRIVER_HIGH, queue.avail_address());
+            write_u64(QUEUE_DEVICE_LOW, QUEUE_DEVICE_HIGH, queue.used_address());
+            self.write(QUEUE_READY, 1);
+        }
+        Ok(())
+    }
+
+    /// Tell the device there are new buffers in queue `index`.
+    pub fn notify(&self, index: u16) {
+        self.write(QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Acknowledge the device's interrupt, returning the `INTERRUPT_*` causes.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+        status
+    }
+
+    /// Read the device-specific configuration at `offset`, retrying if the device changed it
+    /// midway.
+    ///
+    /// - Legacy devices have no generation counter and read back 0 each time.
+    pub fn read_config(&self, offset: usize, buf: &mut [u8]) {
+        loop {
+            let generation = self.read(CONFIG_GENERATION);
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = unsafe { read_volatile((self.base + CONFIG + offset + i) as *const u8) };
+            }
+            if self.read(CONFIG_GENERATION) == generation {
+                break;
+            }
+        }
+    }
+
+    pub fn config_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        self.read_config(offset, &mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    pub fn config_u64(&self, offset: usize) -> u64 {
+        let mut bytes = [0; 8];
+        self.read_config(offset, &mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{device_tree, supervisor_print, supervisor_println};
+
+pub mod blk;
+pub mod console;
+pub mod mmio;
+pub mod queue;
+
+pub use mmio::MmioTransport;
+pub use queue::{Buffer, VirtQueue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceId {
+    Network,
+    Block,
+    Console,
+    Entropy,
+    Gpu,
+    Input,
+    Other(u32),
+}
+
+impl From<u32> for DeviceId {
+    fn from(id: u32) -> Self {
+        match id {
+            1 => DeviceId::Network,
+            2 => DeviceId::Block,
+            3 => DeviceId::Console,
+            4 => DeviceId::Entropy,
+            16 => DeviceId::Gpu,
+            18 => DeviceId::Input,
+            id => DeviceId::Other(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The device refused the features the driver can work with.
+    FeaturesRejected,
+    QueueUnavailable(u16),
+    /// The queue has more descriptors than the device allows.
+    QueueTooLarge(u16),
+    OutOfMemory,
+}
+
+/// A driver for one type of virtio device.
+pub struct Driver {
+    pub device_id: DeviceId,
+    pub name: &'static str,
+    /// Take over the device behind `transport`, whose interrupt is the PLIC source `irq`.
+    pub probe: fn(transport: MmioTransport, irq: Option<u32>) -> Result<(), VirtioError>,
+}
+
+/// The drivers [`init`] binds devices to.
+const DRIVERS: &[Driver] = &[
+    Driver {
+        device_id: DeviceId::Block,
+        name: "virtio-blk",
+        probe: blk::probe,
+    },
+    Driver {
+        device_id: DeviceId::Console,
+        name: "virtio-console",
+        probe: console::probe,
+    },
+];
+
+/// Bind a driver to every virtio-mmio device in the device tree.
+pub fn init() {
+    let Some(tree) = device_tree::get() else {
+        return;
+    };
+    for node in tree.find_compatible("virtio,mmio") {
+        let Some((base, _)) = node.reg().first().copied() else {
+            continue;
+        };
+        let transport = match MmioTransport::new(base) {
+            Ok(transport) => transport,
+            Err(error) => {
+                supervisor_println!("virtio-mmio@{:#x}: {:?}", base, error);
+                continue;
+            }
+        };
+        // QEMU lists every slot, most of them empty.
+        let Some(device_id) = transport.device_id() else {
+            continue;
+        };
+        let version = transport.version();
+        let Some(driver) = DRIVERS.iter().find(|driver| driver.device_id == device_id) else {
+            supervisor_println!(
+                "virtio-mmio@{:#x}: {:?} (v{}), no driver",
+                base,
+                device_id,
+                version
+            );
+            continue;
+        };
+        let irq = node.interrupts().first().copied();
+        match (driver.probe)(transport, irq) {
+            Ok(()) => supervisor_println!(
+                "virtio-mmio@{:#x}: {:?} (v{}) bound to {}",
+                base,
+                device_id,
+                version,
+                driver.name
+            ),
+            Err(error) => supervisor_println!(
+                "virtio-mmio@{:#x}: {} failed: {:?}",
+                base,
+                driver.name,
+                error
+            ),
+        }
+    }
+}
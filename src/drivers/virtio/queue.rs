@@ -0,0 +1,242 @@
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::{
+    alloc::Layout,
+    mem::size_of,
+    ptr::{addr_of_mut, read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+
+use crate::mm::{page_ceil, PAGE_SIZE};
+
+const DESC_F_NEXT: u16 = 1 << 0;
+/// The device writes the buffer rather than reading it.
+const DESC_F_WRITE: u16 = 1 << 1;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer handed to the device, by physical address.
+///
+/// - The memory must stay put until the device returns the chain it is in, so callers pass
+///   heap or kernel addresses, which are identity-mapped.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: usize,
+    pub len: u32,
+    /// The device writes into the buffer; otherwise it only reads it.
+    pub is_writable: bool,
+}
+
+impl Buffer {
+    pub fn readable(data: &[u8]) -> Buffer {
+        Buffer {
+            addr: data.as_ptr() as usize,
+            len: data.len() as u32,
+            is_writable: false,
+        }
+    }
+
+    pub fn writable(data: &mut [u8]) -> Buffer {
+        Buffer {
+            addr: data.as_mut_ptr() as usize,
+            len: data.len() as u32,
+            is_writable: true,
+        }
+    }
+}
+
+/// A split virtqueue: a descriptor table, the driver's available ring and the device's used
+/// ring.
+///
+/// - The rings are laid out as legacy devices need them: contiguous, page-aligned, with the
+///   used ring on its own page boundary. Modern devices take the three addresses as they are.
+/// - The memory comes from the kernel heap, which sits in identity-mapped RAM, so its virtual
+///   addresses are the physical ones the device sees.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    memory: *mut u8,
+    layout: Layout,
+    used_offset: usize,
+    /// Head of the free descriptor list, chained through `next`.
+    free_head: u16,
+    num_free: u16,
+    /// The driver's copy of `avail.idx`.
+    avail_idx: u16,
+    /// How far the driver has read the used ring.
+    last_used_idx: u16,
+}
+
+// The queue owns its rings, which only the device touches besides it.
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    /// Allocate queue `index` with `size` descriptors, a power of two.
+    pub fn new(index: u16, size: u16) -> Option<VirtQueue> {
+        if !size.is_power_of_two() {
+            return None;
+        }
+        let n = size as usize;
+        let driver_size = size_of::<Descriptor>() * n + size_of::<u16>() * (3 + n);
+        let device_size = size_of::<u16>() * 3 + size_of::<UsedElement>() * n;
+        let used_offset = page_ceil(driver_size);
+        let layout =
+            Layout::from_size_align(used_offset + page_ceil(device_size), PAGE_SIZE).ok()?;
+        let memory = unsafe { alloc_zeroed(layout) };
+        if memory.is_null() {
+            return None;
+        }
+        let queue = VirtQueue {
+            index,
+            size,
+            memory,
+            layout,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size {
+            queue.descriptor(i).next = (i + 1) % size;
+        }
+        Some(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    pub fn desc_address(&self) -> usize {
+        self.memory as usize
+    }
+
+    pub fn avail_address(&self) -> usize {
+        self.desc_address() + size_of::<Descriptor>() * self.size as usize
+    }
+
+    pub fn used_address(&self) -> usize {
+        self.desc_address() + self.used_offset
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn descriptor(&self, i: u16) -> &mut Descriptor {
+        unsafe { &mut *(self.desc_address() as *mut Descriptor).add(i as usize) }
+    }
+
+    /// `avail.ring[slot]`, after the `flags` and `idx` fields.
+    fn avail_slot(&self, slot: u16) -> *mut u16 {
+        let ring = (self.avail_address() as *mut u16).wrapping_add(2);
+        ring.wrapping_add((slot % self.size) as usize)
+    }
+
+    fn avail_idx(&self) -> *mut u16 {
+        (self.avail_address() as *mut u16).wrapping_add(1)
+    }
+
+    fn used_idx(&self) -> u16 {
+        let idx = (self.used_address() as *const u16).wrapping_add(1);
+        unsafe { read_volatile(idx) }
+    }
+
+    fn used_element(&self, slot: u16) -> *mut UsedElement {
+        let ring = (self.used_address() + size_of::<u16>() * 2) as *mut UsedElement;
+        ring.wrapping_add((slot % self.size) as usize)
+    }
+
+    /// Chain `buffers` and offer them to the device, returning the head descriptor that
+    /// [`VirtQueue::pop_used`] later hands back.
+    ///
+    /// - The device reads the readable buffers, which must come before the writable ones.
+    /// - Returns `None` if the queue lacks free descriptors.
+    /// - The caller notifies the device afterwards.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let index = self.free_head;
+            let descriptor = self.descriptor(index);
+            descriptor.addr = buffer.addr as u64;
+            descriptor.len = buffer.len;
+            descriptor.flags = if buffer.is_writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                descriptor.flags |= DESC_F_NEXT;
+            }
+            self.free_head = descriptor.next;
+        }
+        self.num_free -= buffers.len() as u16;
+
+        unsafe { write_volatile(self.avail_slot(self.avail_idx), head) };
+        // The device must see the ring entry before the index that publishes it.
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { write_volatile(self.avail_idx(), self.avail_idx) };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    /// Whether the device has returned a chain not popped yet.
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.used_idx() != self.last_used_idx
+    }
+
+    /// Take the next chain the device is done with: its head and the bytes it wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        let element = self.used_element(self.last_used_idx);
+        let (id, len) = unsafe {
+            (
+                read_volatile(addr_of_mut!((*element).id)),
+                read_volatile(addr_of_mut!((*element).len)),
+            )
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        self.free_chain(id as u16);
+        Some((id as u16, len))
+    }
+
+    /// Put the chain at `head` back on the free list.
+    fn free_chain(&mut self, head: u16) {
+        let mut index = head;
+        loop {
+            self.num_free += 1;
+            let descriptor = self.descriptor(index);
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                descriptor.next = self.free_head;
+                break;
+            }
+            index = descriptor.next;
+        }
+        self.free_head = head;
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.memory, self.layout) };
+    }
+}
@@ -1,43 +1,31 @@
-use crate::sbi_call;
+use crate::{console, syscall};
 
 use super::ExceptionMutContext;
 
 const A0: usize = 10;
-const A1: usize = 11;
-const A6: usize = 16;
 const A7: usize = 17;
 
-pub fn abi_call(mut_context: &mut ExceptionMutContext) {
-    let sbi = sbi_call::decode_sbi_call(
-        mut_context.register_context.x[A0],
-        mut_context.register_context.x[A1],
-        mut_context.register_context.x[A6],
-        mut_context.register_context.x[A7],
-    );
+/// The legacy SBI console calls, whose numbers no system call the kernel implements takes.
+const LEGACY_CONSOLE_PUTCHAR: usize = 0x1;
+const LEGACY_CONSOLE_GETCHAR: usize = 0x2;
 
-    match sbi {
-        sbi_call::CompatibleSbi::Legacy(ext) => {
-            let res = sbi_call::legacy_sbi_call(&ext);
-            match res {
-                Ok(value) => {
-                    mut_context.register_context.x[A0] = value as usize;
-                }
-                Err(error) => {
-                    mut_context.register_context.x[A0] = error as usize;
-                }
-            }
+/// Serve an `ecall` from user mode and step past it, unless a system call restarts.
+///
+/// - Nothing reaches the firmware: user tasks must not program the timer or stop the machine.
+pub fn abi_call(mut_context: &mut ExceptionMutContext) {
+    let x = &mut mut_context.register_context.x;
+    match x[A7] {
+        // The kernel owns the console, so user tasks go through it rather than the firmware,
+        // which may not be allowed to touch the UART. Like the firmware call, reading does not
+        // block.
+        LEGACY_CONSOLE_GETCHAR => {
+            x[A0] = console::try_read_byte().map_or(usize::MAX, |byte| byte as usize);
         }
-        sbi_call::CompatibleSbi::Extension(ext) => {
-            let res = sbi_call::sbi_call(&ext);
-            match res {
-                Ok(value) => {
-                    mut_context.register_context.x[A0] = 0;
-                    mut_context.register_context.x[A1] = value as usize;
-                }
-                Err(error) => {
-                    mut_context.register_context.x[A0] = error as usize;
-                }
-            }
+        LEGACY_CONSOLE_PUTCHAR => {
+            console::write_bytes(&[x[A0] as u8]);
+            x[A0] = 0;
         }
+        _ => return syscall::syscall(mut_context),
     }
+    mut_context.sepc += 4;
 }
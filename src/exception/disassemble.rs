@@ -0,0 +1,261 @@
+use core::fmt;
+
+use super::REGISTER_NAMES;
+
+/// A raw instruction that formats as assembly.
+///
+/// - RV64IMA, `Zicsr`, `Zifencei` and the privileged instructions are decoded.
+/// - Anything else, including compressed instructions, is shown as `.word`/`.half`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disassembly {
+    pub instruction: u32,
+    pub len: usize,
+}
+
+impl Disassembly {
+    pub fn new(instruction: u32) -> Self {
+        let len = if instruction & 0b11 == 0b11 { 4 } else { 2 };
+        Disassembly { instruction, len }
+    }
+}
+
+fn reg(index: u32) -> &'static str {
+    REGISTER_NAMES[index as usize & 0x1f]
+}
+
+pub fn csr_name(csr: u32) -> Option<&'static str> {
+    let name = match csr {
+        0x100 => "sstatus",
+        0x104 => "sie",
+        0x105 => "stvec",
+        0x106 => "scounteren",
+        0x140 => "sscratch",
+        0x141 => "sepc",
+        0x142 => "scause",
+        0x143 => "stval",
+        0x144 => "sip",
+        0x14d => "stimecmp",
+        0x180 => "satp",
+        0xc00 => "cycle",
+        0xc01 => "time",
+        0xc02 => "instret",
+        _ => return None,
+    };
+    Some(name)
+}
+
+struct Csr(u32);
+
+impl fmt::Display for Csr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match csr_name(self.0) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// Sign-extend the low `bits` bits of `value`.
+pub fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ins = self.instruction;
+        if self.len == 2 {
+            return write!(f, ".half {:#06x}", ins & 0xffff);
+        }
+
+        let opcode = ins & 0x7f;
+        let rd = (ins >> 7) & 0x1f;
+        let funct3 = (ins >> 12) & 0b111;
+        let rs1 = (ins >> 15) & 0x1f;
+        let rs2 = (ins >> 20) & 0x1f;
+        let funct7 = ins >> 25;
+        let imm_i = sign_extend(ins >> 20, 12);
+        let imm_s = sign_extend((ins >> 25) << 5 | (ins >> 7) & 0x1f, 12);
+        let imm_b = sign_extend(
+            (ins >> 31) << 12
+                | ((ins >> 7) & 1) << 11
+                | ((ins >> 25) & 0x3f) << 5
+                | ((ins >> 8) & 0xf) << 1,
+            13,
+        );
+        let imm_u = (ins >> 12) as i32;
+        let imm_j = sign_extend(
+            (ins >> 31) << 20
+                | ((ins >> 12) & 0xff) << 12
+                | ((ins >> 20) & 1) << 11
+                | ((ins >> 21) & 0x3ff) << 1,
+            21,
+        );
+
+        match opcode {
+            0x37 => return write!(f, "lui {}, {:#x}", reg(rd), imm_u),
+            0x17 => return write!(f, "auipc {}, {:#x}", reg(rd), imm_u),
+            0x6f => return write!(f, "jal {}, {}", reg(rd), imm_j),
+            0x67 if funct3 == 0 => return write!(f, "jalr {}, {}({})", reg(rd), imm_i, reg(rs1)),
+            0x63 => {
+                let name = match funct3 {
+                    0 => Some("beq"),
+                    1 => Some("bne"),
+                    4 => Some("blt"),
+                    5 => Some("bge"),
+                    6 => Some("bltu"),
+                    7 => Some("bgeu"),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    return write!(f, "{} {}, {}, {}", name, reg(rs1), reg(rs2), imm_b);
+                }
+            }
+            0x03 => {
+                let name = ["lb", "lh", "lw", "ld", "lbu", "lhu", "lwu"].get(funct3 as usize);
+                if let Some(name) = name {
+                    return write!(f, "{} {}, {}({})", name, reg(rd), imm_i, reg(rs1));
+                }
+            }
+            0x23 => {
+                let name = ["sb", "sh", "sw", "sd"].get(funct3 as usize);
+                if let Some(name) = name {
+                    return write!(f, "{} {}, {}({})", name, reg(rs2), imm_s, reg(rs1));
+                }
+            }
+            0x13 | 0x1b => {
+                let is_word = opcode == 0x1b;
+                let shamt = (ins >> 20) & if is_word { 0x1f } else { 0x3f };
+                let name = match (funct3, is_word) {
+                    (0, false) => Some("addi"),
+                    (2, false) => Some("slti"),
+                    (3, false) => Some("sltiu"),
+                    (4, false) => Some("xori"),
+                    (6, false) => Some("ori"),
+                    (7, false) => Some("andi"),
+                    (0, true) => Some("addiw"),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    return write!(f, "{} {}, {}, {}", name, reg(rd), reg(rs1), imm_i);
+                }
+                let name = match (funct3, ins >> 26 & 0x3f == 0x10, is_word) {
+                    (1, false, false) => Some("slli"),
+                    (5, false, false) => Some("srli"),
+                    (5, true, false) => Some("srai"),
+                    (1, false, true) => Some("slliw"),
+                    (5, false, true) => Some("srliw"),
+                    (5, true, true) => Some("sraiw"),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    return write!(f, "{} {}, {}, {}", name, reg(rd), reg(rs1), shamt);
+                }
+            }
+            0x33 | 0x3b => {
+                let is_word = opcode == 0x3b;
+                let name = match (funct7, funct3, is_word) {
+                    (0x00, 0, false) => Some("add"),
+                    (0x20, 0, false) => Some("sub"),
+                    (0x00, 1, false) => Some("sll"),
+                    (0x00, 2, false) => Some("slt"),
+                    (0x00, 3, false) => Some("sltu"),
+                    (0x00, 4, false) => Some("xor"),
+                    (0x00, 5, false) => Some("srl"),
+                    (0x20, 5, false) => Some("sra"),
+                    (0x00, 6, false) => Some("or"),
+                    (0x00, 7, false) => Some("and"),
+                    (0x01, 0, false) => Some("mul"),
+                    (0x01, 1, false) => Some("mulh"),
+                    (0x01, 2, false) => Some("mulhsu"),
+                    (0x01, 3, false) => Some("mulhu"),
+                    (0x01, 4, false) => Some("div"),
+                    (0x01, 5, false) => Some("divu"),
+                    (0x01, 6, false) => Some("rem"),
+                    (0x01, 7, false) => Some("remu"),
+                    (0x00, 0, true) => Some("addw"),
+                    (0x20, 0, true) => Some("subw"),
+                    (0x00, 1, true) => Some("sllw"),
+                    (0x00, 5, true) => Some("srlw"),
+                    (0x20, 5, true) => Some("sraw"),
+                    (0x01, 0, true) => Some("mulw"),
+                    (0x01, 4, true) => Some("divw"),
+                    (0x01, 5, true) => Some("divuw"),
+                    (0x01, 6, true) => Some("remw"),
+                    (0x01, 7, true) => Some("remuw"),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    return write!(f, "{} {}, {}, {}", name, reg(rd), reg(rs1), reg(rs2));
+                }
+            }
+            0x2f if funct3 == 2 || funct3 == 3 => {
+                let width = if funct3 == 2 { "w" } else { "d" };
+                let name = match funct7 >> 2 {
+                    0x02 if rs2 == 0 => {
+                        return write!(f, "lr.{} {}, ({})", width, reg(rd), reg(rs1));
+                    }
+                    0x03 => Some("sc"),
+                    0x01 => Some("amoswap"),
+                    0x00 => Some("amoadd"),
+                    0x04 => Some("amoxor"),
+                    0x0c => Some("amoand"),
+                    0x08 => Some("amoor"),
+                    0x10 => Some("amomin"),
+                    0x14 => Some("amomax"),
+                    0x18 => Some("amominu"),
+                    0x1c => Some("amomaxu"),
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    return write!(
+                        f,
+                        "{}.{} {}, {}, ({})",
+                        name,
+                        width,
+                        reg(rd),
+                        reg(rs2),
+                        reg(rs1)
+                    );
+                }
+            }
+            0x0f => match funct3 {
+                0 => return f.write_str("fence"),
+                1 => return f.write_str("fence.i"),
+                _ => {}
+            },
+            0x73 => {
+                let csr = ins >> 20;
+                match funct3 {
+                    0 => {
+                        let name = match ins {
+                            0x0000_0073 => Some("ecall"),
+                            0x0010_0073 => Some("ebreak"),
+                            0x1020_0073 => Some("sret"),
+                            0x3020_0073 => Some("mret"),
+                            0x1050_0073 => Some("wfi"),
+                            _ => None,
+                        };
+                        if let Some(name) = name {
+                            return f.write_str(name);
+                        }
+                        if funct7 == 0x09 && rd == 0 {
+                            return write!(f, "sfence.vma {}, {}", reg(rs1), reg(rs2));
+                        }
+                    }
+                    1..=3 => {
+                        let name = ["csrrw", "csrrs", "csrrc"][funct3 as usize - 1];
+                        return write!(f, "{} {}, {}, {}", name, reg(rd), Csr(csr), reg(rs1));
+                    }
+                    5..=7 => {
+                        let name = ["csrrwi", "csrrsi", "csrrci"][funct3 as usize - 5];
+                        return write!(f, "{} {}, {}, {}", name, reg(rd), Csr(csr), rs1);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        write!(f, ".word {:#010x}", ins)
+    }
+}
@@ -1,5 +1,149 @@
-use super::{ExceptionMutContext, Fault};
+use core::fmt;
 
-pub fn handle_fault(_mut_context: &mut ExceptionMutContext, stval: usize, fault: &Fault) {
-    panic!("Fault: {:?}, stval: {}", fault, stval);
+use crate::{
+    backtrace,
+    mm::address_space::{Access, PageFaultError},
+    supervisor_print, supervisor_println,
+    task::{
+        self,
+        signal::{self, BUS_ADRALN, ILL_ILLOPC, SEGV_ACCERR, SEGV_MAPERR, SIGBUS, SIGILL, SIGSEGV},
+    },
+    Spp,
+};
+
+use super::{
+    disassemble::Disassembly, illegal, misaligned, ExceptionMutContext, Fault, RegisterContext,
+};
+
+const FP: usize = 8;
+
+/// Everything known about a fault that could not be resolved.
+#[derive(Debug, Clone)]
+pub struct FaultReport {
+    pub fault: Fault,
+    pub stval: usize,
+    pub sepc: usize,
+    /// The faulting instruction, if it was decoded.
+    pub instruction: Option<Disassembly>,
+    pub registers: RegisterContext,
+}
+
+impl FaultReport {
+    fn new(
+        mut_context: &ExceptionMutContext,
+        stval: usize,
+        fault: Fault,
+        instruction: Option<Disassembly>,
+    ) -> Self {
+        FaultReport {
+            fault,
+            stval,
+            sepc: mut_context.sepc,
+            instruction,
+            registers: mut_context.register_context.clone(),
+        }
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Fault: {:?}, stval: {:#x}, sepc: {:#x}",
+            self.fault, self.stval, self.sepc
+        )?;
+        if let Some(instruction) = &self.instruction {
+            writeln!(
+                f,
+                "Instruction: {} ({:#x})",
+                instruction, instruction.instruction
+            )?;
+        }
+        write!(f, "{}", self.registers)
+    }
+}
+
+/// - Faults from user mode raise `SIGSEGV`, `SIGILL` or `SIGBUS` in the faulting task, which
+///   terminates its process unless a handler takes it.
+/// - Faults from supervisor mode are kernel bugs and panic.
+pub fn handle_fault(mut_context: &mut ExceptionMutContext, stval: usize, fault: &Fault) {
+    let mut instruction = None;
+    let mut page_fault = None;
+    let mut handle_page_fault = |access| {
+        handle_page_fault(stval, access).map_err(|error| page_fault = Some((access, error)))
+    };
+    let res = match fault {
+        Fault::InstructionPageFault => handle_page_fault(Access::Execute),
+        Fault::LoadPageFault => handle_page_fault(Access::Read),
+        Fault::StoreOrAmoPageFault => handle_page_fault(Access::Write),
+        Fault::LoadAddressMisaligned | Fault::StoreOrAmoAddressMisaligned => {
+            misaligned::emulate(mut_context, stval)
+        }
+        Fault::IllegalInstruction => {
+            instruction = illegal::decode(mut_context, stval);
+            instruction.map_or(Err(()), |instruction| {
+                illegal::emulate(mut_context, instruction)
+            })
+        }
+        _ => Err(()),
+    };
+    if res.is_ok() {
+        return;
+    }
+
+    // Only a fault that ends the task or the kernel says why the page fault was not resolved.
+    let print_page_fault = || {
+        if let Some((access, error)) = page_fault {
+            supervisor_println!(
+                "Unresolved page fault ({:?}) on {:?} at {:#x}",
+                error,
+                access,
+                stval
+            );
+        }
+    };
+    let report = FaultReport::new(mut_context, stval, *fault, instruction);
+    match mut_context.sstatus.mode_before_exception() {
+        Spp::User => {
+            let (signo, code) = signal_for(*fault, page_fault.map(|(_, error)| error));
+            if signal::raise_fault(signo, code, report) {
+                print_page_fault();
+            }
+        }
+        Spp::Supervisor => {
+            print_page_fault();
+            backtrace::print_backtrace_from(report.sepc, report.registers.x[FP]);
+            panic!("{}", report)
+        }
+    }
+}
+
+/// The signal and `si_code` for a fault from user mode, as Linux raises them.
+fn signal_for(fault: Fault, page_fault_error: Option<PageFaultError>) -> (u32, i32) {
+    match fault {
+        Fault::IllegalInstruction => (SIGILL, ILL_ILLOPC),
+        Fault::InstructionAddressMisaligned
+        | Fault::LoadAddressMisaligned
+        | Fault::StoreOrAmoAddressMisaligned => (SIGBUS, BUS_ADRALN),
+        Fault::InstructionPageFault | Fault::LoadPageFault | Fault::StoreOrAmoPageFault
+            if page_fault_error != Some(PageFaultError::AccessDenied) =>
+        {
+            (SIGSEGV, SEGV_MAPERR)
+        }
+        Fault::InstructionPageFault
+        | Fault::LoadPageFault
+        | Fault::StoreOrAmoPageFault
+        | Fault::InstructionAccessFault
+        | Fault::LoadAccessFault
+        | Fault::StoreOrAmoAccessFault => (SIGSEGV, SEGV_ACCERR),
+    }
+}
+
+/// Resolve the fault from the current task's VMAs so that it resumes at `sepc`.
+fn handle_page_fault(stval: usize, access: Access) -> Result<(), PageFaultError> {
+    let address_space = task::with_current(|task| task.address_space.clone());
+    match address_space {
+        Some(address_space) => address_space.lock().handle_page_fault(stval, access),
+        None => Err(PageFaultError::NotMapped),
+    }
 }
@@ -0,0 +1,76 @@
+use alloc::{vec, vec::Vec};
+use core::arch::asm;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{task, Spp};
+
+use super::{disassemble::Disassembly, instruction::fetch_instruction, ExceptionMutContext};
+
+/// Emulate `instruction` and return `true`, or return `false` to leave it to the next hook.
+///
+/// - The caller steps `sepc` over emulated instructions.
+pub type EmulationHook = fn(&mut ExceptionMutContext, Disassembly) -> bool;
+
+lazy_static! {
+    static ref HOOKS: Mutex<Vec<EmulationHook>> = Mutex::new(vec![emulate_counter_read]);
+}
+
+/// Let the kernel emulate more instructions for user tasks, e.g. ones missing on a `-cpu` model.
+pub fn register_emulation_hook(hook: EmulationHook) {
+    HOOKS.lock().push(hook);
+}
+
+/// The illegal instruction, from `stval` if the hart reports it there and from `sepc` otherwise.
+pub(super) fn decode(mut_context: &ExceptionMutContext, stval: usize) -> Option<Disassembly> {
+    if stval != 0 {
+        return Some(Disassembly::new(stval as u32));
+    }
+    let (instruction, _) = fetch_instruction(mut_context).ok()?;
+    Some(Disassembly::new(instruction))
+}
+
+/// Run the hooks on an illegal instruction from a user task.
+pub(super) fn emulate(
+    mut_context: &mut ExceptionMutContext,
+    instruction: Disassembly,
+) -> Result<(), ()> {
+    if mut_context.sstatus.mode_before_exception() != Spp::User {
+        return Err(());
+    }
+    let hooks = HOOKS.lock().clone();
+    if !hooks.iter().any(|hook| hook(mut_context, instruction)) {
+        return Err(());
+    }
+    mut_context.sepc += instruction.len;
+    task::with_current(|task| task.stats.emulated_instructions += 1);
+    Ok(())
+}
+
+/// Read `cycle`, `time` and `instret` for user tasks when `scounteren` denies them.
+fn emulate_counter_read(mut_context: &mut ExceptionMutContext, instruction: Disassembly) -> bool {
+    let ins = instruction.instruction;
+    if instruction.len != 4 || ins & 0x7f != 0x73 {
+        return false;
+    }
+    let rd = ((ins >> 7) & 0x1f) as usize;
+    let funct3 = (ins >> 12) & 0b111;
+    let rs1 = (ins >> 15) & 0x1f;
+    // Only reads: `csrrs`/`csrrc` with `x0`, or `csrrsi`/`csrrci` with zero.
+    if !matches!(funct3, 2 | 3 | 6 | 7) || rs1 != 0 {
+        return false;
+    }
+
+    let value: usize;
+    unsafe {
+        match ins >> 20 {
+            0xc00 => asm!("csrr {}, cycle", out(reg) value),
+            0xc01 => asm!("csrr {}, time", out(reg) value),
+            0xc02 => asm!("csrr {}, instret", out(reg) value),
+            _ => return false,
+        }
+    }
+    mut_context.register_context.set(rd, value);
+    true
+}
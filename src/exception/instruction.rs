@@ -0,0 +1,79 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::{
+    mm::{
+        address_space::{is_user_range, Access},
+        user_access::with_user_access,
+    },
+    task, Spp,
+};
+
+use super::ExceptionMutContext;
+
+/// Make `addr..addr + len` safe for the kernel to touch on behalf of the trapped mode.
+pub fn prepare(
+    mut_context: &ExceptionMutContext,
+    addr: usize,
+    len: usize,
+    access: Access,
+) -> Result<(), ()> {
+    if mut_context.sstatus.mode_before_exception() == Spp::Supervisor {
+        return Ok(());
+    }
+    // Both come from user space, so the range may wrap or reach into the kernel.
+    let end = addr.checked_add(len).ok_or(())?;
+    let address_space = task::with_current(|task| task.address_space.clone());
+    match address_space {
+        Some(_) if len > 0 && !is_user_range(addr, end) => Err(()),
+        Some(address_space) => address_space
+            .lock()
+            .populate(addr, len, access)
+            .map_err(|_| ()),
+        // Tasks without an address space run on physical memory.
+        None => Ok(()),
+    }
+}
+
+/// Run `f` with the privilege of the trapped mode: `sstatus.SUM` is set for user memory.
+fn with_trapped_privilege<R>(mut_context: &ExceptionMutContext, f: impl FnOnce() -> R) -> R {
+    match mut_context.sstatus.mode_before_exception() {
+        Spp::User => with_user_access(f),
+        Spp::Supervisor => f(),
+    }
+}
+
+/// Read `buf.len()` bytes at `addr` one byte at a time, so misaligned addresses are fine.
+pub fn read_bytes(mut_context: &ExceptionMutContext, addr: usize, buf: &mut [u8]) {
+    with_trapped_privilege(mut_context, || {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { read_volatile((addr + i) as *const u8) };
+        }
+    })
+}
+
+/// Write `data` at `addr` one byte at a time, so misaligned addresses are fine.
+pub fn write_bytes(mut_context: &ExceptionMutContext, addr: usize, data: &[u8]) {
+    with_trapped_privilege(mut_context, || {
+        for (i, byte) in data.iter().enumerate() {
+            unsafe { write_volatile((addr + i) as *mut u8, *byte) };
+        }
+    })
+}
+
+/// The instruction at `sepc` and its length in bytes.
+///
+/// - Compressed instructions are 2 bytes long and are returned zero-extended.
+pub fn fetch_instruction(mut_context: &ExceptionMutContext) -> Result<(u32, usize), ()> {
+    let sepc = mut_context.sepc;
+    let mut half = [0; 2];
+    prepare(mut_context, sepc, 2, Access::Execute)?;
+    read_bytes(mut_context, sepc, &mut half);
+    let low = u16::from_le_bytes(half) as u32;
+    if low & 0b11 != 0b11 {
+        return Ok((low, 2));
+    }
+    prepare(mut_context, sepc + 2, 2, Access::Execute)?;
+    read_bytes(mut_context, sepc + 2, &mut half);
+    let high = u16::from_le_bytes(half) as u32;
+    Ok((low | high << 16, 4))
+}
@@ -1,28 +1,26 @@
-use core::arch::asm;
-
-use crate::{exception::Interrupt, sbi_call, supervisor_print, supervisor_println};
+use crate::{drivers, exception::Interrupt, panicking, task, timer};
 
 use super::ExceptionMutContext;
 
-const DELTA: u64 = 10000000;
+const SSIP: usize = 1 << 1;
 
 pub fn handle_interrupt(
-    _mut_context: &mut ExceptionMutContext,
+    mut_context: &mut ExceptionMutContext,
     stval: usize,
     interrupt: &Interrupt,
 ) {
     match interrupt {
-        Interrupt::SupervisorSoftware => supervisor_println!("Supervisor software interrupt"),
-        Interrupt::SupervisorTimer => {
-            supervisor_print!(".");
-
-            let time: u64;
-            unsafe {
-                asm!("csrr {}, time", out(reg) time);
+        Interrupt::SupervisorSoftware => {
+            // Another hart is panicking and stops the world.
+            if panicking::is_panicking() {
+                panicking::park();
             }
-            sbi_call::set_timer(time + DELTA).expect("Failed to set timer");
+            // A kernel thread yields.
+            mut_context.sip &= !SSIP;
+            task::request_reschedule();
         }
-        Interrupt::SupervisorExternal => supervisor_println!("Supervisor external interrupt"),
+        Interrupt::SupervisorTimer => timer::handle_interrupt(),
+        Interrupt::SupervisorExternal => drivers::handle_external_interrupt(),
         _ => panic!("Interrupt: {:?}, stval: {}", interrupt, stval),
     }
 }
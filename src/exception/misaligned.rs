@@ -0,0 +1,119 @@
+use crate::{mm::address_space::Access, task};
+
+use super::{
+    instruction::{fetch_instruction, prepare, read_bytes, write_bytes},
+    ExceptionMutContext,
+};
+
+#[derive(Debug, Clone, Copy)]
+enum MemoryOp {
+    Load {
+        rd: usize,
+        width: usize,
+        is_signed: bool,
+    },
+    Store {
+        rs2: usize,
+        width: usize,
+    },
+}
+
+/// Decode the integer loads and stores of RV64I and RV64C.
+///
+/// - Floating-point accesses are not emulated since `RegisterContext` has no `f` registers.
+/// - AMOs cannot be split into bytes without losing atomicity and are not emulated either.
+fn decode(instruction: u32, len: usize) -> Option<MemoryOp> {
+    if len == 4 {
+        let opcode = instruction & 0x7f;
+        let funct3 = (instruction >> 12) & 0b111;
+        let rd = ((instruction >> 7) & 0x1f) as usize;
+        let rs2 = ((instruction >> 20) & 0x1f) as usize;
+        return match (opcode, funct3) {
+            // LB, LH, LW, LD
+            (0x03, 0..=3) => Some(MemoryOp::Load {
+                rd,
+                width: 1 << funct3,
+                is_signed: true,
+            }),
+            // LBU, LHU, LWU
+            (0x03, 4..=6) => Some(MemoryOp::Load {
+                rd,
+                width: 1 << (funct3 - 4),
+                is_signed: false,
+            }),
+            // SB, SH, SW, SD
+            (0x23, 0..=3) => Some(MemoryOp::Store {
+                rs2,
+                width: 1 << funct3,
+            }),
+            _ => None,
+        };
+    }
+
+    let quadrant = instruction & 0b11;
+    let funct3 = (instruction >> 13) & 0b111;
+    // `rd'`/`rs2'` of the CL and CS formats name `x8` to `x15`.
+    let rd_prime = ((instruction >> 2) & 0b111) as usize + 8;
+    let rd = ((instruction >> 7) & 0x1f) as usize;
+    let rs2 = ((instruction >> 2) & 0x1f) as usize;
+    match (quadrant, funct3) {
+        // C.LW, C.LD
+        (0b00, 0b010) | (0b00, 0b011) => Some(MemoryOp::Load {
+            rd: rd_prime,
+            width: if funct3 == 0b010 { 4 } else { 8 },
+            is_signed: true,
+        }),
+        // C.SW, C.SD
+        (0b00, 0b110) | (0b00, 0b111) => Some(MemoryOp::Store {
+            rs2: rd_prime,
+            width: if funct3 == 0b110 { 4 } else { 8 },
+        }),
+        // C.LWSP, C.LDSP
+        (0b10, 0b010) | (0b10, 0b011) if rd != 0 => Some(MemoryOp::Load {
+            rd,
+            width: if funct3 == 0b010 { 4 } else { 8 },
+            is_signed: true,
+        }),
+        // C.SWSP, C.SDSP
+        (0b10, 0b110) | (0b10, 0b111) => Some(MemoryOp::Store {
+            rs2,
+            width: if funct3 == 0b110 { 4 } else { 8 },
+        }),
+        _ => None,
+    }
+}
+
+/// Perform the misaligned load or store at `sepc` byte by byte and step over it.
+///
+/// - `stval` holds the misaligned address.
+pub fn emulate(mut_context: &mut ExceptionMutContext, stval: usize) -> Result<(), ()> {
+    let (instruction, len) = fetch_instruction(mut_context)?;
+    let op = decode(instruction, len).ok_or(())?;
+
+    let mut bytes = [0; 8];
+    match op {
+        MemoryOp::Load {
+            rd,
+            width,
+            is_signed,
+        } => {
+            prepare(mut_context, stval, width, Access::Read)?;
+            read_bytes(mut_context, stval, &mut bytes[..width]);
+            let mut value = usize::from_le_bytes(bytes);
+            if is_signed && width < 8 {
+                let shift = usize::BITS as usize - 8 * width;
+                value = ((value << shift) as isize >> shift) as usize;
+            }
+            mut_context.register_context.set(rd, value);
+        }
+        MemoryOp::Store { rs2, width } => {
+            prepare(mut_context, stval, width, Access::Write)?;
+            bytes = mut_context.register_context.get(rs2).to_le_bytes();
+            write_bytes(mut_context, stval, &bytes[..width]);
+        }
+    }
+
+    mut_context.sepc += len;
+    task::with_current(|task| task.stats.misaligned_emulations += 1);
+    Ok(())
+}
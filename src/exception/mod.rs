@@ -1,13 +1,20 @@
-use core::arch::{asm, global_asm};
+use core::{
+    arch::{asm, global_asm},
+    fmt,
+};
 
 mod abi_call;
-mod fault;
+pub mod disassemble;
+pub mod fault;
+pub mod illegal;
+pub(crate) mod instruction;
 mod interrupt;
+mod misaligned;
 mod trap;
 
 use crate::{
     exception::{fault::handle_fault, interrupt::handle_interrupt, trap::handle_trap},
-    Sstatus,
+    task, Sstatus,
 };
 
 pub fn setup_supervisor_exception_handler() {
@@ -33,6 +40,35 @@ pub fn enable_supervisor_interrupt(interrupt: Interrupt) {
     }
 }
 
+/// Run `f` with `sstatus.SIE` cleared, restoring it afterwards.
+///
+/// - Kernel threads take interrupts, so they must not hold a lock that an interrupt handler
+///   takes without this.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrrci {}, sstatus, 1 << 1", out(reg) sstatus);
+    }
+    let result = f();
+    if Sstatus(sstatus).is_interrupt_enabled() {
+        unsafe {
+            asm!("csrsi sstatus, 1 << 1");
+        }
+    }
+    result
+}
+
+/// `sstatus.SIE`: whether the hart takes interrupts right now.
+///
+/// - Off in trap handlers and inside [`without_interrupts`].
+pub fn are_interrupts_enabled() -> bool {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrr {}, sstatus", out(reg) sstatus);
+    }
+    Sstatus(sstatus).is_interrupt_enabled()
+}
+
 global_asm!(include_str!("entry.asm"));
 
 #[no_mangle]
@@ -52,6 +88,13 @@ pub extern "C" fn handle_exception(register_context: &mut RegisterContext) {
         }
         _ => panic!("Unhandled exception: {:?}", immut_context.scause),
     }
+
+    task::schedule(&mut mut_context);
+    // Signals reach a task as it returns to user mode; a signal that stops or ends it switches
+    // to the next one.
+    while task::signal::deliver(&mut mut_context) {
+        task::schedule(&mut mut_context);
+    }
 }
 
 #[derive(Debug)]
@@ -151,7 +194,7 @@ pub enum Trap {
     EnvironmentCallFromSMode,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Fault {
     InstructionAddressMisaligned,
     InstructionAccessFault,
@@ -176,11 +219,49 @@ impl From<Cause> for Exception {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RegisterContext {
     pub x: [usize; 32],
 }
 
+impl RegisterContext {
+    /// - `x0` always reads as zero.
+    pub fn get(&self, index: usize) -> usize {
+        match index {
+            0 => 0,
+            _ => self.x[index],
+        }
+    }
+
+    /// - Writes to `x0` are discarded.
+    pub fn set(&mut self, index: usize, value: usize) {
+        if index != 0 {
+            self.x[index] = value;
+        }
+    }
+}
+
+/// ABI names of `x0` to `x31`.
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl fmt::Display for RegisterContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in REGISTER_NAMES.iter().zip(self.x.iter()).enumerate() {
+            write!(f, "{:>4}: {:#018x}", name, value)?;
+            if i % 4 == 3 {
+                writeln!(f)?;
+            } else {
+                write!(f, "  ")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ExceptionMutContext<'entry> {
     pub register_context: &'entry mut RegisterContext,
@@ -1,19 +1,22 @@
-use crate::{supervisor_print, supervisor_println};
+use crate::{debug, gdb, monitor, supervisor_print, supervisor_println};
 
 use super::{abi_call, ExceptionMutContext, Trap};
 
 pub fn handle_trap(mut_context: &mut ExceptionMutContext, stval: usize, trap: &Trap) {
     match trap {
         Trap::Breakpoint => {
+            let is_step = debug::remove_step_breakpoints(mut_context.sepc);
+            if gdb::handle_breakpoint(mut_context)
+                || monitor::handle_breakpoint(mut_context, is_step)
+            {
+                return;
+            }
             supervisor_println!("Breakpoint");
 
             // `ebreak` is just two-bytes long.
             mut_context.sepc += 2;
         }
-        Trap::EnvironmentCallFromUMode => {
-            abi_call::abi_call(mut_context);
-            mut_context.sepc += 4;
-        }
+        Trap::EnvironmentCallFromUMode => abi_call::abi_call(mut_context),
         _ => panic!("Trap: {:?}, stval: {}", trap, stval),
     }
 }
@@ -0,0 +1,44 @@
+use crate::console;
+
+use super::{FileType, FsError, FsResult, Inode, Metadata};
+
+/// `/dev/console`-style access to the kernel console.
+pub struct ConsoleInode;
+
+/// Major 5, minor 1, as Linux numbers the console.
+const CONSOLE_RDEV: u64 = 5 << 8 | 1;
+
+impl Inode for ConsoleInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let mut metadata = Metadata::new(0, FileType::CharDevice, 0o620);
+        metadata.rdev = CONSOLE_RDEV;
+        Ok(metadata)
+    }
+
+    /// Whatever input is buffered, at least one byte.
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match console::try_read_byte() {
+                Some(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        match (len, buf.is_empty()) {
+            (0, false) => Err(FsError::WouldBlock),
+            _ => Ok(len),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> FsResult<usize> {
+        console::write_bytes(data);
+        Ok(data.len())
+    }
+
+    fn block_until_readable(&self) -> bool {
+        console::block_for_input()
+    }
+}
@@ -0,0 +1,126 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use spin::Mutex;
+
+use super::{FileType, FsError, FsResult, Inode, NAME_MAX};
+
+/// A name bound to an inode, linked to its parent so that `..` and the path can be found.
+///
+/// - Parents are held strongly and children weakly, so a dentry lives as long as something
+///   refers to it or to a descendant: an open file, a mount or a task's working directory.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    /// `None` for the root; for a mount's root, the parent of the directory it covers.
+    parent: Option<Arc<Dentry>>,
+    children: Mutex<BTreeMap<String, Weak<Dentry>>>,
+    /// The root of the file system mounted over this directory.
+    mounted: Mutex<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    pub fn new(name: &str, inode: Arc<dyn Inode>, parent: Option<Arc<Dentry>>) -> Arc<Dentry> {
+        Arc::new(Dentry {
+            name: name.to_string(),
+            inode,
+            parent,
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+
+    pub fn file_type(&self) -> FsResult<FileType> {
+        Ok(self.inode.metadata()?.file_type)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == Ok(FileType::Directory)
+    }
+
+    /// The absolute path, like `/usr/bin`.
+    pub fn path(&self) -> String {
+        let mut names = Vec::new();
+        let mut dentry = self;
+        while let Some(parent) = &dentry.parent {
+            names.push(dentry.name.as_str());
+            dentry = parent;
+        }
+        if names.is_empty() {
+            return String::from("/");
+        }
+        names.iter().rev().fold(String::new(), |mut path, name| {
+            path.push('/');
+            path.push_str(name);
+            path
+        })
+    }
+
+    /// The dentry of `name` in this directory, stepping onto whatever is mounted over it.
+    ///
+    /// - `.` and `..` are the caller's to handle.
+    pub fn lookup(self: &Arc<Self>, name: &str) -> FsResult<Arc<Dentry>> {
+        Ok(self.lookup_covered(name)?.covering_mount())
+    }
+
+    /// The dentry of `name` in this directory, even if a file system is mounted over it.
+    pub fn lookup_covered(self: &Arc<Self>, name: &str) -> FsResult<Arc<Dentry>> {
+        if name.len() > NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
+        let cached = self.children.lock().get(name).and_then(Weak::upgrade);
+        let child = match cached {
+            Some(child) => child,
+            None => {
+                let inode = self.inode.lookup(name)?;
+                let child = Dentry::new(name, inode, Some(self.clone()));
+                self.children
+                    .lock()
+                    .insert(name.to_string(), Arc::downgrade(&child));
+                child
+            }
+        };
+        Ok(child)
+    }
+
+    /// The root of the innermost file system mounted here, or this dentry.
+    pub fn covering_mount(self: Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self;
+        loop {
+            let mounted = dentry.mounted.lock().clone();
+            match mounted {
+                Some(root) => dentry = root,
+                None => return dentry,
+            }
+        }
+    }
+
+    pub fn is_mount_point(&self) -> bool {
+        self.mounted.lock().is_some()
+    }
+
+    pub(super) fn set_mounted(&self, root: Option<Arc<Dentry>>) {
+        *self.mounted.lock() = root;
+    }
+
+    /// Drop the cached dentry of `name` after it was removed or replaced.
+    pub fn forget(&self, name: &str) {
+        self.children.lock().remove(name);
+    }
+}
@@ -0,0 +1,151 @@
+use alloc::vec::Vec;
+
+use crate::fs::{FileType, FsError, FsResult};
+
+/// `inode`, `rec_len`, `name_len` and `file_type`, before the name.
+pub const HEADER_SIZE: usize = 8;
+
+/// `file_type` codes, with the `filetype` feature.
+const FT_UNKNOWN: u8 = 0;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_FIFO: u8 = 5;
+const FT_SOCK: u8 = 6;
+const FT_SYMLINK: u8 = 7;
+
+pub fn file_type_code(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::Regular => FT_REG_FILE,
+        FileType::Directory => FT_DIR,
+        FileType::CharDevice => FT_CHRDEV,
+        FileType::BlockDevice => FT_BLKDEV,
+        FileType::Fifo => FT_FIFO,
+        FileType::Socket => FT_SOCK,
+        FileType::Symlink => FT_SYMLINK,
+    }
+}
+
+/// `None` for [`FT_UNKNOWN`] and codes from the future; the inode knows then.
+pub fn file_type_from_code(code: u8) -> Option<FileType> {
+    match code {
+        FT_REG_FILE => Some(FileType::Regular),
+        FT_DIR => Some(FileType::Directory),
+        FT_CHRDEV => Some(FileType::CharDevice),
+        FT_BLKDEV => Some(FileType::BlockDevice),
+        FT_FIFO => Some(FileType::Fifo),
+        FT_SOCK => Some(FileType::Socket),
+        FT_SYMLINK => Some(FileType::Symlink),
+        _ => None,
+    }
+}
+
+/// The bytes an entry with a name of `name_len` bytes needs.
+pub fn entry_size(name_len: usize) -> usize {
+    (HEADER_SIZE + name_len).next_multiple_of(4)
+}
+
+/// An entry of a directory block, which may be unused.
+#[derive(Debug, Clone, Copy)]
+pub struct RawEntry {
+    /// Where the entry starts in its block.
+    pub offset: usize,
+    /// 0 for an unused entry.
+    pub ino: u32,
+    /// From this entry to the next, or to the end of the block.
+    pub rec_len: usize,
+    pub name_len: usize,
+    pub file_type: u8,
+}
+
+impl RawEntry {
+    pub fn name<'block>(&self, block: &'block [u8]) -> &'block [u8] {
+        let start = self.offset + HEADER_SIZE;
+        &block[start..start + self.name_len]
+    }
+
+    /// `.` or `..`, which the VFS answers itself.
+    pub fn is_dot(&self, block: &[u8]) -> bool {
+        matches!(self.name(block), b"." | b"..")
+    }
+
+    /// The bytes the entry itself takes; the rest of `rec_len` is free.
+    pub fn used_size(&self) -> usize {
+        match self.ino {
+            0 => 0,
+            _ => entry_size(self.name_len),
+        }
+    }
+}
+
+/// The entries of a directory block, which must tile it.
+///
+/// - Without the `filetype` feature, the name length is 16 bits and there is no type.
+pub fn parse_block(block: &[u8], has_file_type: bool) -> FsResult<Vec<RawEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < block.len() {
+        let header = block
+            .get(offset..offset + HEADER_SIZE)
+            .ok_or(FsError::Corrupted)?;
+        let ino = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let rec_len = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let (name_len, file_type) = match has_file_type {
+            true => (header[6] as usize, header[7]),
+            false => (
+                u16::from_le_bytes([header[6], header[7]]) as usize,
+                FT_UNKNOWN,
+            ),
+        };
+        if rec_len < HEADER_SIZE
+            || !rec_len.is_multiple_of(4)
+            || offset + rec_len > block.len()
+            || HEADER_SIZE + name_len > rec_len
+        {
+            return Err(FsError::Corrupted);
+        }
+        entries.push(RawEntry {
+            offset,
+            ino,
+            rec_len,
+            name_len,
+            file_type,
+        });
+        offset += rec_len;
+    }
+    Ok(entries)
+}
+
+/// Write an entry at `offset` of `block`.
+pub fn encode(
+    block: &mut [u8],
+    offset: usize,
+    ino: u32,
+    rec_len: usize,
+    name: &[u8],
+    file_type: u8,
+    has_file_type: bool,
+) {
+    let raw = &mut block[offset..offset + HEADER_SIZE + name.len()];
+    raw[..4].copy_from_slice(&ino.to_le_bytes());
+    raw[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    match has_file_type {
+        true => {
+            raw[6] = name.len() as u8;
+            raw[7] = file_type;
+        }
+        false => raw[6..8].copy_from_slice(&(name.len() as u16).to_le_bytes()),
+    }
+    raw[HEADER_SIZE..].copy_from_slice(name);
+}
+
+/// Set `rec_len` of the entry at `offset`.
+pub fn set_rec_len(block: &mut [u8], offset: usize, rec_len: usize) {
+    block[offset + 4..offset + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+}
+
+/// Mark the entry at `offset` unused.
+pub fn clear_ino(block: &mut [u8], offset: usize) {
+    block[offset..offset + 4].fill(0);
+}
@@ -0,0 +1,927 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{mem, time::Duration};
+
+use spin::Mutex;
+
+use crate::{
+    clock,
+    drivers::block::cache,
+    fs::{DirEntry, FileType, FsError, FsResult, Inode, Metadata, NAME_MAX},
+};
+
+use super::{
+    dir::{self, RawEntry},
+    Volume, GOOD_OLD_INODE_SIZE,
+};
+
+/// The type bits of `i_mode`.
+const S_IFMT: u16 = 0o170000;
+const S_IFSOCK: u16 = 0o140000;
+const S_IFLNK: u16 = 0o120000;
+const S_IFREG: u16 = 0o100000;
+const S_IFBLK: u16 = 0o060000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFCHR: u16 = 0o020000;
+const S_IFIFO: u16 = 0o010000;
+
+/// Direct block pointers in `i_block`; the three after them are single, double and triple
+/// indirect.
+const DIRECT_BLOCKS: usize = 12;
+const N_BLOCKS: usize = 15;
+/// A symbolic link target shorter than this is kept in `i_block` itself.
+const FAST_SYMLINK_MAX: usize = N_BLOCKS * 4;
+/// A directory indexed by an HTree, which a linear change leaves stale.
+const INDEX_FL: u32 = 0x1000;
+/// The most hard links an inode may have, which caps subdirectories.
+const LINK_MAX: u16 = 32000;
+/// `h_magic` and `h_refcount` of an extended attribute block.
+const XATTR_MAGIC: u32 = 0xea02_0000;
+const XATTR_REFCOUNT_OFFSET: u64 = 4;
+
+/// The inode fields this driver uses, over the raw bytes that keep the rest.
+#[derive(Debug, Clone)]
+pub(super) struct DiskInode {
+    raw: [u8; GOOD_OLD_INODE_SIZE],
+    mode: u16,
+    uid: u32,
+    size: u64,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    dtime: u32,
+    gid: u32,
+    links: u16,
+    /// Allocated 512-byte units, indirect and extended attribute blocks included.
+    blocks: u32,
+    flags: u32,
+    block: [u32; N_BLOCKS],
+    file_acl: u32,
+}
+
+impl DiskInode {
+    pub(super) fn parse(raw: [u8; GOOD_OLD_INODE_SIZE]) -> DiskInode {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        let mode = u16_at(0);
+        // Only regular files keep the high half of the size there.
+        let size_high = match mode & S_IFMT {
+            S_IFREG => u32_at(108),
+            _ => 0,
+        };
+        DiskInode {
+            mode,
+            uid: u16_at(2) as u32 | (u16_at(120) as u32) << 16,
+            size: u32_at(4) as u64 | (size_high as u64) << 32,
+            atime: u32_at(8),
+            ctime: u32_at(12),
+            mtime: u32_at(16),
+            dtime: u32_at(20),
+            gid: u16_at(24) as u32 | (u16_at(122) as u32) << 16,
+            links: u16_at(26),
+            blocks: u32_at(28),
+            flags: u32_at(32),
+            block: core::array::from_fn(|i| u32_at(40 + i * 4)),
+            file_acl: u32_at(104),
+            raw,
+        }
+    }
+
+    fn new(mode: u16, now: u32) -> DiskInode {
+        let mut inode = DiskInode::parse([0; GOOD_OLD_INODE_SIZE]);
+        inode.mode = mode;
+        inode.links = 1;
+        inode.atime = now;
+        inode.ctime = now;
+        inode.mtime = now;
+        inode
+    }
+
+    pub(super) fn encode(&self) -> [u8; GOOD_OLD_INODE_SIZE] {
+        let mut raw = self.raw;
+        let mut put = |offset: usize, bytes: &[u8]| {
+            raw[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, &self.mode.to_le_bytes());
+        put(2, &(self.uid as u16).to_le_bytes());
+        put(120, &((self.uid >> 16) as u16).to_le_bytes());
+        put(4, &(self.size as u32).to_le_bytes());
+        if self.mode & S_IFMT == S_IFREG {
+            put(108, &((self.size >> 32) as u32).to_le_bytes());
+        }
+        put(8, &self.atime.to_le_bytes());
+        put(12, &self.ctime.to_le_bytes());
+        put(16, &self.mtime.to_le_bytes());
+        put(20, &self.dtime.to_le_bytes());
+        put(24, &(self.gid as u16).to_le_bytes());
+        put(122, &((self.gid >> 16) as u16).to_le_bytes());
+        put(26, &self.links.to_le_bytes());
+        put(28, &self.blocks.to_le_bytes());
+        put(32, &self.flags.to_le_bytes());
+        for (i, block) in self.block.iter().enumerate() {
+            put(40 + i * 4, &block.to_le_bytes());
+        }
+        put(104, &self.file_acl.to_le_bytes());
+        raw
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    fn file_type(&self) -> FsResult<FileType> {
+        match self.mode & S_IFMT {
+            S_IFREG => Ok(FileType::Regular),
+            S_IFDIR => Ok(FileType::Directory),
+            S_IFLNK => Ok(FileType::Symlink),
+            S_IFCHR => Ok(FileType::CharDevice),
+            S_IFBLK => Ok(FileType::BlockDevice),
+            S_IFIFO => Ok(FileType::Fifo),
+            S_IFSOCK => Ok(FileType::Socket),
+            _ => Err(FsError::Corrupted),
+        }
+    }
+
+    /// `i_block` as bytes, which a fast symbolic link keeps its target in.
+    fn inline_data(&self) -> [u8; FAST_SYMLINK_MAX] {
+        let mut raw = [0; FAST_SYMLINK_MAX];
+        for (chunk, block) in raw.chunks_mut(4).zip(&self.block) {
+            chunk.copy_from_slice(&block.to_le_bytes());
+        }
+        raw
+    }
+
+    fn set_inline_data(&mut self, data: &[u8]) {
+        let mut raw = [0; FAST_SYMLINK_MAX];
+        raw[..data.len()].copy_from_slice(data);
+        for (block, chunk) in self.block.iter_mut().zip(raw.chunks(4)) {
+            *block = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+    }
+}
+
+fn now() -> u32 {
+    clock::realtime().as_secs() as u32
+}
+
+fn validate_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(FsError::InvalidInput);
+    }
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+struct State {
+    disk: DiskInode,
+    /// Has no links left while still in use; its blocks and number go when it does.
+    is_unlinked: bool,
+}
+
+/// A file, directory or symbolic link of an [`super::Ext2`] volume.
+pub struct Ext2Inode {
+    volume: Arc<Volume>,
+    ino: u32,
+    state: Mutex<State>,
+}
+
+impl Ext2Inode {
+    pub(super) fn new(volume: Arc<Volume>, ino: u32, disk: DiskInode) -> Ext2Inode {
+        Ext2Inode {
+            volume,
+            ino,
+            state: Mutex::new(State {
+                disk,
+                is_unlinked: false,
+            }),
+        }
+    }
+
+    fn block_size(&self) -> u64 {
+        self.volume.block_size() as u64
+    }
+
+    fn pointers_per_block(&self) -> u64 {
+        self.block_size() / 4
+    }
+
+    /// What a block adds to `i_blocks`.
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size() / 512) as u32
+    }
+
+    fn store(&self, state: &State) -> FsResult<()> {
+        self.volume.write_inode(self.ino, &state.disk)
+    }
+
+    /// Whether the target of a symbolic link lies in `i_block` rather than in a block.
+    fn is_fast_symlink(&self, disk: &DiskInode) -> bool {
+        let xattr_blocks = match disk.file_acl {
+            0 => 0,
+            _ => self.sectors_per_block(),
+        };
+        disk.mode & S_IFMT == S_IFLNK && disk.blocks.saturating_sub(xattr_blocks) == 0
+    }
+
+    /// The slot in `i_block`, then the index into each indirect block, on the way to data block
+    /// `index`.
+    fn block_path(&self, index: u64) -> FsResult<Vec<usize>> {
+        let ppb = self.pointers_per_block();
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok(vec![index as usize]);
+        }
+        let mut index = index - DIRECT_BLOCKS as u64;
+        // Data blocks under the slot.
+        let mut span = ppb;
+        for depth in 1..=3 {
+            if index < span {
+                let mut path = vec![DIRECT_BLOCKS + depth - 1];
+                let mut under = span;
+                for _ in 0..depth {
+                    under /= ppb;
+                    path.push((index / under % ppb) as usize);
+                }
+                return Ok(path);
+            }
+            index -= span;
+            span *= ppb;
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn read_pointer(&self, block: u32, index: usize) -> FsResult<u32> {
+        self.volume.check_block(block)?;
+        let mut raw = [0; 4];
+        self.volume
+            .read(self.volume.block_offset(block) + index as u64 * 4, &mut raw)?;
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn write_pointer(&self, block: u32, index: usize, pointer: u32) -> FsResult<()> {
+        self.volume.check_block(block)?;
+        self.volume.write(
+            self.volume.block_offset(block) + index as u64 * 4,
+            &pointer.to_le_bytes(),
+        )
+    }
+
+    /// The block that holds data block `index`, or 0 for a hole.
+    fn map(&self, disk: &DiskInode, index: u64) -> FsResult<u32> {
+        let path = self.block_path(index)?;
+        let mut block = disk.block[path[0]];
+        for &i in &path[1..] {
+            if block == 0 {
+                return Ok(0);
+            }
+            block = self.read_pointer(block, i)?;
+        }
+        Ok(block)
+    }
+
+    fn allocate(&self, disk: &mut DiskInode, goal: u32) -> FsResult<u32> {
+        let block = self.volume.allocate_block(goal)?;
+        disk.blocks += self.sectors_per_block();
+        Ok(block)
+    }
+
+    fn free(&self, disk: &mut DiskInode, block: u32) -> FsResult<()> {
+        self.volume.free_block(block)?;
+        disk.blocks = disk.blocks.saturating_sub(self.sectors_per_block());
+        Ok(())
+    }
+
+    /// The block that holds data block `index`, allocating it and the indirect blocks on the way.
+    fn map_or_allocate(&self, disk: &mut DiskInode, index: u64) -> FsResult<u32> {
+        let path = self.block_path(index)?;
+        // Right after the previous data block, or else in the inode's group.
+        let goal = match index {
+            0 => 0,
+            _ => self.map(disk, index - 1)?,
+        };
+        let goal = match goal {
+            0 => self.volume.inode_goal(self.ino),
+            _ => goal + 1,
+        };
+        let mut block = disk.block[path[0]];
+        if block == 0 {
+            block = self.allocate(disk, goal)?;
+            disk.block[path[0]] = block;
+        }
+        for &i in &path[1..] {
+            let parent = block;
+            block = self.read_pointer(parent, i)?;
+            if block == 0 {
+                block = self.allocate(disk, goal)?;
+                self.write_pointer(parent, i, block)?;
+            }
+        }
+        Ok(block)
+    }
+
+    /// Free data block `count` and the ones after it, and the indirect blocks left empty.
+    fn free_from(&self, disk: &mut DiskInode, count: u64) -> FsResult<()> {
+        for slot in count.min(DIRECT_BLOCKS as u64) as usize..DIRECT_BLOCKS {
+            let block = mem::take(&mut disk.block[slot]);
+            if block != 0 {
+                self.free(disk, block)?;
+            }
+        }
+        let ppb = self.pointers_per_block();
+        let mut start = DIRECT_BLOCKS as u64;
+        let mut span = ppb;
+        for depth in 1..=3 {
+            let slot = DIRECT_BLOCKS + depth as usize - 1;
+            let from = count.saturating_sub(start);
+            let block = disk.block[slot];
+            if from < span && block != 0 && self.free_branch(disk, block, depth, from)? {
+                disk.block[slot] = 0;
+            }
+            start += span;
+            span *= ppb;
+        }
+        Ok(())
+    }
+
+    /// Free data block `from` and the ones after it under the indirect block `block`, `depth`
+    /// levels above them; whether `block` itself went.
+    fn free_branch(
+        &self,
+        disk: &mut DiskInode,
+        block: u32,
+        depth: u32,
+        from: u64,
+    ) -> FsResult<bool> {
+        let ppb = self.pointers_per_block();
+        let span = ppb.pow(depth - 1);
+        let mut raw = vec![0; self.block_size() as usize];
+        self.volume.read_block(block, &mut raw)?;
+        let first = from / span;
+        let mut is_changed = false;
+        for i in first..ppb {
+            let pointer = &mut raw[i as usize * 4..i as usize * 4 + 4];
+            let child = u32::from_le_bytes(pointer.try_into().unwrap());
+            if child == 0 {
+                continue;
+            }
+            let child_from = match i == first {
+                true => from - first * span,
+                false => 0,
+            };
+            let is_freed = match depth {
+                1 => {
+                    self.free(disk, child)?;
+                    true
+                }
+                _ => self.free_branch(disk, child, depth - 1, child_from)?,
+            };
+            if is_freed {
+                pointer.fill(0);
+                is_changed = true;
+            }
+        }
+        if from == 0 {
+            self.free(disk, block)?;
+            return Ok(true);
+        }
+        if is_changed {
+            self.volume.write_block(block, &raw)?;
+        }
+        Ok(false)
+    }
+
+    fn read_data(&self, disk: &DiskInode, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        let block_size = self.block_size();
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let start = position % block_size;
+            let len = ((block_size - start) as usize).min(buf.len() - done);
+            let dest = &mut buf[done..done + len];
+            match self.map(disk, position / block_size)? {
+                0 => dest.fill(0),
+                block => {
+                    self.volume.check_block(block)?;
+                    self.volume
+                        .read(self.volume.block_offset(block) + start, dest)?;
+                }
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write `data` at `offset`, filling holes on the way and growing the file.
+    fn write_data(&self, disk: &mut DiskInode, offset: u64, data: &[u8]) -> FsResult<()> {
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or(FsError::InvalidInput)?;
+        if end > i32::MAX as u64 && !self.volume.superblock.has_large_file {
+            return Err(FsError::NoSpace);
+        }
+        let block_size = self.block_size();
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let start = position % block_size;
+            let len = ((block_size - start) as usize).min(data.len() - done);
+            let block = self.map_or_allocate(disk, position / block_size)?;
+            self.volume.check_block(block)?;
+            self.volume.write(
+                self.volume.block_offset(block) + start,
+                &data[done..done + len],
+            )?;
+            done += len;
+            disk.size = disk.size.max(position + len as u64);
+        }
+        Ok(())
+    }
+
+    /// Cut the file to `size` bytes, or grow it with a hole.
+    fn resize(&self, disk: &mut DiskInode, size: u64) -> FsResult<()> {
+        if size > i32::MAX as u64 && !self.volume.superblock.has_large_file {
+            return Err(FsError::NoSpace);
+        }
+        let block_size = self.block_size();
+        if size < disk.size {
+            self.free_from(disk, size.div_ceil(block_size))?;
+            // Growing again must read zeros past the cut.
+            let tail = size % block_size;
+            let block = self.map(disk, size / block_size)?;
+            if tail != 0 && block != 0 {
+                self.volume.check_block(block)?;
+                self.volume.write(
+                    self.volume.block_offset(block) + tail,
+                    &vec![0; (block_size - tail) as usize],
+                )?;
+            }
+        }
+        disk.size = size;
+        Ok(())
+    }
+
+    /// Read directory block `index` into `buf`, and its entries.
+    fn read_dir_block(
+        &self,
+        disk: &DiskInode,
+        index: u64,
+        buf: &mut [u8],
+    ) -> FsResult<(u32, Vec<RawEntry>)> {
+        let block = self.map(disk, index)?;
+        // A directory has no holes.
+        if block == 0 {
+            return Err(FsError::Corrupted);
+        }
+        self.volume.read_block(block, buf)?;
+        let entries = dir::parse_block(buf, self.volume.superblock.has_file_type)?;
+        Ok((block, entries))
+    }
+
+    fn dir_blocks(&self, disk: &DiskInode) -> u64 {
+        disk.size / self.block_size()
+    }
+
+    /// The entry `name`, which is neither `.` nor `..`.
+    fn find(&self, disk: &DiskInode, name: &str) -> FsResult<RawEntry> {
+        if !disk.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        let mut buf = vec![0; self.block_size() as usize];
+        for index in 0..self.dir_blocks(disk) {
+            let (_, entries) = self.read_dir_block(disk, index, &mut buf)?;
+            let found = entries.into_iter().find(|entry| {
+                entry.ino != 0 && !entry.is_dot(&buf) && entry.name(&buf) == name.as_bytes()
+            });
+            if let Some(entry) = found {
+                return Ok(entry);
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    /// Whether the directory holds nothing but `.` and `..`.
+    fn is_empty(&self, disk: &DiskInode) -> FsResult<bool> {
+        let mut buf = vec![0; self.block_size() as usize];
+        for index in 0..self.dir_blocks(disk) {
+            let (_, entries) = self.read_dir_block(disk, index, &mut buf)?;
+            if entries
+                .iter()
+                .any(|entry| entry.ino != 0 && !entry.is_dot(&buf))
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Link `ino` into the directory as `name`, in the first gap that fits or a new block.
+    fn add_entry(&self, disk: &mut DiskInode, name: &str, ino: u32, file_type: u8) -> FsResult<()> {
+        let has_file_type = self.volume.superblock.has_file_type;
+        let needed = dir::entry_size(name.len());
+        let block_size = self.block_size() as usize;
+        disk.flags &= !INDEX_FL;
+        let mut buf = vec![0; block_size];
+        for index in 0..self.dir_blocks(disk) {
+            let (block, entries) = self.read_dir_block(disk, index, &mut buf)?;
+            let gap = entries
+                .iter()
+                .find(|entry| entry.rec_len - entry.used_size() >= needed);
+            let Some(entry) = gap else {
+                continue;
+            };
+            let (offset, rec_len) = match entry.ino {
+                0 => (entry.offset, entry.rec_len),
+                _ => {
+                    let used = entry.used_size();
+                    dir::set_rec_len(&mut buf, entry.offset, used);
+                    (entry.offset + used, entry.rec_len - used)
+                }
+            };
+            dir::encode(
+                &mut buf,
+                offset,
+                ino,
+                rec_len,
+                name.as_bytes(),
+                file_type,
+                has_file_type,
+            );
+            return self.volume.write_block(block, &buf);
+        }
+
+        let index = self.dir_blocks(disk);
+        let block = self.map_or_allocate(disk, index)?;
+        buf.fill(0);
+        dir::encode(
+            &mut buf,
+            0,
+            ino,
+            block_size,
+            name.as_bytes(),
+            file_type,
+            has_file_type,
+        );
+        self.volume.write_block(block, &buf)?;
+        disk.size += block_size as u64;
+        Ok(())
+    }
+
+    /// Unlink the entry `name` from the directory.
+    fn remove_entry(&self, disk: &mut DiskInode, name: &str) -> FsResult<()> {
+        disk.flags &= !INDEX_FL;
+        let mut buf = vec![0; self.block_size() as usize];
+        for index in 0..self.dir_blocks(disk) {
+            let (block, entries) = self.read_dir_block(disk, index, &mut buf)?;
+            let found = entries.iter().position(|entry| {
+                entry.ino != 0 && !entry.is_dot(&buf) && entry.name(&buf) == name.as_bytes()
+            });
+            let Some(i) = found else {
+                continue;
+            };
+            // The entry before takes over the space; the first of a block is only marked unused.
+            let entry = entries[i];
+            match i.checked_sub(1).map(|i| entries[i]) {
+                Some(previous) => {
+                    dir::set_rec_len(&mut buf, previous.offset, previous.rec_len + entry.rec_len)
+                }
+                None => dir::clear_ino(&mut buf, entry.offset),
+            }
+            return self.volume.write_block(block, &buf);
+        }
+        Err(FsError::NotFound)
+    }
+
+    /// Make an inode of `mode`, let `init` fill it in, and link it into the directory as `name`.
+    fn add_child(
+        &self,
+        name: &str,
+        mode: u16,
+        init: impl FnOnce(&mut DiskInode, u32) -> FsResult<()>,
+    ) -> FsResult<Arc<dyn Inode>> {
+        self.volume.check_writable()?;
+        validate_name(name)?;
+        let mut state = self.state.lock();
+        match self.find(&state.disk, name) {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => (),
+            Err(error) => return Err(error),
+        }
+        if state.is_unlinked {
+            return Err(FsError::NotFound);
+        }
+        let is_dir = mode & S_IFMT == S_IFDIR;
+        if is_dir && state.disk.links >= LINK_MAX {
+            return Err(FsError::NoSpace);
+        }
+
+        let ino = self.volume.allocate_inode(self.ino, is_dir)?;
+        let now = now();
+        let mut child = DiskInode::new(mode, now);
+        let file_type = dir::file_type_code(child.file_type()?);
+        let result = init(&mut child, ino)
+            .and_then(|()| self.volume.write_inode(ino, &child))
+            .and_then(|()| self.add_entry(&mut state.disk, name, ino, file_type));
+        if result.is_ok() && is_dir {
+            state.disk.links += 1;
+        }
+        state.disk.mtime = now;
+        state.disk.ctime = now;
+        self.store(&state)?;
+        if let Err(error) = result {
+            // Give back what the child took; a failure here leaves it for fsck.
+            let _ = self.release(ino, &mut child);
+            return Err(error);
+        }
+        Ok(self.volume.inode(ino)?)
+    }
+
+    /// Remove the file, or with `is_dir` the empty directory, `name`.
+    fn remove(&self, name: &str, is_dir: bool) -> FsResult<()> {
+        self.volume.check_writable()?;
+        let mut state = self.state.lock();
+        let entry = self.find(&state.disk, name)?;
+        let child = self.volume.inode(entry.ino)?;
+        let mut child_state = child.state.lock();
+        match (is_dir, child_state.disk.is_dir()) {
+            (false, true) => return Err(FsError::IsADirectory),
+            (true, false) => return Err(FsError::NotADirectory),
+            (true, true) => {
+                if !child.is_empty(&child_state.disk)? {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+            }
+            (false, false) => (),
+        }
+
+        self.remove_entry(&mut state.disk, name)?;
+        let now = now();
+        let disk = &mut child_state.disk;
+        // A directory's own `.` goes with its entry.
+        disk.links = match is_dir {
+            true => 0,
+            false => disk.links.saturating_sub(1),
+        };
+        disk.ctime = now;
+        child_state.is_unlinked = child_state.disk.links == 0;
+        child.store(&child_state)?;
+        if is_dir {
+            state.disk.links = state.disk.links.saturating_sub(1);
+        }
+        state.disk.mtime = now;
+        state.disk.ctime = now;
+        self.store(&state)
+    }
+
+    /// Drop a reference to the extended attribute block, freeing it with the last.
+    fn release_xattr_block(&self, disk: &mut DiskInode) -> FsResult<()> {
+        let block = mem::take(&mut disk.file_acl);
+        self.volume.check_block(block)?;
+        let offset = self.volume.block_offset(block);
+        let mut header = [0; 8];
+        self.volume.read(offset, &mut header)?;
+        if u32::from_le_bytes(header[..4].try_into().unwrap()) != XATTR_MAGIC {
+            return Err(FsError::Corrupted);
+        }
+        let refcount = u32::from_le_bytes(header[4..].try_into().unwrap());
+        match refcount {
+            0 | 1 => self.free(disk, block),
+            _ => self.volume.write(
+                offset + XATTR_REFCOUNT_OFFSET,
+                &(refcount - 1).to_le_bytes(),
+            ),
+        }
+    }
+
+    /// Free the blocks of the deleted inode `ino`, then the inode.
+    fn release(&self, ino: u32, disk: &mut DiskInode) -> FsResult<()> {
+        if !self.is_fast_symlink(disk) {
+            self.free_from(disk, 0)?;
+        }
+        if disk.file_acl != 0 {
+            self.release_xattr_block(disk)?;
+        }
+        disk.size = 0;
+        disk.links = 0;
+        disk.dtime = now();
+        self.volume.write_inode(ino, disk)?;
+        self.volume.free_inode(ino, disk.is_dir())
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let state = self.state.lock();
+        let disk = &state.disk;
+        let file_type = disk.file_type()?;
+        let mut metadata = Metadata::new(self.ino as u64, file_type, disk.mode & 0o7777);
+        metadata.nlink = disk.links as u32;
+        metadata.uid = disk.uid;
+        metadata.gid = disk.gid;
+        metadata.size = disk.size;
+        metadata.block_size = self.block_size() as u32;
+        metadata.blocks = disk.blocks as u64;
+        metadata.atime = Duration::from_secs(disk.atime as u64);
+        metadata.mtime = Duration::from_secs(disk.mtime as u64);
+        metadata.ctime = Duration::from_secs(disk.ctime as u64);
+        // The old encoding lives in the first pointer, the new one in the second.
+        if matches!(file_type, FileType::CharDevice | FileType::BlockDevice) {
+            metadata.rdev = match disk.block[0] {
+                0 => disk.block[1],
+                rdev => rdev,
+            } as u64;
+        }
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let state = self.state.lock();
+        if state.disk.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        let size = state.disk.size;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        self.read_data(&state.disk, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> FsResult<usize> {
+        self.volume.check_writable()?;
+        let mut state = self.state.lock();
+        if state.disk.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let result = self.write_data(&mut state.disk, offset, data);
+        // Blocks may have been added even if the write failed.
+        let now = now();
+        state.disk.mtime = now;
+        state.disk.ctime = now;
+        self.store(&state)?;
+        result.map(|()| data.len())
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        self.volume.check_writable()?;
+        let mut state = self.state.lock();
+        if state.disk.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        let result = self.resize(&mut state.disk, size);
+        let now = now();
+        state.disk.mtime = now;
+        state.disk.ctime = now;
+        self.store(&state)?;
+        result
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let state = self.state.lock();
+        let entry = self.find(&state.disk, name)?;
+        Ok(self.volume.inode(entry.ino)?)
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>> {
+        let type_bits = match file_type {
+            FileType::Regular => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            _ => return Err(FsError::Unsupported),
+        };
+        self.add_child(name, type_bits | mode & 0o7777, |disk, ino| {
+            if file_type != FileType::Directory {
+                return Ok(());
+            }
+            let block_size = self.block_size() as usize;
+            let has_file_type = self.volume.superblock.has_file_type;
+            let code = dir::file_type_code(FileType::Directory);
+            let block = self.allocate(disk, self.volume.inode_goal(ino))?;
+            disk.block[0] = block;
+            disk.size = block_size as u64;
+            // `.` and its parent's `..`.
+            disk.links = 2;
+            let mut raw = vec![0; block_size];
+            let dot_size = dir::entry_size(1);
+            dir::encode(&mut raw, 0, ino, dot_size, b".", code, has_file_type);
+            dir::encode(
+                &mut raw,
+                dot_size,
+                self.ino,
+                block_size - dot_size,
+                b"..",
+                code,
+                has_file_type,
+            );
+            self.volume.write_block(block, &raw)
+        })
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        if target.is_empty() {
+            return Err(FsError::InvalidInput);
+        }
+        if target.len() >= self.block_size() as usize {
+            return Err(FsError::NameTooLong);
+        }
+        self.add_child(name, S_IFLNK | 0o777, |disk, ino| {
+            disk.size = target.len() as u64;
+            if target.len() < FAST_SYMLINK_MAX {
+                disk.set_inline_data(target.as_bytes());
+                return Ok(());
+            }
+            let block = self.allocate(disk, self.volume.inode_goal(ino))?;
+            disk.block[0] = block;
+            let mut raw = vec![0; self.block_size() as usize];
+            raw[..target.len()].copy_from_slice(target.as_bytes());
+            self.volume.write_block(block, &raw)
+        })
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.remove(name, false)
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.remove(name, true)
+    }
+
+    fn read_dir(&self, index: usize) -> FsResult<Option<DirEntry>> {
+        let state = self.state.lock();
+        let disk = &state.disk;
+        if !disk.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        let mut buf = vec![0; self.block_size() as usize];
+        let mut remaining = index;
+        for block_index in 0..self.dir_blocks(disk) {
+            let (_, entries) = self.read_dir_block(disk, block_index, &mut buf)?;
+            let used: Vec<RawEntry> = entries
+                .into_iter()
+                .filter(|entry| entry.ino != 0 && !entry.is_dot(&buf))
+                .collect();
+            let Some(&entry) = used.get(remaining) else {
+                remaining -= used.len();
+                continue;
+            };
+            // Without the `filetype` feature, only the inode knows.
+            let file_type = match dir::file_type_from_code(entry.file_type) {
+                Some(file_type) => file_type,
+                None => self.volume.read_inode(entry.ino)?.file_type()?,
+            };
+            return Ok(Some(DirEntry {
+                name: String::from_utf8_lossy(entry.name(&buf)).to_string(),
+                ino: entry.ino as u64,
+                file_type,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        let state = self.state.lock();
+        let disk = &state.disk;
+        if disk.mode & S_IFMT != S_IFLNK {
+            return Err(FsError::InvalidInput);
+        }
+        let len = disk.size as usize;
+        let target = match self.is_fast_symlink(disk) {
+            true => disk
+                .inline_data()
+                .get(..len)
+                .ok_or(FsError::Corrupted)?
+                .to_vec(),
+            false => {
+                if len >= self.block_size() as usize {
+                    return Err(FsError::Corrupted);
+                }
+                let mut target = vec![0; len];
+                self.read_data(disk, 0, &mut target)?;
+                target
+            }
+        };
+        String::from_utf8(target).map_err(|_| FsError::Corrupted)
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(cache::get().sync_device(&self.volume.device)?)
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        if state.is_unlinked {
+            // Nothing is left to report a failure to; what leaks stays lost until fsck.
+            let _ = self.release(self.ino, &mut state.disk);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! ext2, as `mke2fs -t ext2` makes it.
+//!
+//! - Volumes with incompatible features, like extents or a journal to recover, are refused.
+//!   Those with read-only compatible features this driver does not keep up, like metadata
+//!   checksums, are mounted read-only, as is a read-only device.
+//! - HTree directory indexes are read as the linear directories they also are, and dropped
+//!   from a directory that changes.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use spin::Mutex;
+
+use crate::drivers::block::{cache, BlockDevice, SECTOR_SIZE};
+
+use super::{FileSystem, FsError, FsResult, Inode};
+
+mod dir;
+mod inode;
+
+use inode::{DiskInode, Ext2Inode};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xef53;
+/// Where the free block and inode counts lie in the superblock.
+const FREE_COUNTS_OFFSET: u64 = SUPERBLOCK_OFFSET + 12;
+
+const ROOT_INO: u32 = 2;
+/// Revision 0 reserves inodes 1 to 10 and has 128-byte inodes.
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: u64 = 32;
+/// Where the free block, free inode and directory counts lie in a group descriptor.
+const GROUP_COUNTS_OFFSET: u64 = 12;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const RO_COMPAT_BTREE_DIR: u32 = 0x0004;
+const RO_COMPAT_SUPPORTED: u32 =
+    RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE | RO_COMPAT_BTREE_DIR;
+
+/// What the superblock says about the volume's shape.
+#[derive(Debug, Clone)]
+struct Superblock {
+    inodes_count: u32,
+    blocks_count: u32,
+    first_data_block: u32,
+    block_size: usize,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    first_ino: u32,
+    inode_size: usize,
+    /// Directory entries carry the file type.
+    has_file_type: bool,
+    /// Regular files may be 4 GiB and larger.
+    has_large_file: bool,
+    /// Features this driver cannot keep up on writes.
+    is_read_only: bool,
+}
+
+impl Superblock {
+    fn parse(raw: &[u8]) -> FsResult<Superblock> {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        if u16_at(56) != MAGIC {
+            return Err(FsError::Corrupted);
+        }
+        let log_block_size = u32_at(24);
+        if log_block_size > 6 {
+            return Err(FsError::Corrupted);
+        }
+        // A 64 KiB block needs an encoding of `rec_len` that does not fit 16 bits.
+        if log_block_size > 5 {
+            return Err(FsError::Unsupported);
+        }
+        let is_dynamic = u32_at(76) >= 1;
+        let (first_ino, inode_size, incompat, ro_compat) = match is_dynamic {
+            true => (u32_at(84), u16_at(88) as usize, u32_at(96), u32_at(100)),
+            false => (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, 0, 0),
+        };
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(FsError::Unsupported);
+        }
+        let superblock = Superblock {
+            inodes_count: u32_at(0),
+            blocks_count: u32_at(4),
+            first_data_block: u32_at(20),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: u32_at(32),
+            inodes_per_group: u32_at(40),
+            first_ino,
+            inode_size,
+            has_file_type: incompat & INCOMPAT_FILETYPE != 0,
+            has_large_file: ro_compat & RO_COMPAT_LARGE_FILE != 0,
+            is_read_only: ro_compat & !RO_COMPAT_SUPPORTED != 0,
+        };
+        if superblock.blocks_per_group == 0
+            || superblock.inodes_per_group == 0
+            || superblock.blocks_per_group as usize > superblock.block_size * 8
+            || superblock.inodes_per_group as usize > superblock.block_size * 8
+            || superblock.inode_size < GOOD_OLD_INODE_SIZE
+            || !superblock.inode_size.is_power_of_two()
+            || superblock.inode_size > superblock.block_size
+            || superblock.first_data_block >= superblock.blocks_count
+            || superblock.inodes_count as u64
+                > superblock.inodes_per_group as u64 * superblock.group_count() as u64
+        {
+            return Err(FsError::Corrupted);
+        }
+        Ok(superblock)
+    }
+
+    fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    /// Blocks in `group`; the last group may be short.
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = self.first_data_block + group * self.blocks_per_group;
+        self.blocks_per_group.min(self.blocks_count - start)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GroupDesc {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+impl GroupDesc {
+    fn parse(raw: &[u8]) -> GroupDesc {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        GroupDesc {
+            block_bitmap: u32_at(0),
+            inode_bitmap: u32_at(4),
+            inode_table: u32_at(8),
+            free_blocks: u16_at(12),
+            free_inodes: u16_at(14),
+            used_dirs: u16_at(16),
+        }
+    }
+}
+
+/// The group descriptors and free counts, which allocation changes.
+struct Allocation {
+    groups: Vec<GroupDesc>,
+    free_blocks: u32,
+    free_inodes: u32,
+}
+
+/// The volume the inodes of an [`Ext2`] share.
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    is_read_only: bool,
+    allocation: Mutex<Allocation>,
+    /// The inodes in use, so that a file opened twice is one inode.
+    inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+/// The first clear bit of `bitmap` among its first `len`.
+fn find_clear_bit(bitmap: &[u8], len: usize) -> Option<usize> {
+    (0..len).find(|bit| bitmap[bit / 8] & 1 << (bit % 8) == 0)
+}
+
+impl Volume {
+    fn open(device: Arc<dyn BlockDevice>) -> FsResult<Volume> {
+        let mut raw = [0; SUPERBLOCK_SIZE];
+        cache::get().read(&device, SUPERBLOCK_OFFSET, &mut raw)?;
+        let superblock = Superblock::parse(&raw)?;
+        let device_blocks = device.capacity() * SECTOR_SIZE as u64 / superblock.block_size as u64;
+        if (superblock.blocks_count as u64) > device_blocks {
+            return Err(FsError::Corrupted);
+        }
+
+        // The group descriptor table follows the superblock's block.
+        let table = (superblock.first_data_block as u64 + 1) * superblock.block_size as u64;
+        let mut raw_groups = vec![0; superblock.group_count() as usize * GROUP_DESC_SIZE as usize];
+        cache::get().read(&device, table, &mut raw_groups)?;
+        let groups = raw_groups
+            .chunks(GROUP_DESC_SIZE as usize)
+            .map(GroupDesc::parse)
+            .collect();
+
+        Ok(Volume {
+            is_read_only: superblock.is_read_only || device.is_read_only(),
+            device,
+            allocation: Mutex::new(Allocation {
+                groups,
+                free_blocks: u32::from_le_bytes(raw[12..16].try_into().unwrap()),
+                free_inodes: u32::from_le_bytes(raw[16..20].try_into().unwrap()),
+            }),
+            superblock,
+            inodes: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        match self.is_read_only {
+            true => Err(FsError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        Ok(cache::get().read(&self.device, offset, buf)?)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> FsResult<()> {
+        Ok(cache::get().write(&self.device, offset, data)?)
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size() as u64
+    }
+
+    /// Check that `block` lies on the volume, as a corrupted pointer may not.
+    fn check_block(&self, block: u32) -> FsResult<()> {
+        match (self.superblock.first_data_block..self.superblock.blocks_count).contains(&block) {
+            true => Ok(()),
+            false => Err(FsError::Corrupted),
+        }
+    }
+
+    fn read_block(&self, block: u32, buf: &mut [u8]) -> FsResult<()> {
+        self.check_block(block)?;
+        self.read(self.block_offset(block), buf)
+    }
+
+    fn write_block(&self, block: u32, data: &[u8]) -> FsResult<()> {
+        self.check_block(block)?;
+        self.write(self.block_offset(block), data)
+    }
+
+    /// Write the counts of `group` and of the volume back.
+    fn store_counts(&self, allocation: &Allocation, group: u32) -> FsResult<()> {
+        let desc = &allocation.groups[group as usize];
+        let mut counts = [0; 6];
+        counts[..2].copy_from_slice(&desc.free_blocks.to_le_bytes());
+        counts[2..4].copy_from_slice(&desc.free_inodes.to_le_bytes());
+        counts[4..].copy_from_slice(&desc.used_dirs.to_le_bytes());
+        let table = self.block_offset(self.superblock.first_data_block + 1);
+        self.write(
+            table + group as u64 * GROUP_DESC_SIZE + GROUP_COUNTS_OFFSET,
+            &counts,
+        )?;
+        let mut counts = [0; 8];
+        counts[..4].copy_from_slice(&allocation.free_blocks.to_le_bytes());
+        counts[4..].copy_from_slice(&allocation.free_inodes.to_le_bytes());
+        self.write(FREE_COUNTS_OFFSET, &counts)
+    }
+
+    /// Set the first clear bit of a bitmap block among its first `len`.
+    fn take_bit(&self, bitmap: u32, len: usize) -> FsResult<Option<usize>> {
+        let mut raw = vec![0; self.block_size()];
+        self.read_block(bitmap, &mut raw)?;
+        let Some(bit) = find_clear_bit(&raw, len) else {
+            return Ok(None);
+        };
+        let byte = raw[bit / 8] | 1 << (bit % 8);
+        self.write(self.block_offset(bitmap) + (bit / 8) as u64, &[byte])?;
+        Ok(Some(bit))
+    }
+
+    /// Clear a bit of a bitmap block; fails if it was clear already.
+    fn clear_bit(&self, bitmap: u32, bit: usize) -> FsResult<()> {
+        self.check_block(bitmap)?;
+        let offset = self.block_offset(bitmap) + (bit / 8) as u64;
+        let mut byte = [0];
+        self.read(offset, &mut byte)?;
+        if byte[0] & 1 << (bit % 8) == 0 {
+            return Err(FsError::Corrupted);
+        }
+        self.write(offset, &[byte[0] & !(1 << (bit % 8))])
+    }
+
+    /// Allocate a zeroed block, near `goal` if it can.
+    fn allocate_block(&self, goal: u32) -> FsResult<u32> {
+        let superblock = &self.superblock;
+        let mut allocation = self.allocation.lock();
+        let group_count = superblock.group_count();
+        let first = goal.saturating_sub(superblock.first_data_block) / superblock.blocks_per_group;
+        for i in 0..group_count {
+            let group = (first + i) % group_count;
+            let desc = &allocation.groups[group as usize];
+            if desc.free_blocks == 0 {
+                continue;
+            }
+            let len = superblock.blocks_in_group(group) as usize;
+            let Some(bit) = self.take_bit(desc.block_bitmap, len)? else {
+                continue;
+            };
+            allocation.groups[group as usize].free_blocks -= 1;
+            allocation.free_blocks = allocation.free_blocks.saturating_sub(1);
+            self.store_counts(&allocation, group)?;
+            let block =
+                superblock.first_data_block + group * superblock.blocks_per_group + bit as u32;
+            self.write_block(block, &vec![0; self.block_size()])?;
+            return Ok(block);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_block(&self, block: u32) -> FsResult<()> {
+        self.check_block(block)?;
+        let superblock = &self.superblock;
+        let mut allocation = self.allocation.lock();
+        let index = block - superblock.first_data_block;
+        let group = index / superblock.blocks_per_group;
+        let bit = (index % superblock.blocks_per_group) as usize;
+        self.clear_bit(allocation.groups[group as usize].block_bitmap, bit)?;
+        allocation.groups[group as usize].free_blocks += 1;
+        allocation.free_blocks += 1;
+        self.store_counts(&allocation, group)
+    }
+
+    /// Allocate a zeroed inode, in the group of `parent` if it can.
+    fn allocate_inode(&self, parent: u32, is_dir: bool) -> FsResult<u32> {
+        let superblock = &self.superblock;
+        let mut allocation = self.allocation.lock();
+        let group_count = superblock.group_count();
+        let first = (parent - 1) / superblock.inodes_per_group;
+        for i in 0..group_count {
+            let group = (first + i) % group_count;
+            let desc = &allocation.groups[group as usize];
+            if desc.free_inodes == 0 {
+                continue;
+            }
+            let Some(bit) =
+                self.take_bit(desc.inode_bitmap, superblock.inodes_per_group as usize)?
+            else {
+                continue;
+            };
+            let ino = group * superblock.inodes_per_group + bit as u32 + 1;
+            if ino < superblock.first_ino || ino > superblock.inodes_count {
+                // A reserved inode with its bit clear: leave it taken.
+                continue;
+            }
+            let desc = &mut allocation.groups[group as usize];
+            desc.free_inodes -= 1;
+            if is_dir {
+                desc.used_dirs += 1;
+            }
+            allocation.free_inodes = allocation.free_inodes.saturating_sub(1);
+            self.store_counts(&allocation, group)?;
+            // Finding the inode table takes the lock again.
+            drop(allocation);
+            self.write(self.inode_offset(ino)?, &vec![0; superblock.inode_size])?;
+            return Ok(ino);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_inode(&self, ino: u32, is_dir: bool) -> FsResult<()> {
+        let superblock = &self.superblock;
+        let mut allocation = self.allocation.lock();
+        let group = (ino - 1) / superblock.inodes_per_group;
+        let bit = ((ino - 1) % superblock.inodes_per_group) as usize;
+        self.clear_bit(allocation.groups[group as usize].inode_bitmap, bit)?;
+        let desc = &mut allocation.groups[group as usize];
+        desc.free_inodes += 1;
+        if is_dir {
+            desc.used_dirs = desc.used_dirs.saturating_sub(1);
+        }
+        allocation.free_inodes += 1;
+        self.store_counts(&allocation, group)
+    }
+
+    /// The first block of the group of inode `ino`, where its blocks had best go.
+    fn inode_goal(&self, ino: u32) -> u32 {
+        let group = (ino - 1) / self.superblock.inodes_per_group;
+        self.superblock.first_data_block + group * self.superblock.blocks_per_group
+    }
+
+    /// Where inode `ino` lies in its group's inode table.
+    fn inode_offset(&self, ino: u32) -> FsResult<u64> {
+        let superblock = &self.superblock;
+        if ino == 0 || ino > superblock.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+        let group = (ino - 1) / superblock.inodes_per_group;
+        let index = (ino - 1) % superblock.inodes_per_group;
+        let table = self.allocation.lock().groups[group as usize].inode_table;
+        Ok(self.block_offset(table) + index as u64 * superblock.inode_size as u64)
+    }
+
+    fn read_inode(&self, ino: u32) -> FsResult<DiskInode> {
+        let mut raw = [0; GOOD_OLD_INODE_SIZE];
+        self.read(self.inode_offset(ino)?, &mut raw)?;
+        Ok(DiskInode::parse(raw))
+    }
+
+    fn write_inode(&self, ino: u32, inode: &DiskInode) -> FsResult<()> {
+        self.write(self.inode_offset(ino)?, &inode.encode())
+    }
+
+    fn inode(self: &Arc<Self>, ino: u32) -> FsResult<Arc<Ext2Inode>> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let inode = Arc::new(Ext2Inode::new(self.clone(), ino, self.read_inode(ino)?));
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+}
+
+/// A mounted ext2 volume.
+pub struct Ext2 {
+    volume: Arc<Volume>,
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2 {
+    /// Mount the ext2 volume on `device`.
+    ///
+    /// - Fails with [`FsError::Unsupported`] for incompatible features, like ext4's extents.
+    pub fn mount(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Ext2>> {
+        let volume = Arc::new(Volume::open(device)?);
+        let root = volume.inode(ROOT_INO)?;
+        Ok(Arc::new(Ext2 { volume, root }))
+    }
+
+    /// Whether writes fail with [`FsError::ReadOnly`].
+    pub fn is_read_only(&self) -> bool {
+        self.volume.is_read_only
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        match self.volume.is_read_only {
+            true => Ok(()),
+            false => Ok(cache::get().sync_device(&self.volume.device)?),
+        }
+    }
+}
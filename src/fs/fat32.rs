@@ -0,0 +1,173 @@
+//! FAT32 volumes, served by the [`fat32`](::fat32) crate so that its code is tested on the host.
+//!
+//! - Its I/O goes through the shared block cache.
+
+use alloc::sync::Arc;
+
+use crate::{
+    clock,
+    drivers::block::{cache, BlockDevice, BlockError, SECTOR_SIZE},
+};
+
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+
+impl From<::fat32::Error> for FsError {
+    fn from(error: ::fat32::Error) -> Self {
+        match error {
+            ::fat32::Error::NotFound => FsError::NotFound,
+            ::fat32::Error::AlreadyExists => FsError::AlreadyExists,
+            ::fat32::Error::NotADirectory => FsError::NotADirectory,
+            ::fat32::Error::IsADirectory => FsError::IsADirectory,
+            ::fat32::Error::DirectoryNotEmpty => FsError::DirectoryNotEmpty,
+            ::fat32::Error::InvalidInput => FsError::InvalidInput,
+            ::fat32::Error::NameTooLong => FsError::NameTooLong,
+            ::fat32::Error::Unsupported => FsError::Unsupported,
+            ::fat32::Error::NoSpace => FsError::NoSpace,
+            ::fat32::Error::ReadOnly => FsError::ReadOnly,
+            ::fat32::Error::Corrupted => FsError::Corrupted,
+            ::fat32::Error::Io => FsError::Io,
+        }
+    }
+}
+
+impl From<BlockError> for ::fat32::Error {
+    fn from(error: BlockError) -> Self {
+        match error {
+            BlockError::ReadOnly => ::fat32::Error::ReadOnly,
+            BlockError::OutOfMemory => ::fat32::Error::NoSpace,
+            BlockError::OutOfRange => ::fat32::Error::Corrupted,
+            BlockError::Misaligned | BlockError::Unsupported | BlockError::Io => ::fat32::Error::Io,
+        }
+    }
+}
+
+/// A block device as the `fat32` crate addresses it, through the block cache.
+struct CachedDevice(Arc<dyn BlockDevice>);
+
+impl ::fat32::BlockDevice for CachedDevice {
+    fn size(&self) -> u64 {
+        self.0.capacity() * SECTOR_SIZE as u64
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> ::fat32::Result<()> {
+        Ok(cache::get().read(&self.0, offset, buf)?)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> ::fat32::Result<()> {
+        Ok(cache::get().write(&self.0, offset, data)?)
+    }
+
+    fn flush(&self) -> ::fat32::Result<()> {
+        Ok(cache::get().sync_device(&self.0)?)
+    }
+}
+
+/// A mounted FAT32 volume.
+pub struct Fat32 {
+    volume: ::fat32::Fat32,
+    /// Kept so that the root is one inode however often it is asked for.
+    root: Arc<FatInode>,
+}
+
+impl Fat32 {
+    /// Mount the FAT32 volume on `device`.
+    ///
+    /// - Fails with [`FsError::Unsupported`] for FAT12 and FAT16 volumes.
+    pub fn mount(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Fat32>> {
+        let volume = ::fat32::Fat32::mount(Arc::new(CachedDevice(device)), clock::realtime)?;
+        let root = Arc::new(FatInode(volume.root().clone()));
+        Ok(Arc::new(Fat32 { volume, root }))
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &str {
+        "vfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(self.volume.sync()?)
+    }
+}
+
+fn file_type(kind: ::fat32::FileKind) -> FileType {
+    match kind {
+        ::fat32::FileKind::Regular => FileType::Regular,
+        ::fat32::FileKind::Directory => FileType::Directory,
+    }
+}
+
+/// A file or directory of a [`Fat32`] volume.
+struct FatInode(Arc<::fat32::FatInode>);
+
+impl Inode for FatInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let fat = self.0.metadata();
+        let mode = match fat.kind {
+            ::fat32::FileKind::Directory => 0o755,
+            ::fat32::FileKind::Regular => 0o644,
+        };
+        let mode = match fat.is_read_only {
+            true => mode & !0o222,
+            false => mode,
+        };
+        let mut metadata = Metadata::new(fat.ino, file_type(fat.kind), mode);
+        metadata.size = fat.size;
+        metadata.block_size = fat.cluster_size;
+        metadata.blocks = fat.allocated / 512;
+        metadata.atime = fat.accessed;
+        metadata.mtime = fat.modified;
+        metadata.ctime = fat.modified;
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        Ok(self.0.read_at(offset, buf)?)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> FsResult<usize> {
+        Ok(self.0.write_at(offset, data)?)
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        Ok(self.0.truncate(size)?)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        Ok(Arc::new(FatInode(self.0.lookup(name)?)))
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>> {
+        let kind = match file_type {
+            FileType::Regular => ::fat32::FileKind::Regular,
+            FileType::Directory => ::fat32::FileKind::Directory,
+            _ => return Err(FsError::Unsupported),
+        };
+        let inode = self.0.create(name, kind, mode & 0o222 == 0)?;
+        Ok(Arc::new(FatInode(inode)))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        Ok(self.0.unlink(name)?)
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        Ok(self.0.rmdir(name)?)
+    }
+
+    fn read_dir(&self, index: usize) -> FsResult<Option<DirEntry>> {
+        Ok(self.0.read_dir(index)?.map(|entry| DirEntry {
+            name: entry.name,
+            ino: entry.ino,
+            file_type: file_type(entry.kind),
+        }))
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(self.0.sync()?)
+    }
+}
@@ -0,0 +1,89 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{ConsoleInode, File, OpenFlags};
+
+/// The most file descriptors a task may have open.
+pub const MAX_FDS: usize = 1024;
+
+/// A task's open files, indexed by file descriptor.
+#[derive(Clone, Default)]
+pub struct FdTable {
+    files: Vec<Option<FdEntry>>,
+}
+
+#[derive(Clone)]
+struct FdEntry {
+    file: Arc<File>,
+    /// Closed by `execve`.
+    close_on_exec: bool,
+}
+
+impl FdTable {
+    pub fn new() -> FdTable {
+        FdTable { files: Vec::new() }
+    }
+
+    /// Standard input, output and error on the kernel console.
+    pub fn with_console() -> FdTable {
+        let mut table = FdTable::new();
+        let console = Arc::new(File::anonymous(Arc::new(ConsoleInode), OpenFlags::RDWR));
+        for _ in 0..3 {
+            table.insert(console.clone(), false);
+        }
+        table
+    }
+
+    pub fn get(&self, fd: usize) -> Option<Arc<File>> {
+        self.files.get(fd)?.as_ref().map(|entry| entry.file.clone())
+    }
+
+    /// Put `file` at the lowest free descriptor at or above `min`.
+    ///
+    /// - `None` if the table is full.
+    pub fn insert_from(
+        &mut self,
+        min: usize,
+        file: Arc<File>,
+        close_on_exec: bool,
+    ) -> Option<usize> {
+        let fd =
+            (min..MAX_FDS).find(|fd| self.files.get(*fd).is_none_or(|entry| entry.is_none()))?;
+        self.insert_at(fd, file, close_on_exec);
+        Some(fd)
+    }
+
+    pub fn insert(&mut self, file: Arc<File>, close_on_exec: bool) -> Option<usize> {
+        self.insert_from(0, file, close_on_exec)
+    }
+
+    /// Put `file` at `fd`, returning the file it replaces.
+    pub fn insert_at(
+        &mut self,
+        fd: usize,
+        file: Arc<File>,
+        close_on_exec: bool,
+    ) -> Option<Arc<File>> {
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd]
+            .replace(FdEntry {
+                file,
+                close_on_exec,
+            })
+            .map(|entry| entry.file)
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Option<Arc<File>> {
+        self.files.get_mut(fd)?.take().map(|entry| entry.file)
+    }
+
+    /// Close the descriptors marked close-on-exec.
+    pub fn close_on_exec(&mut self) {
+        for entry in self.files.iter_mut() {
+            if entry.as_ref().is_some_and(|entry| entry.close_on_exec) {
+                *entry = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use alloc::sync::Arc;
+use core::ops::BitOr;
+
+use spin::Mutex;
+
+use super::{Dentry, DirEntry, FileType, FsError, FsResult, Inode, Metadata};
+
+/// How a file was opened, in the bits `openat` takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const RDONLY: OpenFlags = OpenFlags(0o0);
+    pub const WRONLY: OpenFlags = OpenFlags(0o1);
+    pub const RDWR: OpenFlags = OpenFlags(0o2);
+    pub const CREAT: OpenFlags = OpenFlags(0o100);
+    pub const EXCL: OpenFlags = OpenFlags(0o200);
+    pub const TRUNC: OpenFlags = OpenFlags(0o1000);
+    pub const APPEND: OpenFlags = OpenFlags(0o2000);
+    pub const NONBLOCK: OpenFlags = OpenFlags(0o4000);
+    pub const DIRECTORY: OpenFlags = OpenFlags(0o200000);
+    pub const NOFOLLOW: OpenFlags = OpenFlags(0o400000);
+    pub const CLOEXEC: OpenFlags = OpenFlags(0o2000000);
+
+    const ACCESS_MODE: u32 = 0o3;
+
+    pub fn contains(&self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.0 & OpenFlags::ACCESS_MODE != OpenFlags::WRONLY.0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        matches!(self.0 & OpenFlags::ACCESS_MODE, 1 | 2)
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, rhs: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file: an inode with an offset and the access it was opened for.
+///
+/// - Shared between file descriptors duplicated from each other, offset included.
+pub struct File {
+    dentry: Option<Arc<Dentry>>,
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    /// A byte offset, or for a directory the index of the next entry.
+    offset: Mutex<u64>,
+}
+
+impl File {
+    pub fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> File {
+        File {
+            inode: dentry.inode().clone(),
+            dentry: Some(dentry),
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+
+    /// A file with no name, like the console or a pipe end.
+    pub fn anonymous(inode: Arc<dyn Inode>, flags: OpenFlags) -> File {
+        File {
+            dentry: None,
+            inode,
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+
+    pub fn dentry(&self) -> Option<&Arc<Dentry>> {
+        self.dentry.as_ref()
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn metadata(&self) -> FsResult<Metadata> {
+        self.inode.metadata()
+    }
+
+    fn is_seekable(&self) -> FsResult<bool> {
+        Ok(matches!(
+            self.metadata()?.file_type,
+            FileType::Regular | FileType::Directory | FileType::BlockDevice
+        ))
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags.is_readable() {
+            return Err(FsError::InvalidInput);
+        }
+        let mut offset = self.offset.lock();
+        let len = self.inode.read_at(*offset, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    pub fn write(&self, data: &[u8]) -> FsResult<usize> {
+        if !self.flags.is_writable() {
+            return Err(FsError::InvalidInput);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.metadata()?.size;
+        }
+        let len = self.inode.write_at(*offset, data)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    pub fn seek(&self, position: SeekFrom) -> FsResult<u64> {
+        if !self.is_seekable()? {
+            return Err(FsError::Unsupported);
+        }
+        let mut offset = self.offset.lock();
+        let (base, delta) = match position {
+            SeekFrom::Start(position) => (0, position as i64),
+            SeekFrom::Current(delta) => (*offset, delta),
+            SeekFrom::End(delta) => (self.metadata()?.size, delta),
+        };
+        let position = base
+            .checked_add_signed(delta)
+            .ok_or(FsError::InvalidInput)?;
+        *offset = position;
+        Ok(position)
+    }
+
+    /// The next directory entry, `.` and `..` first, advancing the offset past it once
+    /// `consume` accepts it.
+    ///
+    /// - `consume` returns `false` when it has no room left, leaving the entry for next time.
+    pub fn read_dir(&self, mut consume: impl FnMut(&DirEntry, u64) -> bool) -> FsResult<()> {
+        let dentry = self.dentry.as_ref().ok_or(FsError::NotADirectory)?;
+        let mut offset = self.offset.lock();
+        loop {
+            let entry = match *offset {
+                0 => DirEntry {
+                    name: ".".into(),
+                    ino: self.metadata()?.ino,
+                    file_type: FileType::Directory,
+                },
+                1 => {
+                    let parent = dentry.parent().unwrap_or(dentry);
+                    DirEntry {
+                        name: "..".into(),
+                        ino: parent.inode().metadata()?.ino,
+                        file_type: FileType::Directory,
+                    }
+                }
+                index => match self.inode.read_dir(index as usize - 2)? {
+                    Some(entry) => entry,
+                    None => return Ok(()),
+                },
+            };
+            if !consume(&entry, *offset + 1) {
+                return Ok(());
+            }
+            *offset += 1;
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! The initial RAM file system: cpio `newc` archives unpacked into a tmpfs at `/`.
+//!
+//! - One archive may be embedded in the kernel's `.initramfs` section, patched in by
+//!   `initramfs` in `scripts.nu`, and another passed by the boot loader through
+//!   `/chosen/linux,initrd-start` and `linux,initrd-end`. The initrd unpacks second, so its
+//!   files win.
+//! - Regular files, directories and symbolic links are unpacked. Device nodes, FIFOs and
+//!   sockets are skipped, and hard links come out as separate files.
+
+use alloc::{str, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use crate::{
+    device_tree,
+    mm::frame::{self, Frame},
+};
+
+use super::{mount, FileSystem, FileType, FsError, FsResult, Inode, TmpFs};
+
+const MAGIC: &[u8] = b"070701";
+/// `newc` with checksums, which are not checked.
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+/// The type bits of `mode`.
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// The header fields, in order after the magic, each 8 hex digits.
+const FIELD_MODE: usize = 1;
+const FIELD_FILE_SIZE: usize = 6;
+const FIELD_NAME_SIZE: usize = 11;
+
+/// The initrd, kept from the frame allocator until it is unpacked.
+struct Initrd {
+    start: usize,
+    end: usize,
+    _frames: Vec<Frame>,
+}
+
+static INITRD: Mutex<Option<Initrd>> = Mutex::new(None);
+
+/// What [`unpack`] made.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnpackStats {
+    pub files: usize,
+    pub directories: usize,
+    pub symlinks: usize,
+    /// Device nodes, FIFOs and sockets.
+    pub skipped: usize,
+}
+
+/// The archive in the kernel's `.initramfs` section, if one was patched in.
+fn embedded() -> Option<&'static [u8]> {
+    extern "C" {
+        fn sinitramfs();
+        fn einitramfs();
+    }
+    let start = sinitramfs as *const () as usize;
+    let end = einitramfs as *const () as usize;
+    let archive = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    is_archive(archive).then_some(archive)
+}
+
+fn is_archive(archive: &[u8]) -> bool {
+    archive.starts_with(MAGIC) || archive.starts_with(MAGIC_CRC)
+}
+
+/// Keep the initrd the device tree names from being handed out as frames.
+///
+/// - Must run right after the device tree is parsed, before any frame is allocated.
+pub fn reserve_initrd() {
+    let Some(chosen) = device_tree::get().and_then(|tree| tree.find_path("/chosen")) else {
+        return;
+    };
+    let (Some(start), Some(end)) = (
+        chosen.property_usize("linux,initrd-start"),
+        chosen.property_usize("linux,initrd-end"),
+    ) else {
+        return;
+    };
+    if start >= end {
+        return;
+    }
+    *INITRD.lock() = Some(Initrd {
+        start,
+        end,
+        _frames: frame::reserve(start, end),
+    });
+}
+
+/// Mount a tmpfs at `/` and unpack the embedded archive and the initrd into it.
+///
+/// - The initrd's frames are freed afterwards.
+pub fn init() -> FsResult<UnpackStats> {
+    let fs = TmpFs::new();
+    let root = fs.root();
+    mount("/", fs)?;
+
+    let mut stats = UnpackStats::default();
+    if let Some(archive) = embedded() {
+        unpack(archive, &root, &mut stats)?;
+    }
+    let initrd = INITRD.lock().take();
+    if let Some(initrd) = initrd {
+        let archive = unsafe {
+            core::slice::from_raw_parts(initrd.start as *const u8, initrd.end - initrd.start)
+        };
+        unpack(archive, &root, &mut stats)?;
+    }
+    Ok(stats)
+}
+
+fn align4(offset: usize) -> usize {
+    offset.next_multiple_of(4)
+}
+
+/// Header field `index`, counted after the magic.
+fn field(header: &[u8], index: usize) -> FsResult<usize> {
+    let start = MAGIC.len() + index * 8;
+    let digits = str::from_utf8(&header[start..start + 8]).map_err(|_| FsError::Corrupted)?;
+    usize::from_str_radix(digits, 16).map_err(|_| FsError::Corrupted)
+}
+
+/// Unpack the cpio `newc` archives in `archive`, one after another, into the directory `root`.
+///
+/// - An archive without a trailer is [`FsError::Corrupted`], but what it held so far is kept.
+/// - Files already there are overwritten and directories already there are kept.
+pub fn unpack(archive: &[u8], root: &Arc<dyn Inode>, stats: &mut UnpackStats) -> FsResult<()> {
+    let mut offset = 0;
+    loop {
+        let header = archive
+            .get(offset..offset + HEADER_SIZE)
+            .ok_or(FsError::Corrupted)?;
+        if !is_archive(header) {
+            return Err(FsError::Corrupted);
+        }
+        let mode = field(header, FIELD_MODE)? as u32;
+        let file_size = field(header, FIELD_FILE_SIZE)?;
+        let name_size = field(header, FIELD_NAME_SIZE)?;
+
+        let name_start = offset + HEADER_SIZE;
+        let name = archive
+            .get(name_start..name_start + name_size)
+            .and_then(|name| name.strip_suffix(&[0]))
+            .ok_or(FsError::Corrupted)?;
+        let name = str::from_utf8(name).map_err(|_| FsError::Corrupted)?;
+        let data_start = align4(name_start + name_size);
+        let data = archive
+            .get(data_start..data_start + file_size)
+            .ok_or(FsError::Corrupted)?;
+        offset = align4(data_start + file_size);
+
+        if name == TRAILER {
+            // Another archive may follow, after zeros; anything else is past the end.
+            while archive.get(offset) == Some(&0) {
+                offset += 1;
+            }
+            if !is_archive(&archive[offset.min(archive.len())..]) {
+                return Ok(());
+            }
+            continue;
+        }
+        let path = name.trim_start_matches("./").trim_start_matches('/');
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        let (dir, name) = make_parents(root, path)?;
+        let permissions = (mode & 0o7777) as u16;
+        match mode & S_IFMT {
+            S_IFDIR => {
+                match dir.create(name, FileType::Directory, permissions) {
+                    Ok(_) => (),
+                    Err(FsError::AlreadyExists) => (),
+                    Err(error) => return Err(error),
+                }
+                stats.directories += 1;
+            }
+            S_IFREG => {
+                let file = match dir.create(name, FileType::Regular, permissions) {
+                    Err(FsError::AlreadyExists) => {
+                        let file = dir.lookup(name)?;
+                        file.truncate(0)?;
+                        file
+                    }
+                    file => file?,
+                };
+                file.write_at(0, data)?;
+                stats.files += 1;
+            }
+            S_IFLNK => {
+                let target = str::from_utf8(data).map_err(|_| FsError::Corrupted)?;
+                match dir.unlink(name) {
+                    Ok(()) | Err(FsError::NotFound) => (),
+                    Err(error) => return Err(error),
+                }
+                dir.symlink(name, target)?;
+                stats.symlinks += 1;
+            }
+            _ => stats.skipped += 1,
+        }
+    }
+}
+
+/// The directory that holds `path` and the last component, making the directories on the way.
+fn make_parents<'path>(
+    root: &Arc<dyn Inode>,
+    path: &'path str,
+) -> FsResult<(Arc<dyn Inode>, &'path str)> {
+    let mut components = path
+        .split('/')
+        .filter(|name| !name.is_empty() && *name != ".");
+    let mut name = components.next().ok_or(FsError::InvalidInput)?;
+    let mut dir = root.clone();
+    for next in components {
+        if name == ".." {
+            return Err(FsError::InvalidInput);
+        }
+        dir = match dir.lookup(name) {
+            Err(FsError::NotFound) => dir.create(name, FileType::Directory, 0o755)?,
+            child => child?,
+        };
+        name = next;
+    }
+    match name {
+        ".." => Err(FsError::InvalidInput),
+        _ => Ok((dir, name)),
+    }
+}
@@ -0,0 +1,223 @@
+use alloc::{string::String, sync::Arc};
+use core::time::Duration;
+
+use crate::drivers::block::BlockError;
+
+mod console;
+mod dentry;
+mod ext2;
+mod fat32;
+mod fd_table;
+mod file;
+pub mod initramfs;
+mod path;
+mod pipe;
+mod tmpfs;
+
+pub use console::ConsoleInode;
+pub use dentry::Dentry;
+pub use ext2::Ext2;
+pub use fat32::Fat32;
+pub use fd_table::{FdTable, MAX_FDS};
+pub use file::{File, OpenFlags, SeekFrom};
+pub use path::{
+    lookup, lookup_parent, mount, mounts, root, sync_all, unmount, MountInfo, MAX_SYMLINKS,
+};
+pub use pipe::pipe;
+pub use tmpfs::TmpFs;
+
+/// The longest file name a directory entry may have.
+pub const NAME_MAX: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    /// An argument makes no sense, like `..` as a name to create.
+    InvalidInput,
+    NameTooLong,
+    /// Too many symbolic links while resolving a path.
+    TooManySymlinks,
+    ReadOnly,
+    NoSpace,
+    /// The file system or the file does not support the operation.
+    Unsupported,
+    /// The operation spans two file systems.
+    CrossDevice,
+    /// The target is a mount point or otherwise in use.
+    Busy,
+    /// No data yet; a blocking caller waits and retries.
+    WouldBlock,
+    /// A write to a pipe whose read end is closed.
+    BrokenPipe,
+    /// The underlying data is malformed.
+    Corrupted,
+    Io,
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        match error {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            BlockError::OutOfMemory => FsError::NoSpace,
+            BlockError::OutOfRange => FsError::Corrupted,
+            BlockError::Misaligned | BlockError::Unsupported | BlockError::Io => FsError::Io,
+        }
+    }
+}
+
+pub type FsResult<T> = Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+/// What `stat` reports about an inode.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// Unique within its file system.
+    pub ino: u64,
+    pub file_type: FileType,
+    /// Permission bits, like `0o644`.
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// Preferred I/O size.
+    pub block_size: u32,
+    /// Allocated 512-byte units.
+    pub blocks: u64,
+    /// Access, modification and status change times since the Unix epoch.
+    pub atime: Duration,
+    pub mtime: Duration,
+    pub ctime: Duration,
+    /// Device number of a device file.
+    pub rdev: u64,
+}
+
+impl Metadata {
+    /// Metadata with the given identity and everything else zero.
+    pub fn new(ino: u64, file_type: FileType, mode: u16) -> Metadata {
+        Metadata {
+            ino,
+            file_type,
+            mode,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            block_size: 512,
+            blocks: 0,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
+            rdev: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub file_type: FileType,
+}
+
+/// A file, directory, symbolic link or device of some file system.
+///
+/// - Operations that do not apply to an inode's type fail with [`FsError::Unsupported`],
+///   [`FsError::IsADirectory`] or [`FsError::NotADirectory`] by default.
+/// - The VFS runs from system calls, with interrupts off, so implementations may take spin
+///   locks without masking interrupts themselves.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> FsResult<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Cut or zero-extend a regular file to `size` bytes.
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(FsError::IsADirectory)
+    }
+
+    /// The entry `name` of a directory, never `.` or `..`, which the VFS handles.
+    fn lookup(&self, _name: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a regular file or a directory named `name`.
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u16) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a symbolic link named `name` pointing to `target`.
+    fn symlink(&self, _name: &str, _target: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Remove the non-directory entry `name`.
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Remove the empty directory `name`.
+    fn rmdir(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// The directory entry at `index`, excluding `.` and `..`; `None` past the last one.
+    fn read_dir(&self, _index: usize) -> FsResult<Option<DirEntry>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// The target of a symbolic link.
+    fn read_link(&self) -> FsResult<String> {
+        Err(FsError::InvalidInput)
+    }
+
+    /// Write the inode's dirty data back.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Block the current task until a read would not fail with [`FsError::WouldBlock`].
+    ///
+    /// - Returns `false` without blocking if it would not anymore.
+    fn block_until_readable(&self) -> bool {
+        false
+    }
+
+    /// Block the current task until a write would not fail with [`FsError::WouldBlock`].
+    fn block_until_writable(&self) -> bool {
+        false
+    }
+}
+
+/// A mounted file system.
+pub trait FileSystem: Send + Sync {
+    /// The type name, like `tmpfs`.
+    fn name(&self) -> &str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write every dirty block back.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use spin::Mutex;
+
+use super::{Dentry, FileSystem, FileType, FsError, FsResult};
+
+/// How many symbolic links one path resolution may follow.
+pub const MAX_SYMLINKS: usize = 40;
+
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+    /// The directory the file system covers; `None` for the root file system.
+    mount_point: Option<Arc<Dentry>>,
+    root: Arc<Dentry>,
+}
+
+static ROOT: Mutex<Option<Arc<Dentry>>> = Mutex::new(None);
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    pub path: String,
+    pub fs_name: String,
+}
+
+/// The root directory, once a file system is mounted at `/`.
+pub fn root() -> FsResult<Arc<Dentry>> {
+    ROOT.lock().clone().ok_or(FsError::NotFound)
+}
+
+/// Resolve `path` starting at `start`, or at the root for absolute paths.
+///
+/// - `follow` says whether a symbolic link in the last component is followed; links in the
+///   other components always are.
+pub fn lookup(start: Option<Arc<Dentry>>, path: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+    let mut links = 0;
+    walk(start, path, follow, &mut links)
+}
+
+fn walk(
+    start: Option<Arc<Dentry>>,
+    path: &str,
+    follow: bool,
+    links: &mut usize,
+) -> FsResult<Arc<Dentry>> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    let mut dentry = match (path.starts_with('/'), start) {
+        (false, Some(start)) => start,
+        _ => root()?,
+    };
+    // A trailing slash asks for a directory, through a link if need be.
+    let follow = follow || path.ends_with('/');
+    let components: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+    for (i, name) in components.iter().enumerate() {
+        if !dentry.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        match *name {
+            "." => continue,
+            ".." => {
+                if let Some(parent) = dentry.parent() {
+                    dentry = parent.clone();
+                }
+                continue;
+            }
+            _ => (),
+        }
+        let child = dentry.lookup(name)?;
+        let is_last = i + 1 == components.len();
+        dentry = if child.file_type()? == FileType::Symlink && (follow || !is_last) {
+            *links += 1;
+            if *links > MAX_SYMLINKS {
+                return Err(FsError::TooManySymlinks);
+            }
+            let target = child.inode().read_link()?;
+            walk(Some(dentry), &target, true, links)?
+        } else {
+            child
+        };
+    }
+    if path.ends_with('/') && !dentry.is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+    Ok(dentry)
+}
+
+/// Resolve every component of `path` but the last, returning its directory and the last
+/// name, for operations that create or remove entries.
+pub fn lookup_parent(start: Option<Arc<Dentry>>, path: &str) -> FsResult<(Arc<Dentry>, String)> {
+    let trimmed = path.trim_end_matches('/');
+    let (dir, name) = match trimmed.rfind('/') {
+        Some(i) => (&trimmed[..=i], &trimmed[i + 1..]),
+        None => ("", trimmed),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(match path.starts_with('/') && name.is_empty() {
+            // `/` itself
+            true => FsError::Busy,
+            false => FsError::InvalidInput,
+        });
+    }
+    let dir = match dir {
+        "" => match start {
+            Some(start) => start,
+            None => root()?,
+        },
+        dir => lookup(start, dir, true)?,
+    };
+    if !dir.is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+    Ok((dir, name.to_string()))
+}
+
+/// Mount `fs` over the directory at `path`, or as the root file system at `/`.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult<()> {
+    let mut root = ROOT.lock();
+    let mount = match root.as_ref() {
+        None if path == "/" => {
+            let fs_root = Dentry::new("", fs.root(), None);
+            *root = Some(fs_root.clone());
+            Mount {
+                path: path.to_string(),
+                fs,
+                mount_point: None,
+                root: fs_root,
+            }
+        }
+        None => return Err(FsError::NotFound),
+        Some(_) => {
+            drop(root);
+            let mount_point = lookup(None, path, true)?;
+            if !mount_point.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            // `..` from the mount's root leads where it does from the covered directory.
+            let fs_root = Dentry::new(mount_point.name(), fs.root(), mount_point.parent().cloned());
+            mount_point.set_mounted(Some(fs_root.clone()));
+            Mount {
+                path: mount_point.path(),
+                fs,
+                mount_point: Some(mount_point),
+                root: fs_root,
+            }
+        }
+    };
+    MOUNTS.lock().push(mount);
+    Ok(())
+}
+
+/// Sync and detach the file system mounted at `path`.
+///
+/// - Fails with [`FsError::Busy`] while something else holds its root, such as an open file
+///   below it, or a file system is mounted inside it.
+pub fn unmount(path: &str) -> FsResult<()> {
+    let target = lookup(None, path, true)?;
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| Arc::ptr_eq(&mount.root, &target))
+        .ok_or(FsError::InvalidInput)?;
+    // Held by the mount table, its mount point and `target`.
+    if Arc::strong_count(&target) > 3 {
+        return Err(FsError::Busy);
+    }
+    let mount = &mounts[index];
+    let Some(mount_point) = &mount.mount_point else {
+        return Err(FsError::Busy);
+    };
+    mount.fs.sync()?;
+    mount_point.set_mounted(None);
+    mounts.remove(index);
+    Ok(())
+}
+
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| MountInfo {
+            path: mount.path.clone(),
+            fs_name: mount.fs.name().to_string(),
+        })
+        .collect()
+}
+
+/// Write every mounted file system back.
+pub fn sync_all() -> FsResult<()> {
+    let file_systems: Vec<Arc<dyn FileSystem>> =
+        MOUNTS.lock().iter().map(|mount| mount.fs.clone()).collect();
+    file_systems.iter().try_for_each(|fs| fs.sync())
+}
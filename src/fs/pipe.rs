@@ -0,0 +1,149 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::task::WaitQueue;
+
+use super::{FileType, FsError, FsResult, Inode, Metadata};
+
+/// Bytes a pipe buffers before writers block, as on Linux.
+const PIPE_CAPACITY: usize = 16 * 4096;
+/// Writes up to this size go in whole or not at all, so they never interleave.
+const PIPE_BUF: usize = 4096;
+
+/// Numbers pipes for `stat`; they live on no file system.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+struct State {
+    data: VecDeque<u8>,
+    /// Whether each end still has a file open on it.
+    has_reader: bool,
+    has_writer: bool,
+}
+
+impl State {
+    fn free(&self) -> usize {
+        PIPE_CAPACITY - self.data.len()
+    }
+}
+
+/// A bounded byte queue from a write end to a read end.
+struct Pipe {
+    ino: u64,
+    state: Mutex<State>,
+    /// Readers waiting for data or for the last writer to go.
+    readers: WaitQueue,
+    /// Writers waiting for room or for the last reader to go.
+    writers: WaitQueue,
+}
+
+impl Pipe {
+    fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new(self.ino, FileType::Fifo, 0o600);
+        metadata.block_size = PIPE_BUF as u32;
+        metadata
+    }
+}
+
+/// A new pipe's read end and write end.
+///
+/// - A read with nothing buffered is [`FsError::WouldBlock`], or the end of file once the write
+///   end is gone.
+/// - A write with no room is [`FsError::WouldBlock`], and [`FsError::BrokenPipe`] once the read
+///   end is gone. A write larger than [`PIPE_BUF`] may go in part; the `write` system call
+///   blocks for the rest unless the file is non-blocking.
+/// - An end closes when the last file on it is dropped.
+pub fn pipe() -> (Arc<dyn Inode>, Arc<dyn Inode>) {
+    let pipe = Arc::new(Pipe {
+        ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+        state: Mutex::new(State {
+            data: VecDeque::new(),
+            has_reader: true,
+            has_writer: true,
+        }),
+        readers: WaitQueue::new(),
+        writers: WaitQueue::new(),
+    });
+    (Arc::new(ReadEnd(pipe.clone())), Arc::new(WriteEnd(pipe)))
+}
+
+struct ReadEnd(Arc<Pipe>);
+
+impl Inode for ReadEnd {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(self.0.metadata())
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let mut state = self.0.state.lock();
+        if state.data.is_empty() && !buf.is_empty() {
+            return match state.has_writer {
+                true => Err(FsError::WouldBlock),
+                false => Ok(0),
+            };
+        }
+        let len = buf.len().min(state.data.len());
+        for (dest, byte) in buf.iter_mut().zip(state.data.drain(..len)) {
+            *dest = byte;
+        }
+        drop(state);
+        self.0.writers.wake_all();
+        Ok(len)
+    }
+
+    fn block_until_readable(&self) -> bool {
+        self.0.readers.block_if(|| {
+            let state = self.0.state.lock();
+            state.data.is_empty() && state.has_writer
+        })
+    }
+}
+
+impl Drop for ReadEnd {
+    fn drop(&mut self) {
+        self.0.state.lock().has_reader = false;
+        self.0.writers.wake_all();
+    }
+}
+
+struct WriteEnd(Arc<Pipe>);
+
+impl Inode for WriteEnd {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(self.0.metadata())
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> FsResult<usize> {
+        let mut state = self.0.state.lock();
+        if !state.has_reader {
+            return Err(FsError::BrokenPipe);
+        }
+        let free = state.free();
+        let len = match data.len() <= PIPE_BUF {
+            true if free < data.len() => return Err(FsError::WouldBlock),
+            true => data.len(),
+            false if free == 0 => return Err(FsError::WouldBlock),
+            false => data.len().min(free),
+        };
+        state.data.extend(&data[..len]);
+        drop(state);
+        self.0.readers.wake_all();
+        Ok(len)
+    }
+
+    fn block_until_writable(&self) -> bool {
+        // Less than `PIPE_BUF` free is what fails a write, whatever its size.
+        self.0.writers.block_if(|| {
+            let state = self.0.state.lock();
+            state.free() < PIPE_BUF && state.has_reader
+        })
+    }
+}
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        self.0.state.lock().has_writer = false;
+        self.0.readers.wake_all();
+    }
+}
@@ -0,0 +1,281 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    clock,
+    mm::{frame::Frame, PAGE_SIZE},
+};
+
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata, NAME_MAX};
+
+/// A file system that lives in RAM and is gone on reboot.
+///
+/// - File data is kept in frames, one per page written, so holes cost nothing.
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<TmpFs> {
+        let next_ino = Arc::new(AtomicU64::new(1));
+        let root = TmpInode::new(&next_ino, FileType::Directory, 0o755, Content::directory());
+        Arc::new(TmpFs { root })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+enum Content {
+    Regular(Vec<Option<Frame>>),
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+}
+
+impl Content {
+    fn directory() -> Content {
+        Content::Directory(BTreeMap::new())
+    }
+}
+
+struct State {
+    metadata: Metadata,
+    content: Content,
+}
+
+struct TmpInode {
+    /// Shared by every inode of the file system, to number new ones.
+    next_ino: Arc<AtomicU64>,
+    state: Mutex<State>,
+}
+
+impl TmpInode {
+    fn new(
+        next_ino: &Arc<AtomicU64>,
+        file_type: FileType,
+        mode: u16,
+        content: Content,
+    ) -> Arc<TmpInode> {
+        let ino = next_ino.fetch_add(1, Ordering::Relaxed);
+        let mut metadata = Metadata::new(ino, file_type, mode);
+        metadata.block_size = PAGE_SIZE as u32;
+        let now = clock::realtime();
+        metadata.atime = now;
+        metadata.mtime = now;
+        metadata.ctime = now;
+        if let Content::Symlink(target) = &content {
+            metadata.size = target.len() as u64;
+        }
+        Arc::new(TmpInode {
+            next_ino: next_ino.clone(),
+            state: Mutex::new(State { metadata, content }),
+        })
+    }
+
+    /// Add `name` to the directory, made by `make` unless the name is taken.
+    fn add_entry(
+        &self,
+        name: &str,
+        make: impl FnOnce() -> Arc<TmpInode>,
+    ) -> FsResult<Arc<dyn Inode>> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidInput);
+        }
+        if name.len() > NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
+        let mut state = self.state.lock();
+        let Content::Directory(entries) = &mut state.content else {
+            return Err(FsError::NotADirectory);
+        };
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let inode = make();
+        entries.insert(name.to_string(), inode.clone());
+        touch(&mut state.metadata);
+        Ok(inode)
+    }
+
+    /// Remove the entry `name`, after `check` accepts its inode.
+    fn remove_entry(&self, name: &str, check: impl FnOnce(&State) -> FsResult<()>) -> FsResult<()> {
+        let mut state = self.state.lock();
+        let Content::Directory(entries) = &mut state.content else {
+            return Err(FsError::NotADirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+        check(&inode.state.lock())?;
+        entries.remove(name);
+        touch(&mut state.metadata);
+        Ok(())
+    }
+}
+
+fn touch(metadata: &mut Metadata) {
+    let now = clock::realtime();
+    metadata.mtime = now;
+    metadata.ctime = now;
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let state = self.state.lock();
+        let mut metadata = state.metadata.clone();
+        if let Content::Regular(pages) = &state.content {
+            let resident = pages.iter().filter(|page| page.is_some()).count();
+            metadata.blocks = (resident * PAGE_SIZE / 512) as u64;
+        }
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let state = self.state.lock();
+        let Content::Regular(pages) = &state.content else {
+            return Err(FsError::IsADirectory);
+        };
+        let size = state.metadata.size;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let mut done = 0;
+        while done < len {
+            let position = offset as usize + done;
+            let start = position % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - start).min(len - done);
+            let dest = &mut buf[done..done + chunk];
+            match &pages[position / PAGE_SIZE] {
+                Some(frame) => dest.copy_from_slice(&frame.as_bytes()[start..start + chunk]),
+                None => dest.fill(0),
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        let State { metadata, content } = &mut *state;
+        let Content::Regular(pages) = content else {
+            return Err(FsError::IsADirectory);
+        };
+        let end = offset as usize + data.len();
+        if pages.len() < end.div_ceil(PAGE_SIZE) {
+            pages.resize_with(end.div_ceil(PAGE_SIZE), || None);
+        }
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset as usize + done;
+            let start = position % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - start).min(data.len() - done);
+            let page = &mut pages[position / PAGE_SIZE];
+            if page.is_none() {
+                *page = Some(Frame::alloc().ok_or(FsError::NoSpace)?);
+            }
+            let frame = page.as_ref().unwrap();
+            frame.as_bytes_mut()[start..start + chunk].copy_from_slice(&data[done..done + chunk]);
+            done += chunk;
+            metadata.size = metadata.size.max((position + chunk) as u64);
+        }
+        touch(metadata);
+        Ok(data.len())
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        let mut state = self.state.lock();
+        let State { metadata, content } = &mut *state;
+        let Content::Regular(pages) = content else {
+            return Err(FsError::IsADirectory);
+        };
+        let size = size as usize;
+        if (size as u64) < metadata.size {
+            pages.truncate(size.div_ceil(PAGE_SIZE));
+            // Growing again must read zeros past the cut.
+            if let Some(Some(frame)) = pages.get(size / PAGE_SIZE) {
+                frame.as_bytes_mut()[size % PAGE_SIZE..].fill(0);
+            }
+        }
+        metadata.size = size as u64;
+        touch(metadata);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let state = self.state.lock();
+        let Content::Directory(entries) = &state.content else {
+            return Err(FsError::NotADirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+        Ok(inode.clone())
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>> {
+        let content = match file_type {
+            FileType::Regular => Content::Regular(Vec::new()),
+            FileType::Directory => Content::directory(),
+            _ => return Err(FsError::Unsupported),
+        };
+        self.add_entry(name, || {
+            TmpInode::new(&self.next_ino, file_type, mode, content)
+        })
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        self.add_entry(name, || {
+            let content = Content::Symlink(target.to_string());
+            TmpInode::new(&self.next_ino, FileType::Symlink, 0o777, content)
+        })
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.remove_entry(name, |state| match state.content {
+            Content::Directory(_) => Err(FsError::IsADirectory),
+            _ => Ok(()),
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.remove_entry(name, |state| match &state.content {
+            Content::Directory(entries) if entries.is_empty() => Ok(()),
+            Content::Directory(_) => Err(FsError::DirectoryNotEmpty),
+            _ => Err(FsError::NotADirectory),
+        })
+    }
+
+    fn read_dir(&self, index: usize) -> FsResult<Option<DirEntry>> {
+        let state = self.state.lock();
+        let Content::Directory(entries) = &state.content else {
+            return Err(FsError::NotADirectory);
+        };
+        let Some((name, inode)) = entries.iter().nth(index) else {
+            return Ok(None);
+        };
+        let metadata = &inode.state.lock().metadata;
+        Ok(Some(DirEntry {
+            name: name.clone(),
+            ino: metadata.ino,
+            file_type: metadata.file_type,
+        }))
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        match &self.state.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidInput),
+        }
+    }
+}
@@ -0,0 +1,460 @@
+mod transport;
+
+pub use transport::{SbiConsole, Transport};
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+    fmt::Write,
+    str,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{
+    debug,
+    exception::ExceptionMutContext,
+    task::{self, TaskId},
+};
+
+/// `x0`..`x31` followed by `pc`, as GDB numbers them for RISC-V.
+const PC: usize = 32;
+const REGISTER_COUNT: usize = 33;
+/// `SIGTRAP`
+const STOP_SIGNAL: u8 = 5;
+/// `EFAULT`
+const MEMORY_ERROR: &str = "E0e";
+const INVALID_ERROR: &str = "E16";
+const PACKET_SIZE: usize = 0x1000;
+
+lazy_static! {
+    static ref TRANSPORT: Mutex<Option<Box<dyn Transport>>> = Mutex::new(None);
+}
+
+/// GDB resumed the kernel with `c` or `s` and is waiting for a stop reply.
+static IS_RESUMED: AtomicBool = AtomicBool::new(false);
+
+/// Hand `ebreak` traps to a GDB on the other end of `transport`.
+///
+/// - Nothing is read until the next `ebreak`, where GDB finds the kernel stopped.
+pub fn attach(transport: Box<dyn Transport>) {
+    IS_RESUMED.store(false, Ordering::Relaxed);
+    *TRANSPORT.lock() = Some(transport);
+}
+
+pub fn is_attached() -> bool {
+    TRANSPORT.lock().is_some()
+}
+
+/// GDB thread IDs start at 1, as 0 and -1 mean "any" and "all".
+fn thread_id(id: TaskId) -> usize {
+    id.0 + 1
+}
+
+/// The task behind a thread ID from GDB, `None` for "any" or "all".
+fn task_id(thread: &str) -> Option<TaskId> {
+    match thread {
+        "0" | "-1" => None,
+        thread => parse_hex(thread)
+            .filter(|thread| *thread > 0)
+            .map(|thread| TaskId(thread - 1)),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn decode_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Little-endian hex, as RSP encodes registers.
+fn push_register(reply: &mut String, value: usize) {
+    for byte in value.to_le_bytes() {
+        let _ = write!(reply, "{:02x}", byte);
+    }
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    let bytes: [u8; 8] = decode_hex_bytes(text)?.try_into().ok()?;
+    Some(usize::from_le_bytes(bytes))
+}
+
+/// `addr,len` from `m`, `M`, `Z` and `z` packets.
+fn parse_address_and_len(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+}
+
+struct Session<'stub, 'context, 'entry> {
+    transport: &'stub mut dyn Transport,
+    mut_context: &'context mut ExceptionMutContext<'entry>,
+    current: TaskId,
+    /// The thread `g`, `G`, `p` and `P` refer to, set by `Hg`.
+    selected: TaskId,
+}
+
+impl Session<'_, '_, '_> {
+    fn receive(&mut self, packet: &mut Vec<u8>) {
+        loop {
+            packet.clear();
+            while self.transport.read_byte() != b'$' {}
+            let mut checksum = 0u8;
+            loop {
+                let byte = self.transport.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(byte);
+                packet.push(byte);
+            }
+            let high = self.transport.read_byte();
+            let low = self.transport.read_byte();
+            let expected = str::from_utf8(&[high, low])
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+            if expected == Some(checksum) {
+                self.transport.write_byte(b'+');
+                return;
+            }
+            self.transport.write_byte(b'-');
+        }
+    }
+
+    fn send(&mut self, reply: &str) {
+        let checksum = reply
+            .bytes()
+            .fold(0u8, |checksum, byte| checksum.wrapping_add(byte));
+        loop {
+            self.transport.write_byte(b'$');
+            for byte in reply.bytes() {
+                self.transport.write_byte(byte);
+            }
+            self.transport.write_byte(b'#');
+            for digit in [checksum >> 4, checksum & 0xf] {
+                self.transport
+                    .write_byte(char::from_digit(digit as u32, 16).unwrap() as u8);
+            }
+            // Anything other than an ack or a nack is line noise.
+            loop {
+                match self.transport.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn stop_reply(&self) -> String {
+        let mut reply = String::new();
+        let _ = write!(
+            reply,
+            "T{:02x}thread:{:x};",
+            STOP_SIGNAL,
+            thread_id(self.current)
+        );
+        reply
+    }
+
+    fn read_register(&self, index: usize) -> Option<usize> {
+        if self.selected == self.current {
+            return match index {
+                PC => Some(self.mut_context.sepc),
+                0..PC => Some(self.mut_context.register_context.get(index)),
+                _ => None,
+            };
+        }
+        task::with_task(self.selected, |task| match index {
+            PC => Some(task.context.sepc),
+            0..PC => Some(task.context.registers.get(index)),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    fn write_register(&mut self, index: usize, value: usize) -> Option<()> {
+        if self.selected == self.current {
+            match index {
+                PC => self.mut_context.sepc = value,
+                0..PC => self.mut_context.register_context.set(index, value),
+                _ => return None,
+            }
+            return Some(());
+        }
+        task::with_task(self.selected, |task| {
+            match index {
+                PC => task.context.sepc = value,
+                0..PC => task.context.registers.set(index, value),
+                _ => return None,
+            }
+            Some(())
+        })
+        .flatten()
+    }
+
+    fn threads(&self) -> String {
+        let mut reply = String::from("m");
+        task::for_each_task(|id, _, _| {
+            if reply.len() > 1 {
+                reply.push(',');
+            }
+            let _ = write!(reply, "{:x}", thread_id(id));
+        });
+        reply
+    }
+
+    fn thread_extra_info(&self, thread: &str) -> String {
+        let mut info = String::new();
+        task::for_each_task(|id, task, _| {
+            if Some(id) != task_id(thread) {
+                return;
+            }
+            let state = task.state.name();
+            let space = match task.address_space {
+                Some(_) => "user",
+                None => "flat",
+            };
+            let _ = write!(info, "{}, {}", state, space);
+        });
+        let mut reply = String::new();
+        for byte in info.bytes() {
+            let _ = write!(reply, "{:02x}", byte);
+        }
+        reply
+    }
+
+    fn is_alive(&self, id: TaskId) -> bool {
+        task::with_task(id, |_| ()).is_some()
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = parse_address_and_len(args)?;
+        let mut reply = String::new();
+        for addr in addr..addr + len.min(PACKET_SIZE / 2) {
+            let _ = write!(reply, "{:02x}", debug::read_byte(addr)?);
+        }
+        Some(reply)
+    }
+
+    fn write_memory(&self, args: &str) -> Option<()> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = parse_address_and_len(range)?;
+        let data = decode_hex_bytes(data).filter(|data| data.len() == len)?;
+        if (addr..addr + len).any(|addr| debug::translate(addr).is_none()) {
+            return None;
+        }
+        for (i, byte) in data.iter().enumerate() {
+            debug::write_byte(addr + i, *byte)?;
+        }
+        debug::fence_i();
+        Some(())
+    }
+
+    /// `Z0`/`z0`: software breakpoints; other kinds are left to GDB.
+    fn breakpoint(&self, args: &str, insert: bool) -> Option<&'static str> {
+        let (kind, args) = args.split_once(',')?;
+        if kind != "0" {
+            return Some("");
+        }
+        let (addr, len) = parse_address_and_len(args.split(';').next()?)?;
+        let result = match insert {
+            true if len == 2 || len == 4 => debug::insert_breakpoint(addr, len),
+            true => return Some(INVALID_ERROR),
+            false => debug::remove_breakpoint(addr),
+        };
+        Some(match result {
+            Some(()) => "OK",
+            None => MEMORY_ERROR,
+        })
+    }
+
+    /// Answer packets until GDB resumes or detaches.
+    fn serve(&mut self) -> Resume {
+        let mut packet = Vec::new();
+        loop {
+            self.receive(&mut packet);
+            let Ok(packet) = str::from_utf8(&packet) else {
+                self.send("");
+                continue;
+            };
+            let (command, args) = packet.split_at(packet.len().min(1));
+            let reply = match command {
+                "?" => self.stop_reply(),
+                "g" => {
+                    let mut reply = String::new();
+                    for index in 0..REGISTER_COUNT {
+                        push_register(&mut reply, self.read_register(index).unwrap_or(0));
+                    }
+                    reply
+                }
+                "G" => {
+                    let values: Option<Vec<usize>> = (0..REGISTER_COUNT)
+                        .map(|index| parse_register(args.get(index * 16..(index + 1) * 16)?))
+                        .collect();
+                    let is_written = values.is_some_and(|values| {
+                        values
+                            .iter()
+                            .enumerate()
+                            .all(|(index, value)| self.write_register(index, *value).is_some())
+                    });
+                    String::from(if is_written { "OK" } else { INVALID_ERROR })
+                }
+                "p" => match parse_hex(args).and_then(|index| self.read_register(index)) {
+                    Some(value) => {
+                        let mut reply = String::new();
+                        push_register(&mut reply, value);
+                        reply
+                    }
+                    // Floating-point and CSR registers are unavailable.
+                    None => String::from("xxxxxxxxxxxxxxxx"),
+                },
+                "P" => {
+                    let is_written = args.split_once('=').is_some_and(|(index, value)| {
+                        parse_hex(index)
+                            .zip(parse_register(value))
+                            .and_then(|(index, value)| self.write_register(index, value))
+                            .is_some()
+                    });
+                    String::from(if is_written { "OK" } else { INVALID_ERROR })
+                }
+                "m" => self
+                    .read_memory(args)
+                    .unwrap_or_else(|| String::from(MEMORY_ERROR)),
+                "M" => String::from(match self.write_memory(args) {
+                    Some(()) => "OK",
+                    None => MEMORY_ERROR,
+                }),
+                "Z" | "z" => String::from(self.breakpoint(args, command == "Z").unwrap_or("")),
+                "H" => {
+                    let (operation, thread) = args.split_at(args.len().min(1));
+                    match (operation, task_id(thread)) {
+                        ("g", Some(id)) if self.is_alive(id) => {
+                            self.selected = id;
+                            String::from("OK")
+                        }
+                        ("g", Some(_)) => String::from(INVALID_ERROR),
+                        ("g", None) => {
+                            self.selected = self.current;
+                            String::from("OK")
+                        }
+                        // `c` and `s` always resume the whole kernel.
+                        _ => String::from("OK"),
+                    }
+                }
+                "T" => String::from(match task_id(args) {
+                    Some(id) if self.is_alive(id) => "OK",
+                    _ => INVALID_ERROR,
+                }),
+                "c" | "s" => {
+                    if let Some(addr) = parse_hex(args) {
+                        self.mut_context.sepc = addr;
+                    }
+                    return if command == "c" {
+                        Resume::Continue
+                    } else {
+                        Resume::Step
+                    };
+                }
+                "D" => {
+                    self.send("OK");
+                    return Resume::Detach;
+                }
+                "k" => return Resume::Detach,
+                "q" => match packet.split(':').next().unwrap_or(packet) {
+                    "qSupported" => {
+                        let mut reply = String::new();
+                        let _ = write!(reply, "PacketSize={:x}", PACKET_SIZE);
+                        reply
+                    }
+                    "qAttached" => String::from("1"),
+                    "qC" => {
+                        let mut reply = String::new();
+                        let _ = write!(reply, "QC{:x}", thread_id(self.current));
+                        reply
+                    }
+                    "qfThreadInfo" => self.threads(),
+                    "qsThreadInfo" => String::from("l"),
+                    query => match query.strip_prefix("qThreadExtraInfo,") {
+                        Some(thread) => self.thread_extra_info(thread),
+                        None => String::new(),
+                    },
+                },
+                // Unsupported packets get an empty reply.
+                _ => String::new(),
+            };
+            self.send(&reply);
+        }
+    }
+}
+
+/// Stop in the debugger on `ebreak`, returning `false` if no GDB is attached.
+///
+/// - Registers of other tasks are their saved contexts; memory is seen through the current page
+///   table, so user processes are debugged while one of their threads is stopped.
+/// - `s` plants step breakpoints through `debug`, as S-mode has no hardware single-step.
+pub fn handle_breakpoint(mut_context: &mut ExceptionMutContext) -> bool {
+    let mut transport = TRANSPORT.lock();
+    let Some(stream) = transport.as_mut() else {
+        return false;
+    };
+    let current = task::current_id();
+    let mut session = Session {
+        transport: stream.as_mut(),
+        mut_context: &mut *mut_context,
+        current,
+        selected: current,
+    };
+    if IS_RESUMED.swap(false, Ordering::Relaxed) {
+        let reply = session.stop_reply();
+        session.send(&reply);
+    }
+
+    let resume = loop {
+        match session.serve() {
+            Resume::Step => {
+                // Stepping over an `ebreak` in the code needs no breakpoint.
+                if let Some(len) = debug::compiled_ebreak_len(session.mut_context.sepc) {
+                    session.mut_context.sepc += len;
+                    let reply = session.stop_reply();
+                    session.send(&reply);
+                    continue;
+                }
+                match debug::plant_step_breakpoints(session.mut_context) {
+                    Ok(()) => break Resume::Step,
+                    Err(_) => session.send(MEMORY_ERROR),
+                }
+            }
+            resume => break resume,
+        }
+    };
+
+    // An `ebreak` in the code is skipped rather than trapping again.
+    if !matches!(resume, Resume::Step) {
+        if let Some(len) = debug::compiled_ebreak_len(mut_context.sepc) {
+            mut_context.sepc += len;
+        }
+    }
+    match resume {
+        Resume::Continue | Resume::Step => IS_RESUMED.store(true, Ordering::Relaxed),
+        Resume::Detach => {
+            debug::remove_all_breakpoints();
+            *transport = None;
+        }
+    }
+    true
+}
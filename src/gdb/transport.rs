@@ -0,0 +1,66 @@
+use core::hint::spin_loop;
+
+use crate::{
+    drivers::{uart::Uart, virtio::console::VirtioConsole},
+    sbi_call::{self, LegacyExtension},
+};
+
+/// A byte stream to the debugger.
+///
+/// - The stub only runs inside a trap with interrupts off, so transports poll.
+pub trait Transport: Send {
+    /// Wait for the next byte from the debugger.
+    fn read_byte(&mut self) -> u8;
+    fn write_byte(&mut self, byte: u8);
+}
+
+/// The firmware console.
+///
+/// - Shared with the kernel log, so use it only when nothing else prints while GDB is attached;
+///   a [`Uart`] or [`VirtioConsole`] of its own is the intended transport.
+pub struct SbiConsole;
+
+impl Transport for SbiConsole {
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = sbi_call::console_getchar() {
+                return byte;
+            }
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        let _ = sbi_call::legacy_sbi_call(&LegacyExtension::ConsolePutChar { ch: byte });
+    }
+}
+
+/// An NS16550A other than the console, like the one `drivers::uart::find_spare` finds.
+impl Transport for Uart {
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = Uart::read_byte(self) {
+                return byte;
+            }
+            spin_loop();
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.write_bytes(&[byte]);
+    }
+}
+
+impl Transport for VirtioConsole {
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = VirtioConsole::read_byte(self) {
+                return byte;
+            }
+            spin_loop();
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.write_bytes(&[byte]);
+    }
+}
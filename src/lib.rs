@@ -1,8 +1,24 @@
 #![no_std] // don't link the Rust standard library
 
+extern crate alloc;
+
+pub mod backtrace;
+pub mod boot_options;
+pub mod clock;
 pub mod console;
+pub mod debug;
+pub mod device_tree;
+pub mod drivers;
 pub mod exception;
+pub mod fs;
+pub mod gdb;
+pub mod mm;
+pub mod monitor;
+pub mod panicking;
 pub mod sbi_call;
+pub mod syscall;
+pub mod task;
+pub mod timer;
 
 use core::{fmt, panic::PanicInfo};
 
@@ -10,8 +26,7 @@ use core::{fmt, panic::PanicInfo};
 /// - `!` means this function never returns.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    supervisor_println!("{}", info);
-    sbi_call::shutdown();
+    panicking::handle_panic(info)
 }
 
 pub struct Sstatus(pub usize);
@@ -37,6 +52,20 @@ impl Sstatus {
         let spp = (self.0 >> 8) & 1;
         Spp::from(spp)
     }
+
+    /// SPIE
+    pub fn set_interrupt_enabled_before_exception(&mut self, enabled: bool) {
+        self.0 = self.0 & !(1 << 5) | (enabled as usize) << 5;
+    }
+
+    /// SPP
+    pub fn set_mode_before_exception(&mut self, spp: Spp) {
+        let spp = match spp {
+            Spp::User => 0,
+            Spp::Supervisor => 1,
+        };
+        self.0 = self.0 & !(1 << 8) | spp << 8;
+    }
 }
 
 impl fmt::Debug for Sstatus {
@@ -54,7 +83,7 @@ impl fmt::Debug for Sstatus {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Spp {
     Supervisor,
     User,
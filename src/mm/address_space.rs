@@ -0,0 +1,353 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::{
+    frame::Frame,
+    page_ceil, page_floor,
+    page_table::{flush_tlb, MapError, PageTable, PteFlags},
+    PAGE_SIZE, PAGE_SIZE_BITS,
+};
+
+/// One past the highest user address in Sv39.
+pub const USER_END: usize = 0x40_0000_0000;
+
+/// Address ranges taken by the kernel's global mappings in every [`PageTable`].
+const KERNEL_WINDOWS: [(usize, usize); 2] = [(0x0, 0x4000_0000), (0x8000_0000, 0xc000_0000)];
+
+/// Whether `start..end` is free for user mappings.
+pub fn is_user_range(start: usize, end: usize) -> bool {
+    start < end
+        && end <= USER_END
+        && KERNEL_WINDOWS
+            .iter()
+            .all(|(kernel_start, kernel_end)| end <= *kernel_start || *kernel_end <= start)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    /// Zero-filled on first touch.
+    Anonymous,
+    /// Zero-filled on first touch, and grows down on faults below `start` until `limit`.
+    Stack { limit: usize },
+}
+
+/// A virtual memory area: a page-aligned range of user addresses sharing permissions.
+#[derive(Debug)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    /// Subset of `R`, `W` and `X`.
+    pub flags: PteFlags,
+    pub kind: VmaKind,
+    /// Resident pages, keyed by VPN.
+    pages: BTreeMap<usize, Frame>,
+}
+
+impl Vma {
+    fn new(start: usize, end: usize, flags: PteFlags, kind: VmaKind) -> Self {
+        Vma {
+            start,
+            end,
+            flags,
+            kind,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn permits(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.flags.contains(PteFlags::R),
+            Access::Write => self.flags.contains(PteFlags::W),
+            Access::Execute => self.flags.contains(PteFlags::X),
+        }
+    }
+
+    pub fn resident_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn pte_flags(&self) -> PteFlags {
+        self.flags | PteFlags::U
+    }
+}
+
+#[derive(Debug)]
+pub enum VmaError {
+    OutOfRange,
+    Overlap,
+    OutOfMemory,
+}
+
+impl From<MapError> for VmaError {
+    fn from(error: MapError) -> Self {
+        match error {
+            MapError::OutOfMemory => VmaError::OutOfMemory,
+            MapError::AlreadyMapped => VmaError::Overlap,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// No VMA covers the address.
+    NotMapped,
+    /// A VMA covers the address but forbids the access.
+    AccessDenied,
+    OutOfMemory,
+}
+
+/// The user half of a process: a page table plus the VMAs that describe it.
+///
+/// - Pages are populated lazily by [`AddressSpace::handle_page_fault`].
+pub struct AddressSpace {
+    page_table: PageTable,
+    vmas: Vec<Vma>,
+}
+
+impl AddressSpace {
+    pub fn new() -> Option<Self> {
+        Some(AddressSpace {
+            page_table: PageTable::new()?,
+            vmas: Vec::new(),
+        })
+    }
+
+    pub fn satp(&self) -> usize {
+        self.page_table.satp()
+    }
+
+    pub fn vmas(&self) -> &[Vma] {
+        &self.vmas
+    }
+
+    fn insert_vma(&mut self, vma: Vma) -> Result<(), VmaError> {
+        let limit = match vma.kind {
+            VmaKind::Stack { limit } => limit,
+            VmaKind::Anonymous => vma.start,
+        };
+        if !is_user_range(limit, vma.end) {
+            return Err(VmaError::OutOfRange);
+        }
+        if self
+            .vmas
+            .iter()
+            .any(|other| limit < other.end && other.start < vma.end)
+        {
+            return Err(VmaError::Overlap);
+        }
+        let index = self.vmas.partition_point(|other| other.start < vma.start);
+        self.vmas.insert(index, vma);
+        Ok(())
+    }
+
+    /// Reserve zero-filled memory, populated on first touch.
+    pub fn map_anonymous(
+        &mut self,
+        start: usize,
+        len: usize,
+        flags: PteFlags,
+    ) -> Result<(), VmaError> {
+        let vma = Vma::new(
+            page_floor(start),
+            page_ceil(start + len),
+            flags,
+            VmaKind::Anonymous,
+        );
+        self.insert_vma(vma)
+    }
+
+    /// Reserve a stack of `size` bytes below `top` that may grow down to `max_size` bytes.
+    pub fn map_stack(&mut self, top: usize, size: usize, max_size: usize) -> Result<(), VmaError> {
+        let top = page_ceil(top);
+        let vma = Vma::new(
+            top - page_ceil(size),
+            top,
+            PteFlags::R | PteFlags::W,
+            VmaKind::Stack {
+                limit: top - page_ceil(max_size),
+            },
+        );
+        self.insert_vma(vma)
+    }
+
+    /// Map `data` at `start` eagerly, zero-filling the rest of the last page.
+    pub fn map_data(&mut self, start: usize, data: &[u8], flags: PteFlags) -> Result<(), VmaError> {
+        let mut vma = Vma::new(
+            page_floor(start),
+            page_ceil(start + data.len()),
+            flags,
+            VmaKind::Anonymous,
+        );
+        if !is_user_range(vma.start, vma.end) {
+            return Err(VmaError::OutOfRange);
+        }
+        let mut offset = start - vma.start;
+        let mut data = data;
+        for vpn in (vma.start >> PAGE_SIZE_BITS)..(vma.end >> PAGE_SIZE_BITS) {
+            let frame = Frame::alloc().ok_or(VmaError::OutOfMemory)?;
+            let len = data.len().min(PAGE_SIZE - offset);
+            frame.as_bytes_mut()[offset..offset + len].copy_from_slice(&data[..len]);
+            data = &data[len..];
+            offset = 0;
+            vma.pages.insert(vpn, frame);
+        }
+        let pte_flags = vma.pte_flags();
+        let pages: Vec<(usize, usize)> = vma
+            .pages
+            .iter()
+            .map(|(vpn, frame)| (*vpn, frame.ppn()))
+            .collect();
+        self.insert_vma(vma)?;
+        for (vpn, ppn) in pages {
+            self.page_table.map(vpn, ppn, pte_flags)?;
+        }
+        Ok(())
+    }
+
+    fn find_vma(&self, addr: usize) -> Option<usize> {
+        self.vmas.iter().position(|vma| vma.contains(addr))
+    }
+
+    /// Find the stack that may grow down to cover `addr`, extending it.
+    fn grow_stack(&mut self, addr: usize) -> Option<usize> {
+        let index = self.vmas.iter().position(|vma| match vma.kind {
+            VmaKind::Stack { limit } => limit <= addr && addr < vma.start,
+            VmaKind::Anonymous => false,
+        })?;
+        let new_start = page_floor(addr);
+        if index > 0 && self.vmas[index - 1].end > new_start {
+            return None;
+        }
+        self.vmas[index].start = new_start;
+        Some(index)
+    }
+
+    /// Resolve a page fault at `addr` from the VMA list.
+    ///
+    /// - Untouched pages of anonymous memory and stacks are allocated zero-filled.
+    /// - Stacks grow down on faults between their start and their limit.
+    /// - Writes to copy-on-write pages get a private copy, or reclaim the page if it is no
+    ///   longer shared.
+    pub fn handle_page_fault(&mut self, addr: usize, access: Access) -> Result<(), PageFaultError> {
+        let index = self
+            .find_vma(addr)
+            .or_else(|| self.grow_stack(addr))
+            .ok_or(PageFaultError::NotMapped)?;
+        let vma = &mut self.vmas[index];
+        if !vma.permits(access) {
+            return Err(PageFaultError::AccessDenied);
+        }
+
+        let vpn = addr >> PAGE_SIZE_BITS;
+        let pte = match self.page_table.entry(vpn) {
+            Some(pte) => pte,
+            None => {
+                let frame = Frame::alloc().ok_or(PageFaultError::OutOfMemory)?;
+                self.page_table
+                    .map(vpn, frame.ppn(), vma.pte_flags())
+                    .map_err(|_| PageFaultError::OutOfMemory)?;
+                vma.pages.insert(vpn, frame);
+                return Ok(());
+            }
+        };
+
+        if access == Access::Write && pte.flags().contains(PteFlags::COW) {
+            let frame = vma.pages.get_mut(&vpn).expect("COW page without a frame");
+            if frame.ref_count() > 1 {
+                let copy = Frame::alloc().ok_or(PageFaultError::OutOfMemory)?;
+                copy.as_bytes_mut().copy_from_slice(frame.as_bytes());
+                *frame = copy;
+            }
+            self.page_table.remap(vpn, frame.ppn(), vma.pte_flags());
+            return Ok(());
+        }
+
+        // The PTE already allows the access, e.g. a stale TLB entry after a remap.
+        flush_tlb(addr);
+        Ok(())
+    }
+
+    /// Fault in every page of `start..start + len` for `access` ahead of a kernel access.
+    ///
+    /// - The kernel must not take page faults on user memory, as traps do not nest.
+    pub fn populate(
+        &mut self,
+        start: usize,
+        len: usize,
+        access: Access,
+    ) -> Result<(), PageFaultError> {
+        let required = match access {
+            Access::Read => PteFlags::R,
+            Access::Write => PteFlags::W,
+            Access::Execute => PteFlags::X,
+        } | PteFlags::U;
+        for page in (page_floor(start)..start + len).step_by(PAGE_SIZE) {
+            let is_ready = self
+                .page_table
+                .entry(page >> PAGE_SIZE_BITS)
+                .is_some_and(|pte| {
+                    pte.flags().contains(required) && !pte.flags().contains(PteFlags::COW)
+                });
+            if !is_ready {
+                self.handle_page_fault(page.max(start), access)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `data` to `addr`, faulting in pages as a user write would.
+    ///
+    /// - The space need not be the active one, e.g. a new process's before it first runs.
+    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), PageFaultError> {
+        let end = addr.checked_add(data.len());
+        if !data.is_empty() && !end.is_some_and(|end| is_user_range(addr, end)) {
+            return Err(PageFaultError::NotMapped);
+        }
+        self.populate(addr, data.len(), Access::Write)?;
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let len = data.len().min(PAGE_SIZE - offset);
+            let vma = &self.vmas[self.find_vma(addr).unwrap()];
+            let frame = &vma.pages[&(addr >> PAGE_SIZE_BITS)];
+            frame.as_bytes_mut()[offset..offset + len].copy_from_slice(&data[..len]);
+            addr += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Duplicate the address space for `fork`.
+    ///
+    /// - Resident pages are shared; writable ones become read-only copy-on-write in both spaces.
+    pub fn fork(&mut self) -> Option<AddressSpace> {
+        let mut child = AddressSpace::new()?;
+        for vma in &self.vmas {
+            let mut child_vma = Vma::new(vma.start, vma.end, vma.flags, vma.kind);
+            let mut flags = vma.pte_flags();
+            if flags.contains(PteFlags::W) {
+                flags = flags.remove(PteFlags::W) | PteFlags::COW;
+            }
+            for (vpn, frame) in &vma.pages {
+                if flags.contains(PteFlags::COW) {
+                    self.page_table.remap(*vpn, frame.ppn(), flags);
+                }
+                child.page_table.map(*vpn, frame.ppn(), flags).ok()?;
+                child_vma.pages.insert(*vpn, frame.clone());
+            }
+            child.vmas.push(child_vma);
+        }
+        Some(child)
+    }
+}
@@ -0,0 +1,182 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use spin::Mutex;
+
+use super::{page_ceil, MEMORY_END, PAGE_SIZE, PAGE_SIZE_BITS};
+
+/// A reference-counted physical page.
+///
+/// - Cloning a `Frame` shares the page; it is freed when the last clone is dropped.
+/// - Copy-on-write relies on [`Frame::ref_count`] to tell whether a page is still shared.
+pub struct Frame {
+    ppn: usize,
+}
+
+impl Frame {
+    /// Allocate a zeroed frame.
+    pub fn alloc() -> Option<Self> {
+        let ppn = FRAME_ALLOCATOR.lock().alloc()?;
+        let frame = Frame { ppn };
+        frame.as_bytes_mut().fill(0);
+        Some(frame)
+    }
+
+    pub fn ppn(&self) -> usize {
+        self.ppn
+    }
+
+    pub fn address(&self) -> usize {
+        self.ppn << PAGE_SIZE_BITS
+    }
+
+    pub fn ref_count(&self) -> usize {
+        FRAME_ALLOCATOR.lock().ref_count(self.ppn)
+    }
+
+    /// - RAM is identity-mapped in every address space, so the physical address is also a valid
+    ///   kernel virtual address.
+    #[allow(clippy::mut_from_ref)]
+    pub fn as_bytes_mut(&self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.address() as *mut u8, PAGE_SIZE) }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.address() as *const u8, PAGE_SIZE) }
+    }
+}
+
+impl Clone for Frame {
+    fn clone(&self) -> Self {
+        FRAME_ALLOCATOR.lock().share(self.ppn);
+        Frame { ppn: self.ppn }
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR.lock().release(self.ppn);
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("address", &format_args!("{:#x}", self.address()))
+            .finish()
+    }
+}
+
+struct FrameAllocator {
+    /// First PPN managed by the allocator.
+    base: usize,
+    /// Next never-allocated PPN.
+    current: usize,
+    /// One past the last PPN.
+    end: usize,
+    /// Frames from `current` on that [`reserve`] took and the bump allocation skips.
+    reserved: usize,
+    recycled: Vec<usize>,
+    ref_counts: Vec<u16>,
+}
+
+impl FrameAllocator {
+    const fn empty() -> Self {
+        FrameAllocator {
+            base: 0,
+            current: 0,
+            end: 0,
+            reserved: 0,
+            recycled: Vec::new(),
+            ref_counts: Vec::new(),
+        }
+    }
+
+    fn init(&mut self, start: usize, end: usize) {
+        self.base = page_ceil(start) >> PAGE_SIZE_BITS;
+        self.current = self.base;
+        self.end = end >> PAGE_SIZE_BITS;
+        self.ref_counts = alloc::vec![0; self.end - self.base];
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        let ppn = match self.recycled.pop() {
+            Some(ppn) => ppn,
+            None => {
+                while self.current != self.end && self.ref_counts[self.current - self.base] != 0 {
+                    self.current += 1;
+                    self.reserved -= 1;
+                }
+                if self.current == self.end {
+                    return None;
+                }
+                self.current += 1;
+                self.current - 1
+            }
+        };
+        self.ref_counts[ppn - self.base] = 1;
+        Some(ppn)
+    }
+
+    /// Take `ppn` if it was never allocated.
+    fn reserve(&mut self, ppn: usize) -> bool {
+        if !(self.current..self.end).contains(&ppn) || self.ref_counts[ppn - self.base] != 0 {
+            return false;
+        }
+        self.ref_counts[ppn - self.base] = 1;
+        self.reserved += 1;
+        true
+    }
+
+    fn share(&mut self, ppn: usize) {
+        self.ref_counts[ppn - self.base] += 1;
+    }
+
+    fn release(&mut self, ppn: usize) {
+        let count = &mut self.ref_counts[ppn - self.base];
+        assert!(*count > 0, "Frame {:#x} released twice", ppn);
+        *count -= 1;
+        if *count == 0 {
+            // A reserved frame the bump allocation has yet to reach is handed out by it.
+            match ppn >= self.current {
+                true => self.reserved -= 1,
+                false => self.recycled.push(ppn),
+            }
+        }
+    }
+
+    fn ref_count(&self, ppn: usize) -> usize {
+        self.ref_counts[ppn - self.base] as usize
+    }
+
+    fn free_frames(&self) -> usize {
+        self.end - self.current - self.reserved + self.recycled.len()
+    }
+}
+
+static FRAME_ALLOCATOR: Mutex<FrameAllocator> = Mutex::new(FrameAllocator::empty());
+
+pub fn init() {
+    extern "C" {
+        fn end();
+    }
+    FRAME_ALLOCATOR
+        .lock()
+        .init(end as *const () as usize, MEMORY_END);
+}
+
+/// Keep the frames of `[start, end)` that were never allocated out of the allocator, like an
+/// initrd the firmware left in RAM.
+///
+/// - Dropping the returned frames frees them.
+pub fn reserve(start: usize, end: usize) -> Vec<Frame> {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    (start >> PAGE_SIZE_BITS..page_ceil(end) >> PAGE_SIZE_BITS)
+        .filter(|ppn| allocator.reserve(*ppn))
+        .map(|ppn| Frame { ppn })
+        .collect()
+}
+
+pub fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().free_frames()
+}
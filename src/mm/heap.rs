@@ -0,0 +1,39 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::addr_of_mut,
+};
+
+use linked_list_allocator::LockedHeap;
+
+use crate::exception::without_interrupts;
+
+const KERNEL_HEAP_SIZE: usize = 8 * 1024 * 1024;
+
+// The heap lives in `.bss`, so it is part of the kernel image and never handed out as a frame.
+static mut KERNEL_HEAP: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
+
+/// A `LockedHeap` that holds its lock with interrupts off, as both kernel threads and interrupt
+/// handlers allocate.
+struct KernelHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| unsafe { self.0.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| unsafe { self.0.dealloc(ptr, layout) })
+    }
+}
+
+#[global_allocator]
+static HEAP_ALLOCATOR: KernelHeap = KernelHeap(LockedHeap::empty());
+
+pub fn init() {
+    unsafe {
+        HEAP_ALLOCATOR
+            .0
+            .lock()
+            .init(addr_of_mut!(KERNEL_HEAP) as *mut u8, KERNEL_HEAP_SIZE);
+    }
+}
@@ -0,0 +1,27 @@
+pub mod address_space;
+pub mod frame;
+pub mod heap;
+pub mod page_table;
+pub mod user_access;
+
+pub const PAGE_SIZE: usize = 4096;
+pub const PAGE_SIZE_BITS: usize = 12;
+
+/// Start of RAM on QEMU `virt`.
+pub const MEMORY_START: usize = 0x8000_0000;
+
+/// End of RAM on QEMU `virt` with the default `-m 128M`.
+pub const MEMORY_END: usize = 0x8800_0000;
+
+pub fn init() {
+    heap::init();
+    frame::init();
+}
+
+pub fn page_floor(addr: usize) -> usize {
+    addr & !(PAGE_SIZE - 1)
+}
+
+pub fn page_ceil(addr: usize) -> usize {
+    page_floor(addr + PAGE_SIZE - 1)
+}
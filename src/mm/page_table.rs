@@ -0,0 +1,267 @@
+use alloc::vec::Vec;
+use core::{arch::asm, fmt, ops::BitOr};
+
+use super::{frame::Frame, PAGE_SIZE_BITS};
+
+/// Sv39 PTE permission and status bits.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PteFlags(pub usize);
+
+impl PteFlags {
+    pub const EMPTY: PteFlags = PteFlags(0);
+    pub const V: PteFlags = PteFlags(1 << 0);
+    pub const R: PteFlags = PteFlags(1 << 1);
+    pub const W: PteFlags = PteFlags(1 << 2);
+    pub const X: PteFlags = PteFlags(1 << 3);
+    pub const U: PteFlags = PteFlags(1 << 4);
+    pub const G: PteFlags = PteFlags(1 << 5);
+    pub const A: PteFlags = PteFlags(1 << 6);
+    pub const D: PteFlags = PteFlags(1 << 7);
+    /// RSW bit 8: the page is shared read-only and must be copied on the first write.
+    pub const COW: PteFlags = PteFlags(1 << 8);
+
+    pub fn contains(&self, other: PteFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn remove(self, other: PteFlags) -> PteFlags {
+        PteFlags(self.0 & !other.0)
+    }
+}
+
+impl BitOr for PteFlags {
+    type Output = PteFlags;
+
+    fn bitor(self, rhs: PteFlags) -> PteFlags {
+        PteFlags(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for PteFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = ["V", "R", "W", "X", "U", "G", "A", "D", "COW"];
+        for (i, name) in names.iter().enumerate() {
+            if self.0 & (1 << i) != 0 {
+                f.write_str(name)?;
+            } else {
+                f.write_str("-")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PageTableEntry(pub usize);
+
+impl PageTableEntry {
+    pub fn new(ppn: usize, flags: PteFlags) -> Self {
+        PageTableEntry(ppn << 10 | flags.0)
+    }
+
+    pub fn ppn(&self) -> usize {
+        (self.0 >> 10) & ((1 << 44) - 1)
+    }
+
+    pub fn flags(&self) -> PteFlags {
+        PteFlags(self.0 & 0x3ff)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.flags().contains(PteFlags::V)
+    }
+
+    /// - A valid PTE with any of R/W/X set is a leaf; otherwise it points to the next level.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && self.0 & (PteFlags::R | PteFlags::W | PteFlags::X).0 != 0
+    }
+}
+
+impl fmt::Debug for PageTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageTableEntry")
+            .field("ppn", &format_args!("{:#x}", self.ppn()))
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+/// - Table frames are identity-mapped like the rest of RAM.
+fn entries<'table>(ppn: usize) -> &'table mut [PageTableEntry] {
+    unsafe { core::slice::from_raw_parts_mut((ppn << PAGE_SIZE_BITS) as *mut PageTableEntry, 512) }
+}
+
+/// Split a virtual page number into its three Sv39 indices, root first.
+fn vpn_indices(vpn: usize) -> [usize; 3] {
+    [(vpn >> 18) & 0x1ff, (vpn >> 9) & 0x1ff, vpn & 0x1ff]
+}
+
+/// An Sv39 page table.
+///
+/// - Every table maps the kernel with global gigapages so traps can be taken without switching
+///   `satp`:
+///   - `0x0000_0000..0x4000_0000`: MMIO, identity-mapped.
+///   - `0x8000_0000..0xc000_0000`: RAM, identity-mapped.
+/// - Only the table frames are owned here; the frames behind leaf PTEs are owned by the mapping
+///   that installed them.
+pub struct PageTable {
+    root: Frame,
+    frames: Vec<Frame>,
+}
+
+impl PageTable {
+    pub fn new() -> Option<Self> {
+        let root = Frame::alloc()?;
+        let table = PageTable {
+            root,
+            frames: Vec::new(),
+        };
+        let kernel_flags = PteFlags::V | PteFlags::G | PteFlags::A | PteFlags::D;
+        let entries = entries(table.root.ppn());
+        entries[0] = PageTableEntry::new(0x0, kernel_flags | PteFlags::R | PteFlags::W);
+        entries[2] = PageTableEntry::new(
+            0x8000_0000 >> PAGE_SIZE_BITS,
+            kernel_flags | PteFlags::R | PteFlags::W | PteFlags::X,
+        );
+        Some(table)
+    }
+
+    /// Walk to the leaf PTE of `vpn`, allocating intermediate tables if `create` is set.
+    fn walk(&mut self, vpn: usize, create: bool) -> Option<&mut PageTableEntry> {
+        let indices = vpn_indices(vpn);
+        let mut ppn = self.root.ppn();
+        for (level, index) in indices.iter().enumerate() {
+            let pte = &mut entries(ppn)[*index];
+            if level == 2 {
+                return Some(pte);
+            }
+            if pte.is_leaf() {
+                // Huge pages belong to the kernel and are never split.
+                return None;
+            }
+            if !pte.is_valid() {
+                if !create {
+                    return None;
+                }
+                let frame = Frame::alloc()?;
+                *pte = PageTableEntry::new(frame.ppn(), PteFlags::V);
+                self.frames.push(frame);
+            }
+            ppn = pte.ppn();
+        }
+        unreachable!()
+    }
+
+    pub fn map(&mut self, vpn: usize, ppn: usize, flags: PteFlags) -> Result<(), MapError> {
+        let pte = self.walk(vpn, true).ok_or(MapError::OutOfMemory)?;
+        if pte.is_valid() {
+            return Err(MapError::AlreadyMapped);
+        }
+        *pte = PageTableEntry::new(ppn, flags | PteFlags::V | PteFlags::A | PteFlags::D);
+        Ok(())
+    }
+
+    /// Replace the leaf PTE of an already mapped page.
+    pub fn remap(&mut self, vpn: usize, ppn: usize, flags: PteFlags) {
+        let pte = self.walk(vpn, false).expect("Remapping an unmapped page");
+        *pte = PageTableEntry::new(ppn, flags | PteFlags::V | PteFlags::A | PteFlags::D);
+        flush_tlb(vpn << PAGE_SIZE_BITS);
+    }
+
+    pub fn unmap(&mut self, vpn: usize) -> Option<PageTableEntry> {
+        let pte = self.walk(vpn, false)?;
+        if !pte.is_valid() {
+            return None;
+        }
+        let old = *pte;
+        *pte = PageTableEntry(0);
+        flush_tlb(vpn << PAGE_SIZE_BITS);
+        Some(old)
+    }
+
+    pub fn entry(&mut self, vpn: usize) -> Option<PageTableEntry> {
+        self.walk(vpn, false)
+            .map(|pte| *pte)
+            .filter(|pte| pte.is_valid())
+    }
+
+    /// Translate a virtual address to its physical address, honouring kernel huge pages.
+    pub fn translate(&self, va: usize) -> Option<usize> {
+        translate(self.satp(), va)
+    }
+
+    /// `satp` value selecting this table in Sv39 mode.
+    pub fn satp(&self) -> usize {
+        8 << 60 | self.root.ppn()
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    OutOfMemory,
+    AlreadyMapped,
+}
+
+pub fn flush_tlb(va: usize) {
+    unsafe {
+        asm!("sfence.vma {}, zero", in(reg) va);
+    }
+}
+
+/// Translate `va` through the table selected by `satp`, honouring kernel huge pages.
+///
+/// - Works on the live `satp` without owning the table, e.g. from the monitor.
+pub fn translate(satp: usize, va: usize) -> Option<usize> {
+    let indices = vpn_indices(va >> PAGE_SIZE_BITS);
+    let mut ppn = satp_ppn(satp);
+    for (level, index) in indices.iter().enumerate() {
+        let pte = entries(ppn)[*index];
+        if !pte.is_valid() {
+            return None;
+        }
+        if pte.is_leaf() {
+            let offset_bits = PAGE_SIZE_BITS + 9 * (2 - level);
+            let offset = va & ((1 << offset_bits) - 1);
+            return Some((pte.ppn() << PAGE_SIZE_BITS) + offset);
+        }
+        ppn = pte.ppn();
+    }
+    None
+}
+
+/// The PTEs visited while translating `va` through the table selected by `satp`, root first,
+/// up to the leaf or the first invalid one.
+pub fn trace(satp: usize, va: usize) -> Vec<PageTableEntry> {
+    let mut visited = Vec::new();
+    let mut ppn = satp_ppn(satp);
+    for index in vpn_indices(va >> PAGE_SIZE_BITS) {
+        let pte = entries(ppn)[index];
+        visited.push(pte);
+        if !pte.is_valid() || pte.is_leaf() {
+            break;
+        }
+        ppn = pte.ppn();
+    }
+    visited
+}
+
+fn satp_ppn(satp: usize) -> usize {
+    satp & ((1 << 44) - 1)
+}
+
+/// The `satp` of the running hart.
+pub fn current_satp() -> usize {
+    let satp: usize;
+    unsafe {
+        asm!("csrr {}, satp", out(reg) satp);
+    }
+    satp
+}
+
+/// Switch to the given `satp`, `0` being bare mode.
+pub fn activate(satp: usize) {
+    unsafe {
+        asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+    }
+}
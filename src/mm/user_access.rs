@@ -0,0 +1,22 @@
+use core::arch::asm;
+
+/// SUM: supervisor loads and stores may touch `U` pages.
+const SUM: usize = 1 << 18;
+/// MXR: loads may read execute-only pages, which instruction fetches need.
+const MXR: usize = 1 << 19;
+
+/// Run `f` with user memory accessible from supervisor mode.
+///
+/// - The pages touched by `f` must be populated beforehand, see
+///   [`AddressSpace::populate`](super::address_space::AddressSpace::populate).
+pub fn with_user_access<R>(f: impl FnOnce() -> R) -> R {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrrs {}, sstatus, {}", out(reg) sstatus, in(reg) SUM | MXR);
+    }
+    let res = f();
+    unsafe {
+        asm!("csrc sstatus, {}", in(reg) !sstatus & (SUM | MXR));
+    }
+    res
+}
@@ -0,0 +1,337 @@
+use core::{
+    str::{self, SplitWhitespace},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    backtrace, console,
+    debug::{self, read_byte, read_instruction, translate},
+    drivers::block::cache,
+    exception::ExceptionMutContext,
+    mm::{page_table, PAGE_SIZE_BITS},
+    panicking::{self, PanicPolicy},
+    supervisor_print, supervisor_println, task, Spp,
+};
+
+const LINE_CAPACITY: usize = 128;
+const FP: usize = 8;
+const DEFAULT_DUMP_LEN: usize = 64;
+const MAX_DUMP_LEN: usize = 4096;
+/// Whether `ebreak` enters the monitor instead of being reported and skipped.
+static BREAK_ON_EBREAK: AtomicBool = AtomicBool::new(false);
+
+/// Enter the monitor on `ebreak`; off by default, as the monitor waits for console input.
+pub fn set_break_on_ebreak(enabled: bool) {
+    BREAK_ON_EBREAK.store(enabled, Ordering::Relaxed);
+}
+
+/// Read a line from the console by polling, echoing it and handling backspace.
+///
+/// - Polling works with interrupts disabled, which is the only state the monitor runs in.
+fn read_line(buf: &mut [u8; LINE_CAPACITY]) -> &str {
+    let mut len = 0;
+    loop {
+        let Some(byte) = console::poll_byte() else {
+            continue;
+        };
+        match byte {
+            b'\r' | b'\n' => {
+                supervisor_println!();
+                break;
+            }
+            // Backspace and DEL
+            0x08 | 0x7f => {
+                if len > 0 {
+                    len -= 1;
+                    supervisor_print!("\x08 \x08");
+                }
+            }
+            0x20..=0x7e if len < buf.len() => {
+                buf[len] = byte;
+                len += 1;
+                supervisor_print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+    str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// Parse `0x`-prefixed hexadecimal or decimal.
+fn parse_number(word: &str) -> Option<usize> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn dump_memory(words: &mut SplitWhitespace) {
+    let Some(addr) = words.next().and_then(parse_number) else {
+        supervisor_println!("Usage: x <addr> [len]");
+        return;
+    };
+    let len = words
+        .next()
+        .and_then(parse_number)
+        .unwrap_or(DEFAULT_DUMP_LEN)
+        .min(MAX_DUMP_LEN);
+    for line in (addr..addr + len).step_by(16) {
+        supervisor_print!("{:#018x}:", line);
+        for addr in line..(line + 16).min(addr + len) {
+            match read_byte(addr) {
+                Some(byte) => supervisor_print!(" {:02x}", byte),
+                None => supervisor_print!(" ??"),
+            }
+        }
+        supervisor_println!();
+    }
+}
+
+fn write_memory(words: &mut SplitWhitespace) {
+    let (Some(addr), Some(value)) = (
+        words.next().and_then(parse_number),
+        words.next().and_then(parse_number),
+    ) else {
+        supervisor_println!("Usage: w <addr> <u64>");
+        return;
+    };
+    if (addr..addr + 8).any(|addr| translate(addr).is_none()) {
+        supervisor_println!("{:#x} is not mapped to RAM", addr);
+        return;
+    }
+    for (i, byte) in value.to_le_bytes().iter().enumerate() {
+        debug::write_byte(addr + i, *byte);
+    }
+    debug::fence_i();
+}
+
+fn print_page_walk(words: &mut SplitWhitespace) {
+    let Some(addr) = words.next().and_then(parse_number) else {
+        supervisor_println!("Usage: pt <addr>");
+        return;
+    };
+    let satp = page_table::current_satp();
+    if satp == 0 {
+        supervisor_println!("Paging is off, {:#x} is a physical address", addr);
+        return;
+    }
+    supervisor_println!("satp: {:#x}", satp);
+    let visited = page_table::trace(satp, addr);
+    for (depth, pte) in visited.iter().enumerate() {
+        let level = 2 - depth;
+        let index = (addr >> (PAGE_SIZE_BITS + 9 * level)) & 0x1ff;
+        supervisor_println!("  level {} [{:3}]: {:?}", level, index, pte);
+    }
+    match page_table::translate(satp, addr) {
+        Some(physical) => supervisor_println!("{:#x} -> {:#x}", addr, physical),
+        None => supervisor_println!("{:#x} is not mapped", addr),
+    }
+}
+
+/// - The saved `sepc` of the running task is stale until it is switched out.
+fn print_tasks() {
+    let is_listed = task::for_each_task(|id, task, is_current| {
+        let state = task.state.name();
+        let pages: Option<usize> = task.address_space.as_ref().and_then(|space| {
+            let space = space.try_lock()?;
+            Some(space.vmas().iter().map(|vma| vma.resident_pages()).sum())
+        });
+        supervisor_print!(
+            "{} {:>3} {:<8} sepc: {:#018x}",
+            if is_current { '*' } else { ' ' },
+            id.0,
+            state,
+            task.context.sepc,
+        );
+        match (&task.address_space, pages) {
+            (None, _) => supervisor_print!("  flat"),
+            (Some(_), Some(pages)) => supervisor_print!("  {} pages", pages),
+            (Some(_), None) => supervisor_print!("  address space locked"),
+        }
+        supervisor_println!(
+            "  misaligned: {}  emulated: {}",
+            task.stats.misaligned_emulations,
+            task.stats.emulated_instructions
+        );
+    });
+    if !is_listed {
+        supervisor_println!("The scheduler is locked by the interrupted code");
+    }
+}
+
+fn print_cache_stats() {
+    match cache::stats() {
+        Some(stats) => {
+            let lookups = stats.hits + stats.misses;
+            supervisor_println!(
+                "{}/{} blocks resident, {} dirty, {} pinned",
+                stats.resident,
+                stats.capacity,
+                stats.dirty,
+                stats.pinned
+            );
+            supervisor_println!(
+                "hits: {}  misses: {}  hit rate: {}%  evictions: {}  write-backs: {}",
+                stats.hits,
+                stats.misses,
+                (stats.hits * 100).checked_div(lookups).unwrap_or(0),
+                stats.evictions,
+                stats.write_backs
+            );
+        }
+        None => supervisor_println!("No block cache, or it is locked by the interrupted code"),
+    }
+}
+
+/// Run a command that inspects the machine without needing a trapped context.
+///
+/// - Returns `false` for unknown commands.
+fn run_inspection_command(command: &str, words: &mut SplitWhitespace) -> bool {
+    match command {
+        "x" => dump_memory(words),
+        "w" => write_memory(words),
+        "pt" => print_page_walk(words),
+        "tasks" => print_tasks(),
+        "cache" => print_cache_stats(),
+        _ => return false,
+    }
+    true
+}
+
+fn print_inspection_help() {
+    supervisor_println!("  x <addr> [len]   dump memory through the current page table");
+    supervisor_println!("  w <addr> <u64>   write a double word through the current page table");
+    supervisor_println!("  pt <addr>        walk the current page table for an address");
+    supervisor_println!("  tasks            list the tasks");
+    supervisor_println!("  cache            show block cache statistics");
+}
+
+fn print_help() {
+    supervisor_println!("Commands:");
+    supervisor_println!("  help      show this message");
+    supervisor_println!("  bt        print a backtrace of the monitor");
+    print_inspection_help();
+    supervisor_println!("  reboot    reboot the machine");
+    supervisor_println!("  shutdown  power off the machine");
+    supervisor_println!("  spin      stop here and wait for a debugger");
+}
+
+/// Run the monitor after a panic; the kernel cannot be resumed from here.
+pub fn run_after_panic() -> ! {
+    supervisor_println!("Entering the monitor, type `help` for commands");
+    let mut buf = [0; LINE_CAPACITY];
+    loop {
+        supervisor_print!("monitor> ");
+        let line = read_line(&mut buf);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some("help") => print_help(),
+            Some("bt") => backtrace::print_backtrace(),
+            Some("reboot") => panicking::terminate(PanicPolicy::Reboot),
+            Some("shutdown") => panicking::terminate(PanicPolicy::Shutdown),
+            Some("spin") => panicking::terminate(PanicPolicy::Spin),
+            Some(command) if run_inspection_command(command, &mut words) => {}
+            Some(command) => supervisor_println!("Unknown command `{}`", command),
+        }
+    }
+}
+
+fn print_location(mut_context: &ExceptionMutContext) {
+    let pc = mut_context.sepc;
+    supervisor_print!("{:#018x}", pc);
+    if mut_context.sstatus.mode_before_exception() == Spp::Supervisor {
+        if let Some((name, offset)) = backtrace::symbolize(pc) {
+            supervisor_print!(" <{}+{:#x}>", name, offset);
+        }
+    }
+    match read_instruction(pc) {
+        Some(instruction) => supervisor_println!(": {}", instruction),
+        None => supervisor_println!(": <not mapped>"),
+    }
+}
+
+fn print_csrs(mut_context: &ExceptionMutContext) {
+    let satp = page_table::current_satp();
+    supervisor_println!(" sstatus: {:#018x}", mut_context.sstatus.0);
+    supervisor_println!("     sie: {:#018x}", mut_context.sie);
+    supervisor_println!("     sip: {:#018x}", mut_context.sip);
+    supervisor_println!("    satp: {:#018x}", satp);
+    supervisor_println!("    sepc: {:#018x}", mut_context.sepc);
+    supervisor_println!(
+        "    mode: {:?}",
+        mut_context.sstatus.mode_before_exception()
+    );
+}
+
+fn print_breakpoint_help() {
+    supervisor_println!("Commands:");
+    supervisor_println!("  help             show this message");
+    supervisor_println!("  regs             dump the trapped registers");
+    supervisor_println!("  csrs             dump sstatus, sie, sip and satp");
+    supervisor_println!("  bt               print a backtrace of the trapped kernel code");
+    print_inspection_help();
+    supervisor_println!("  s, step          execute one instruction");
+    supervisor_println!("  c, continue      resume execution");
+}
+
+/// Enter the monitor on `ebreak`, returning `false` if it is not wanted.
+///
+/// - `is_step` tells a stop after `step` from an `ebreak` in the code, which is skipped on
+///   resume.
+/// - Breakpoints must not be placed where the scheduler lock is held.
+pub fn handle_breakpoint(mut_context: &mut ExceptionMutContext, is_step: bool) -> bool {
+    if !is_step && !BREAK_ON_EBREAK.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    supervisor_println!(
+        "{} in task {:?}",
+        if is_step { "Step" } else { "Breakpoint" },
+        task::current_id()
+    );
+    print_location(mut_context);
+
+    let mut buf = [0; LINE_CAPACITY];
+    loop {
+        supervisor_print!("debug> ");
+        let line = read_line(&mut buf);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some("help") => print_breakpoint_help(),
+            Some("regs") => {
+                supervisor_print!("{}", mut_context.register_context);
+                supervisor_println!("sepc: {:#018x}", mut_context.sepc);
+            }
+            Some("csrs") => print_csrs(mut_context),
+            Some("bt") => match mut_context.sstatus.mode_before_exception() {
+                Spp::Supervisor => backtrace::print_backtrace_from(
+                    mut_context.sepc,
+                    mut_context.register_context.get(FP),
+                ),
+                Spp::User => supervisor_println!("User stacks are not walked"),
+            },
+            Some("s" | "step") => {
+                // Stepping over an `ebreak` in the code needs no breakpoint.
+                if let Some(len) = debug::compiled_ebreak_len(mut_context.sepc) {
+                    mut_context.sepc += len;
+                    print_location(mut_context);
+                    continue;
+                }
+                match debug::plant_step_breakpoints(mut_context) {
+                    Ok(()) => return true,
+                    Err(addr) => supervisor_println!("Cannot step: {:#x} is not in RAM", addr),
+                }
+            }
+            Some("c" | "continue") => {
+                if let Some(len) = debug::compiled_ebreak_len(mut_context.sepc) {
+                    mut_context.sepc += len;
+                }
+                return true;
+            }
+            Some(command) if run_inspection_command(command, &mut words) => {}
+            Some(command) => supervisor_println!("Unknown command `{}`", command),
+        }
+    }
+}
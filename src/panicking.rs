@@ -0,0 +1,192 @@
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    backtrace,
+    clock::Instant,
+    console, emergency_println, monitor,
+    sbi_call::{self, Extension},
+    supervisor_print, supervisor_println,
+};
+
+/// What the kernel does once a panic has been reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Power off through SBI SRST.
+    Shutdown,
+    /// Cold reboot through SBI SRST.
+    Reboot,
+    /// Stop in place for a debugger to attach.
+    Spin,
+    /// Enter the console monitor.
+    Monitor,
+}
+
+impl PanicPolicy {
+    /// The policy named by the `panic=` boot option.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "shutdown" => Some(PanicPolicy::Shutdown),
+            "reboot" => Some(PanicPolicy::Reboot),
+            "spin" => Some(PanicPolicy::Spin),
+            "monitor" => Some(PanicPolicy::Monitor),
+            _ => None,
+        }
+    }
+}
+
+impl From<u8> for PanicPolicy {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PanicPolicy::Shutdown,
+            1 => PanicPolicy::Reboot,
+            2 => PanicPolicy::Spin,
+            3 => PanicPolicy::Monitor,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl From<PanicPolicy> for u8 {
+    fn from(policy: PanicPolicy) -> Self {
+        match policy {
+            PanicPolicy::Shutdown => 0,
+            PanicPolicy::Reboot => 1,
+            PanicPolicy::Spin => 2,
+            PanicPolicy::Monitor => 3,
+        }
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(0);
+static PANIC_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// The harts that started running the kernel, one bit per hart ID.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+/// The hart that booted the kernel.
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+/// Harts that stopped for good in [`park`].
+static PARKED_HARTS: AtomicUsize = AtomicUsize::new(0);
+/// How long a panic waits for the other harts to park.
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub fn set_policy(policy: PanicPolicy) {
+    POLICY.store(policy.into(), Ordering::Relaxed);
+}
+
+pub fn policy() -> PanicPolicy {
+    PanicPolicy::from(POLICY.load(Ordering::Relaxed))
+}
+
+/// Record that the boot hart `hart_id` is running the kernel, so a panic can stop it.
+///
+/// - Hart IDs past the width of the mask are not recorded.
+pub fn boot_hart_started(hart_id: usize) {
+    BOOT_HART.store(hart_id, Ordering::SeqCst);
+    if hart_id < usize::BITS as usize {
+        ONLINE_HARTS.fetch_or(1 << hart_id, Ordering::SeqCst);
+    }
+}
+
+/// The hart running this code.
+///
+/// - No other hart is started, so it is the boot hart.
+fn current_hart() -> usize {
+    BOOT_HART.load(Ordering::SeqCst)
+}
+
+pub fn is_panicking() -> bool {
+    PANIC_COUNT.load(Ordering::SeqCst) != 0
+}
+
+fn disable_interrupts() {
+    unsafe {
+        asm!("csrci sstatus, 1 << 1");
+    }
+}
+
+/// Stop this hart for good.
+pub fn park() -> ! {
+    disable_interrupts();
+    PARKED_HARTS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+/// Carry out the final step of `policy` without anything that could panic again.
+pub fn terminate(policy: PanicPolicy) -> ! {
+    let _ = match policy {
+        PanicPolicy::Shutdown => sbi_call::sbi_call(&Extension::Shutdown),
+        PanicPolicy::Reboot => sbi_call::sbi_call(&Extension::Reboot),
+        PanicPolicy::Spin | PanicPolicy::Monitor => Ok(0),
+    };
+    park()
+}
+
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    disable_interrupts();
+
+    // A panic while panicking, either nested on this hart or racing on another one.
+    // - The console lock may be held by the first panic, so print without it.
+    if PANIC_COUNT.fetch_add(1, Ordering::SeqCst) != 0 {
+        emergency_println!("Double panic: {}", info);
+        match policy() {
+            PanicPolicy::Shutdown | PanicPolicy::Reboot => terminate(policy()),
+            PanicPolicy::Spin | PanicPolicy::Monitor => park(),
+        }
+    }
+
+    if !stop_other_harts() {
+        // A hart that does not park may still hold the console, or take it back, so the
+        // report goes out without locks and there is no monitor to enter.
+        emergency_println!("{}", info);
+        emergency_println!("Other harts did not stop");
+        match policy() {
+            PanicPolicy::Shutdown | PanicPolicy::Reboot => terminate(policy()),
+            PanicPolicy::Spin | PanicPolicy::Monitor => park(),
+        }
+    }
+
+    // Whoever held the console will never run again.
+    unsafe {
+        console::force_unlock();
+    }
+    supervisor_println!("{}", info);
+    backtrace::print_backtrace();
+
+    match policy() {
+        PanicPolicy::Monitor => monitor::run_after_panic(),
+        policy => terminate(policy),
+    }
+}
+
+/// Stop the world: the other harts park in their software interrupt handler.
+///
+/// - Returns `false` if some did not within [`STOP_TIMEOUT`], e.g. spinning with interrupts off.
+fn stop_other_harts() -> bool {
+    let current = current_hart();
+    let mut others = ONLINE_HARTS.load(Ordering::SeqCst);
+    if current < usize::BITS as usize {
+        others &= !(1 << current);
+    }
+    if others == 0 {
+        return true;
+    }
+    let _ = sbi_call::send_ipi(others, 0);
+    let others = others.count_ones() as usize;
+    let start = Instant::now();
+    while start.elapsed() < STOP_TIMEOUT {
+        if PARKED_HARTS.load(Ordering::SeqCst) >= others {
+            return true;
+        }
+        spin_loop();
+    }
+    false
+}
@@ -56,6 +56,25 @@ pub fn shutdown() -> ! {
     panic!("Should have been shutdown")
 }
 
+/// - `mask_base == usize::MAX` selects every hart and ignores `hart_mask`.
+pub fn send_ipi(hart_mask: usize, mask_base: usize) -> Result<(), SbiError> {
+    sbi_call(&Extension::SendIpi {
+        hart_mask,
+        mask_base,
+    })
+    .map(|_| ())
+}
+
+/// Poll the firmware console for one byte.
+pub fn console_getchar() -> Option<u8> {
+    // The legacy call returns the byte in `a0`, or `-1` if there is none.
+    match legacy_sbi_call(&LegacyExtension::ConsoleGetChar) {
+        Ok(_) => Some(0),
+        Err(-1) => None,
+        Err(ch) => Some(ch as u8),
+    }
+}
+
 pub fn set_timer(stime_value: u64) -> Result<(), ()> {
     let res = sbi_call(&Extension::SetTimer { stime_value });
     match res {
@@ -114,10 +133,12 @@ impl LegacyExtension {
 }
 
 pub enum Extension {
-    Base(BaseFunction),            // 0x10
-    SetTimer { stime_value: u64 }, // 0x54494D45
-    SendIpi { hart_mask: usize },  // 0x735049
-    Shutdown,                      // 0x53525354
+    Base(BaseFunction),                             // 0x10
+    SetTimer { stime_value: u64 },                  // 0x54494D45
+    SendIpi { hart_mask: usize, mask_base: usize }, // 0x735049
+    Shutdown,                                       // 0x53525354
+    Reboot,                                         // 0x53525354
+    WarmReboot,                                     // 0x53525354
 }
 
 impl Extension {
@@ -127,6 +148,8 @@ impl Extension {
             Extension::SetTimer { .. } => 0x54494D45,
             Extension::SendIpi { .. } => 0x735049,
             Extension::Shutdown => 0x53525354,
+            Extension::Reboot => 0x53525354,
+            Extension::WarmReboot => 0x53525354,
         }
     }
 
@@ -134,8 +157,11 @@ impl Extension {
         match self {
             Extension::Base(f) => f.arg0(),
             Extension::SetTimer { stime_value } => *stime_value as isize,
-            Extension::SendIpi { hart_mask } => *hart_mask as isize,
+            Extension::SendIpi { hart_mask, .. } => *hart_mask as isize,
             Extension::Shutdown => 0,
+            // Cold reboot
+            Extension::Reboot => 1,
+            Extension::WarmReboot => 2,
         }
     }
 
@@ -146,6 +172,7 @@ impl Extension {
                 64 => 0,
                 _ => panic!("Unsupported architecture"),
             },
+            Extension::SendIpi { mask_base, .. } => *mask_base as isize,
             _ => 0,
         }
     }
@@ -208,8 +235,13 @@ pub fn decode_sbi_call(a0: usize, a1: usize, a6: usize, a7: usize) -> Compatible
         }),
         0x735049 => CompatibleSbi::Extension(Extension::SendIpi {
             hart_mask: a0 as usize,
+            mask_base: a1,
         }),
-        0x53525354 => CompatibleSbi::Extension(Extension::Shutdown),
+        0x53525354 => match a0 {
+            1 => CompatibleSbi::Extension(Extension::Reboot),
+            2 => CompatibleSbi::Extension(Extension::WarmReboot),
+            _ => CompatibleSbi::Extension(Extension::Shutdown),
+        },
         _ => panic!("Unknown SBI function"),
     }
 }
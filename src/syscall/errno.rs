@@ -0,0 +1,124 @@
+use crate::{
+    fs::FsError,
+    task::{elf::ElfError, signal::SendError},
+};
+
+/// Linux error numbers, returned negated in `a0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Permission denied
+    EACCES = 13,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// Cross-device link
+    EXDEV = 18,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Too many open files
+    EMFILE = 24,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Illegal seek
+    ESPIPE = 29,
+    /// Read-only file system
+    EROFS = 30,
+    /// Broken pipe
+    EPIPE = 32,
+    /// Math result not representable
+    ERANGE = 34,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+    /// Too many symbolic links encountered
+    ELOOP = 40,
+    /// Operation not supported
+    EOPNOTSUPP = 95,
+    /// Kernel-internal: the task blocked, and the call runs again once it is woken.
+    ERESTARTSYS = 512,
+    /// Kernel-internal: the call set up the registers to resume with, like `execve`.
+    EJUSTRETURN = 517,
+}
+
+impl Errno {
+    /// The value a failed system call leaves in `a0`.
+    pub fn to_return_value(self) -> usize {
+        (-(self as isize)) as usize
+    }
+}
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => Errno::ENOENT,
+            FsError::AlreadyExists => Errno::EEXIST,
+            FsError::NotADirectory => Errno::ENOTDIR,
+            FsError::IsADirectory => Errno::EISDIR,
+            FsError::DirectoryNotEmpty => Errno::ENOTEMPTY,
+            FsError::InvalidInput => Errno::EINVAL,
+            FsError::NameTooLong => Errno::ENAMETOOLONG,
+            FsError::TooManySymlinks => Errno::ELOOP,
+            FsError::ReadOnly => Errno::EROFS,
+            FsError::NoSpace => Errno::ENOSPC,
+            FsError::Unsupported => Errno::EOPNOTSUPP,
+            FsError::CrossDevice => Errno::EXDEV,
+            FsError::Busy => Errno::EBUSY,
+            FsError::WouldBlock => Errno::EAGAIN,
+            FsError::BrokenPipe => Errno::EPIPE,
+            FsError::Corrupted | FsError::Io => Errno::EIO,
+        }
+    }
+}
+
+impl From<ElfError> for Errno {
+    fn from(error: ElfError) -> Self {
+        match error {
+            ElfError::Fs(error) => error.into(),
+            ElfError::PermissionDenied => Errno::EACCES,
+            ElfError::NotExecutable => Errno::ENOEXEC,
+            ElfError::ArgumentsTooLong => Errno::E2BIG,
+            ElfError::OutOfMemory => Errno::ENOMEM,
+        }
+    }
+}
+
+impl From<SendError> for Errno {
+    fn from(error: SendError) -> Self {
+        match error {
+            SendError::NotFound => Errno::ESRCH,
+            SendError::NotPermitted => Errno::EPERM,
+        }
+    }
+}
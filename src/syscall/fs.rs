@@ -0,0 +1,386 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::mem;
+
+use crate::{
+    exception::ExceptionMutContext,
+    fs::{self, Dentry, DirEntry, File, FileType, FsError, Metadata, OpenFlags, SeekFrom, MAX_FDS},
+    task::{
+        self,
+        signal::{self, SigInfo, SIGPIPE},
+    },
+};
+
+use super::{copy_from_user, copy_to_user, prepare_to_user, read_user_path, Errno, SyscallResult};
+
+/// `dirfd` for the working directory.
+const AT_FDCWD: isize = -100;
+/// `unlinkat` removes a directory.
+const AT_REMOVEDIR: usize = 0x200;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+/// The most bytes one `read` or `write` moves; callers loop for more.
+const MAX_IO_SIZE: usize = 64 * 1024;
+
+/// `struct stat` on RV64.
+const STAT_SIZE: usize = 128;
+
+fn files() -> Arc<spin::Mutex<fs::FdTable>> {
+    task::with_current(|task| task.files.clone())
+}
+
+fn get_file(fd: usize) -> Result<Arc<File>, Errno> {
+    files().lock().get(fd).ok_or(Errno::EBADF)
+}
+
+/// Where a path relative to `dirfd` starts; `None` means the root.
+fn start_dir(dirfd: usize, path: &str) -> Result<Option<Arc<Dentry>>, Errno> {
+    if path.starts_with('/') {
+        return Ok(None);
+    }
+    if dirfd as isize == AT_FDCWD {
+        return Ok(task::with_current(|task| task.cwd.clone()));
+    }
+    let file = get_file(dirfd)?;
+    let dentry = file.dentry().ok_or(Errno::ENOTDIR)?;
+    if !dentry.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(Some(dentry.clone()))
+}
+
+/// `openat(dirfd, pathname, flags, mode)`
+pub fn openat(
+    mut_context: &ExceptionMutContext,
+    dirfd: usize,
+    path: usize,
+    flags: usize,
+    mode: usize,
+) -> SyscallResult {
+    let path = read_user_path(mut_context, path)?;
+    let flags = OpenFlags(flags as u32);
+    let start = start_dir(dirfd, &path)?;
+    let follow = !flags.contains(OpenFlags::NOFOLLOW);
+
+    let dentry = if flags.contains(OpenFlags::CREAT) {
+        let (dir, name) = fs::lookup_parent(start.clone(), &path)?;
+        match dir.lookup(&name) {
+            Ok(_) if flags.contains(OpenFlags::EXCL) => return Err(Errno::EEXIST),
+            Ok(_) => fs::lookup(start, &path, follow)?,
+            Err(FsError::NotFound) => {
+                dir.inode()
+                    .create(&name, FileType::Regular, (mode & 0o7777) as u16)?;
+                dir.lookup(&name)?
+            }
+            Err(error) => return Err(error.into()),
+        }
+    } else {
+        fs::lookup(start, &path, follow)?
+    };
+
+    match dentry.file_type()? {
+        FileType::Symlink => return Err(Errno::ELOOP),
+        FileType::Directory if flags.is_writable() => return Err(Errno::EISDIR),
+        FileType::Directory => (),
+        _ if flags.contains(OpenFlags::DIRECTORY) => return Err(Errno::ENOTDIR),
+        FileType::Regular if flags.contains(OpenFlags::TRUNC) && flags.is_writable() => {
+            dentry.inode().truncate(0)?;
+        }
+        _ => (),
+    }
+    let file = Arc::new(File::new(dentry, flags));
+    files()
+        .lock()
+        .insert(file, flags.contains(OpenFlags::CLOEXEC))
+        .ok_or(Errno::EMFILE)
+}
+
+/// `close(fd)`
+pub fn close(fd: usize) -> SyscallResult {
+    files().lock().remove(fd).ok_or(Errno::EBADF)?;
+    Ok(0)
+}
+
+/// `pipe2(pipefd, flags)`
+pub fn pipe2(mut_context: &ExceptionMutContext, pipefd: usize, flags: usize) -> SyscallResult {
+    let flags = OpenFlags(flags as u32);
+    if flags.0 & !(OpenFlags::CLOEXEC | OpenFlags::NONBLOCK).0 != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+    let nonblock = OpenFlags(flags.0 & OpenFlags::NONBLOCK.0);
+    let (read_end, write_end) = fs::pipe();
+    let read_end = Arc::new(File::anonymous(read_end, OpenFlags::RDONLY | nonblock));
+    let write_end = Arc::new(File::anonymous(write_end, OpenFlags::WRONLY | nonblock));
+
+    let files = files();
+    let mut files = files.lock();
+    let read_fd = files.insert(read_end, close_on_exec).ok_or(Errno::EMFILE)?;
+    let Some(write_fd) = files.insert(write_end, close_on_exec) else {
+        files.remove(read_fd);
+        return Err(Errno::EMFILE);
+    };
+    let mut fds = [0; 8];
+    fds[..4].copy_from_slice(&(read_fd as i32).to_le_bytes());
+    fds[4..].copy_from_slice(&(write_fd as i32).to_le_bytes());
+    if let Err(errno) = copy_to_user(mut_context, pipefd, &fds) {
+        files.remove(read_fd);
+        files.remove(write_fd);
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+/// `dup(oldfd)`
+pub fn dup(oldfd: usize) -> SyscallResult {
+    let file = get_file(oldfd)?;
+    files().lock().insert(file, false).ok_or(Errno::EMFILE)
+}
+
+/// `dup3(oldfd, newfd, flags)`
+///
+/// - Whatever `newfd` held is closed first.
+pub fn dup3(oldfd: usize, newfd: usize, flags: usize) -> SyscallResult {
+    let flags = OpenFlags(flags as u32);
+    if oldfd == newfd || flags.0 & !OpenFlags::CLOEXEC.0 != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let file = get_file(oldfd)?;
+    if newfd >= MAX_FDS {
+        return Err(Errno::EBADF);
+    }
+    files()
+        .lock()
+        .insert_at(newfd, file, flags.contains(OpenFlags::CLOEXEC));
+    Ok(newfd)
+}
+
+/// `read(fd, buf, count)`
+///
+/// - If nothing can be read yet, the task blocks and the call restarts once it is woken,
+///   unless the file was opened with `O_NONBLOCK`.
+/// - A bad `buf` fails with `EFAULT` before anything is read, so no pipe data is lost.
+pub fn read(
+    mut_context: &ExceptionMutContext,
+    fd: usize,
+    buf: usize,
+    count: usize,
+) -> SyscallResult {
+    let file = get_file(fd)?;
+    let mut data = vec![0; count.min(MAX_IO_SIZE)];
+    prepare_to_user(mut_context, buf, data.len())?;
+    loop {
+        match file.read(&mut data) {
+            Ok(len) => {
+                copy_to_user(mut_context, buf, &data[..len])?;
+                return Ok(len);
+            }
+            Err(FsError::WouldBlock) if !file.flags().contains(OpenFlags::NONBLOCK) => {
+                if file.inode().block_until_readable() {
+                    return Err(Errno::ERESTARTSYS);
+                }
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// `write(fd, buf, count)`
+///
+/// - A blocking write goes on until all of `buf` is written: the task blocks whenever there is
+///   no room, and the call restarts from where it was once woken. A signal handler or the last
+///   reader going ends it early with what it wrote, if anything.
+/// - A write to a pipe with no reader left raises `SIGPIPE` as it fails with `EPIPE`.
+pub fn write(
+    mut_context: &ExceptionMutContext,
+    fd: usize,
+    buf: usize,
+    count: usize,
+) -> SyscallResult {
+    let file = get_file(fd)?;
+    let mut data = vec![0; count.min(MAX_IO_SIZE)];
+    copy_from_user(mut_context, buf, &mut data)?;
+    let is_blocking = !file.flags().contains(OpenFlags::NONBLOCK);
+    let mut written = task::with_current(|task| mem::take(&mut task.written));
+    loop {
+        match file.write(&data[written..]) {
+            Ok(len) => {
+                written += len;
+                if written == data.len() || len == 0 || !is_blocking {
+                    return Ok(written);
+                }
+            }
+            Err(FsError::WouldBlock) if is_blocking => {
+                if file.inode().block_until_writable() {
+                    task::with_current(|task| task.written = written);
+                    return Err(Errno::ERESTARTSYS);
+                }
+            }
+            Err(FsError::BrokenPipe) => {
+                signal::raise(SigInfo::kernel(SIGPIPE));
+                return match written {
+                    0 => Err(Errno::EPIPE),
+                    written => Ok(written),
+                };
+            }
+            Err(_) if written != 0 => return Ok(written),
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// `lseek(fd, offset, whence)`
+pub fn lseek(fd: usize, offset: usize, whence: usize) -> SyscallResult {
+    let file = get_file(fd)?;
+    let position = match whence {
+        SEEK_SET => SeekFrom::Start(offset as u64),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL),
+    };
+    match file.seek(position) {
+        Ok(position) => Ok(position as usize),
+        Err(FsError::Unsupported) => Err(Errno::ESPIPE),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn mode_type_bits(file_type: FileType) -> u32 {
+    match file_type {
+        FileType::Fifo => 0o010000,
+        FileType::CharDevice => 0o020000,
+        FileType::Directory => 0o040000,
+        FileType::BlockDevice => 0o060000,
+        FileType::Regular => 0o100000,
+        FileType::Symlink => 0o120000,
+        FileType::Socket => 0o140000,
+    }
+}
+
+/// `struct stat` as RV64 Linux lays it out.
+fn encode_stat(metadata: &Metadata) -> [u8; STAT_SIZE] {
+    let mut stat = [0; STAT_SIZE];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        stat[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    // st_dev stays 0.
+    put(8, &metadata.ino.to_le_bytes());
+    put(
+        16,
+        &(mode_type_bits(metadata.file_type) | metadata.mode as u32).to_le_bytes(),
+    );
+    put(20, &metadata.nlink.to_le_bytes());
+    put(24, &metadata.uid.to_le_bytes());
+    put(28, &metadata.gid.to_le_bytes());
+    put(32, &metadata.rdev.to_le_bytes());
+    put(48, &metadata.size.to_le_bytes());
+    put(56, &metadata.block_size.to_le_bytes());
+    put(64, &metadata.blocks.to_le_bytes());
+    for (offset, time) in [
+        (72, metadata.atime),
+        (88, metadata.mtime),
+        (104, metadata.ctime),
+    ] {
+        put(offset, &time.as_secs().to_le_bytes());
+        put(offset + 8, &(time.subsec_nanos() as u64).to_le_bytes());
+    }
+    stat
+}
+
+/// `fstat(fd, statbuf)`
+pub fn fstat(mut_context: &ExceptionMutContext, fd: usize, statbuf: usize) -> SyscallResult {
+    let metadata = get_file(fd)?.metadata()?;
+    copy_to_user(mut_context, statbuf, &encode_stat(&metadata))?;
+    Ok(0)
+}
+
+fn dirent_type(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::Fifo => 1,
+        FileType::CharDevice => 2,
+        FileType::Directory => 4,
+        FileType::BlockDevice => 6,
+        FileType::Regular => 8,
+        FileType::Symlink => 10,
+        FileType::Socket => 12,
+    }
+}
+
+/// `getdents64(fd, dirp, count)`
+pub fn getdents64(
+    mut_context: &ExceptionMutContext,
+    fd: usize,
+    dirp: usize,
+    count: usize,
+) -> SyscallResult {
+    let file = get_file(fd)?;
+    let mut buf = Vec::new();
+    file.read_dir(|entry: &DirEntry, next_offset| {
+        // `struct linux_dirent64`: d_ino, d_off, d_reclen, d_type, then the name and a NUL,
+        // padded to 8 bytes.
+        let len = (19 + entry.name.len() + 1).next_multiple_of(8);
+        if buf.len() + len > count {
+            return false;
+        }
+        buf.extend_from_slice(&entry.ino.to_le_bytes());
+        buf.extend_from_slice(&next_offset.to_le_bytes());
+        buf.extend_from_slice(&(len as u16).to_le_bytes());
+        buf.push(dirent_type(entry.file_type));
+        buf.extend_from_slice(entry.name.as_bytes());
+        buf.resize(buf.len() + len - 19 - entry.name.len(), 0);
+        true
+    })?;
+    if buf.is_empty() && count != 0 {
+        // Either the end, or no room for the next entry.
+        let mut is_end = true;
+        file.read_dir(|_, _| {
+            is_end = false;
+            false
+        })?;
+        if !is_end {
+            return Err(Errno::EINVAL);
+        }
+    }
+    copy_to_user(mut_context, dirp, &buf)?;
+    Ok(buf.len())
+}
+
+/// `mkdirat(dirfd, pathname, mode)`
+pub fn mkdirat(
+    mut_context: &ExceptionMutContext,
+    dirfd: usize,
+    path: usize,
+    mode: usize,
+) -> SyscallResult {
+    let path = read_user_path(mut_context, path)?;
+    let (dir, name) = fs::lookup_parent(start_dir(dirfd, &path)?, &path)?;
+    dir.inode()
+        .create(&name, FileType::Directory, (mode & 0o7777) as u16)?;
+    Ok(0)
+}
+
+/// `unlinkat(dirfd, pathname, flags)`
+pub fn unlinkat(
+    mut_context: &ExceptionMutContext,
+    dirfd: usize,
+    path: usize,
+    flags: usize,
+) -> SyscallResult {
+    let path = read_user_path(mut_context, path)?;
+    let (dir, name) = fs::lookup_parent(start_dir(dirfd, &path)?, &path)?;
+    let child = dir.lookup_covered(&name)?;
+    if child.is_mount_point() {
+        return Err(Errno::EBUSY);
+    }
+    if flags & AT_REMOVEDIR != 0 {
+        dir.inode().rmdir(&name)?;
+    } else if child.is_dir() {
+        return Err(Errno::EISDIR);
+    } else {
+        dir.inode().unlink(&name)?;
+    }
+    dir.forget(&name);
+    Ok(0)
+}
@@ -0,0 +1,180 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::{
+    exception::{
+        instruction::{prepare, read_bytes, write_bytes},
+        ExceptionMutContext,
+    },
+    mm::{address_space::Access, PAGE_SIZE},
+    task,
+};
+
+mod errno;
+mod fs;
+mod process;
+mod signal;
+mod time;
+
+pub use errno::Errno;
+pub use time::interrupt_nanosleep;
+
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+const A3: usize = 13;
+const A4: usize = 14;
+const A5: usize = 15;
+const A7: usize = 17;
+
+/// The longest path a system call takes, with its NUL.
+const PATH_MAX: usize = 4096;
+
+/// Linux system call numbers, from `asm-generic/unistd.h`.
+const DUP: usize = 23;
+const DUP3: usize = 24;
+const MKDIRAT: usize = 34;
+const UNLINKAT: usize = 35;
+const OPENAT: usize = 56;
+const CLOSE: usize = 57;
+const PIPE2: usize = 59;
+const GETDENTS64: usize = 61;
+const LSEEK: usize = 62;
+const READ: usize = 63;
+const WRITE: usize = 64;
+const FSTAT: usize = 80;
+const EXIT: usize = 93;
+const EXIT_GROUP: usize = 94;
+const NANOSLEEP: usize = 101;
+const CLOCK_GETTIME: usize = 113;
+const KILL: usize = 129;
+const TKILL: usize = 130;
+const TGKILL: usize = 131;
+const RT_SIGACTION: usize = 134;
+const RT_SIGPROCMASK: usize = 135;
+const RT_SIGPENDING: usize = 136;
+const RT_SIGRETURN: usize = 139;
+const GETTIMEOFDAY: usize = 169;
+const GETPID: usize = 172;
+const GETPPID: usize = 173;
+const GETTID: usize = 178;
+const CLONE: usize = 220;
+const EXECVE: usize = 221;
+const WAIT4: usize = 260;
+
+type SyscallResult = Result<usize, Errno>;
+
+/// Serve a Linux system call from a user task and step past its `ecall`.
+///
+/// - A number the kernel does not implement fails with [`Errno::ENOSYS`].
+/// - A call that blocked the task with [`Errno::ERESTARTSYS`] stays on its `ecall` with its
+///   arguments intact, to run again once the task is woken.
+/// - A call that returns [`Errno::EJUSTRETURN`] left the registers as the task resumes with.
+pub fn syscall(mut_context: &mut ExceptionMutContext) {
+    let x = &mut_context.register_context.x;
+    let args = [x[A0], x[A1], x[A2], x[A3], x[A4], x[A5]];
+    let res = match x[A7] {
+        MKDIRAT => fs::mkdirat(mut_context, args[0], args[1], args[2]),
+        UNLINKAT => fs::unlinkat(mut_context, args[0], args[1], args[2]),
+        OPENAT => fs::openat(mut_context, args[0], args[1], args[2], args[3]),
+        CLOSE => fs::close(args[0]),
+        PIPE2 => fs::pipe2(mut_context, args[0], args[1]),
+        DUP => fs::dup(args[0]),
+        DUP3 => fs::dup3(args[0], args[1], args[2]),
+        GETDENTS64 => fs::getdents64(mut_context, args[0], args[1], args[2]),
+        LSEEK => fs::lseek(args[0], args[1], args[2]),
+        READ => fs::read(mut_context, args[0], args[1], args[2]),
+        WRITE => fs::write(mut_context, args[0], args[1], args[2]),
+        FSTAT => fs::fstat(mut_context, args[0], args[1]),
+        NANOSLEEP => time::nanosleep(mut_context, args[0], args[1]),
+        CLOCK_GETTIME => time::clock_gettime(mut_context, args[0], args[1]),
+        GETTIMEOFDAY => time::gettimeofday(mut_context, args[0], args[1]),
+        KILL => signal::kill(args[0], args[1]),
+        TKILL => signal::tkill(args[0], args[1]),
+        TGKILL => signal::tgkill(args[0], args[1], args[2]),
+        RT_SIGACTION => signal::rt_sigaction(mut_context, args[0], args[1], args[2], args[3]),
+        RT_SIGPROCMASK => signal::rt_sigprocmask(mut_context, args[0], args[1], args[2], args[3]),
+        RT_SIGPENDING => signal::rt_sigpending(mut_context, args[0], args[1]),
+        RT_SIGRETURN => signal::rt_sigreturn(mut_context),
+        EXIT => process::exit(args[0]),
+        EXIT_GROUP => process::exit_group(args[0]),
+        GETPID => process::getpid(),
+        GETPPID => process::getppid(),
+        GETTID => process::gettid(),
+        CLONE => process::clone(mut_context, args[0], args[1], args[2], args[3], args[4]),
+        EXECVE => process::execve(mut_context, args[0], args[1], args[2]),
+        WAIT4 => process::wait4(mut_context, args[0], args[1], args[2], args[3]),
+        _ => Err(Errno::ENOSYS),
+    };
+    mut_context.register_context.x[A0] = match res {
+        Ok(value) => value,
+        Err(Errno::ERESTARTSYS) => {
+            task::signal::set_restarting();
+            return;
+        }
+        Err(Errno::EJUSTRETURN) => return,
+        Err(errno) => errno.to_return_value(),
+    };
+    mut_context.sepc += 4;
+}
+
+/// Copy `buf.len()` bytes in from user memory at `addr`.
+fn copy_from_user(
+    mut_context: &ExceptionMutContext,
+    addr: usize,
+    buf: &mut [u8],
+) -> Result<(), Errno> {
+    prepare(mut_context, addr, buf.len(), Access::Read).map_err(|_| Errno::EFAULT)?;
+    read_bytes(mut_context, addr, buf);
+    Ok(())
+}
+
+/// Copy `data` out to user memory at `addr`.
+fn copy_to_user(mut_context: &ExceptionMutContext, addr: usize, data: &[u8]) -> Result<(), Errno> {
+    prepare(mut_context, addr, data.len(), Access::Write).map_err(|_| Errno::EFAULT)?;
+    write_bytes(mut_context, addr, data);
+    Ok(())
+}
+
+/// Check that `len` bytes of user memory at `addr` can be written, so that a [`copy_to_user`]
+/// there after a read that cannot be undone does not fail.
+fn prepare_to_user(
+    mut_context: &ExceptionMutContext,
+    addr: usize,
+    len: usize,
+) -> Result<(), Errno> {
+    prepare(mut_context, addr, len, Access::Write).map_err(|_| Errno::EFAULT)
+}
+
+/// Read a NUL-terminated path from user memory.
+fn read_user_path(mut_context: &ExceptionMutContext, addr: usize) -> Result<String, Errno> {
+    let path = read_user_cstring(mut_context, addr, PATH_MAX, Errno::ENAMETOOLONG)?;
+    String::from_utf8(path).map_err(|_| Errno::EINVAL)
+}
+
+/// Read a NUL-terminated string from user memory, without its NUL.
+///
+/// - Fails with `too_long` if no NUL comes within `max_len` bytes.
+fn read_user_cstring(
+    mut_context: &ExceptionMutContext,
+    addr: usize,
+    max_len: usize,
+    too_long: Errno,
+) -> Result<Vec<u8>, Errno> {
+    let mut string = Vec::new();
+    let mut addr = addr;
+    loop {
+        // A page at a time, so the string may end right before an unmapped page.
+        let len = (PAGE_SIZE - addr % PAGE_SIZE).min(max_len - string.len());
+        let mut chunk = vec![0; len];
+        copy_from_user(mut_context, addr, &mut chunk)?;
+        if let Some(end) = chunk.iter().position(|byte| *byte == 0) {
+            string.extend_from_slice(&chunk[..end]);
+            return Ok(string);
+        }
+        string.extend_from_slice(&chunk);
+        if string.len() == max_len {
+            return Err(too_long);
+        }
+        addr += len;
+    }
+}
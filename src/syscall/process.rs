@@ -0,0 +1,277 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use crate::{
+    exception::ExceptionMutContext,
+    task::{
+        self,
+        elf::{self, ARG_MAX},
+        ChildState, ExitReason, Task, TaskContext, TaskId, WaitFor,
+    },
+};
+
+use super::{
+    copy_from_user, copy_to_user, read_user_cstring, read_user_path, Errno, SyscallResult, A0,
+};
+
+const SP: usize = 2;
+const TP: usize = 4;
+
+/// `clone` flags, from `linux/sched.h`.
+const CSIGNAL: usize = 0xff;
+const CLONE_VM: usize = 0x100;
+const CLONE_FS: usize = 0x200;
+const CLONE_FILES: usize = 0x400;
+const CLONE_SIGHAND: usize = 0x800;
+const CLONE_VFORK: usize = 0x4000;
+const CLONE_THREAD: usize = 0x10000;
+const CLONE_SYSVSEM: usize = 0x40000;
+const CLONE_SETTLS: usize = 0x80000;
+const CLONE_PARENT_SETTID: usize = 0x100000;
+const CLONE_DETACHED: usize = 0x400000;
+const CLONE_CHILD_SETTID: usize = 0x1000000;
+/// What `clone` takes; `CLONE_SYSVSEM` and `CLONE_DETACHED` change nothing here.
+const CLONE_SUPPORTED: usize = CSIGNAL
+    | CLONE_VM
+    | CLONE_FS
+    | CLONE_FILES
+    | CLONE_SIGHAND
+    | CLONE_VFORK
+    | CLONE_THREAD
+    | CLONE_SYSVSEM
+    | CLONE_SETTLS
+    | CLONE_PARENT_SETTID
+    | CLONE_DETACHED
+    | CLONE_CHILD_SETTID;
+
+/// `wait4` options.
+const WNOHANG: usize = 1;
+const WUNTRACED: usize = 2;
+const WCONTINUED: usize = 8;
+
+/// `struct rusage` on RV64.
+const RUSAGE_SIZE: usize = 144;
+
+/// `exit(status)`: end the calling thread.
+pub fn exit(status: usize) -> SyscallResult {
+    task::exit_current(ExitReason::Exit(status as i32));
+    Err(Errno::EJUSTRETURN)
+}
+
+/// `exit_group(status)`: end every thread of the process.
+pub fn exit_group(status: usize) -> SyscallResult {
+    task::exit_group(ExitReason::Exit(status as i32));
+    Err(Errno::EJUSTRETURN)
+}
+
+/// `getpid()`
+pub fn getpid() -> SyscallResult {
+    Ok(task::with_current(|task| task.tgid.0))
+}
+
+/// `getppid()`
+///
+/// - 0 for a process with no parent, as for one started by the kernel.
+pub fn getppid() -> SyscallResult {
+    Ok(task::with_current(|task| {
+        task.parent.map_or(0, |parent| parent.0)
+    }))
+}
+
+/// `gettid()`
+pub fn gettid() -> SyscallResult {
+    Ok(task::current_id().0)
+}
+
+/// `clone(flags, stack, parent_tid, tls, child_tid)`
+///
+/// - Without `CLONE_VM` the child gets a copy-on-write copy of the address space, as with
+///   `fork`; a task without one of its own, like a flat task, cannot fork.
+/// - `CLONE_FS` is implied: the working directory is not shared, but nothing changes it.
+/// - `CLONE_CHILD_CLEARTID` and the namespace flags are not supported.
+pub fn clone(
+    mut_context: &ExceptionMutContext,
+    flags: usize,
+    stack: usize,
+    parent_tid: usize,
+    tls: usize,
+    child_tid: usize,
+) -> SyscallResult {
+    if flags & !CLONE_SUPPORTED != 0
+        || (flags & CLONE_THREAD != 0 && flags & CLONE_SIGHAND == 0)
+        || (flags & CLONE_SIGHAND != 0 && flags & CLONE_VM == 0)
+    {
+        return Err(Errno::EINVAL);
+    }
+    let (address_space, files, cwd, signal_actions, blocked) = task::with_current(|task| {
+        (
+            task.address_space.clone(),
+            task.files.clone(),
+            task.cwd.clone(),
+            task.signal_actions.clone(),
+            task.signals.blocked,
+        )
+    });
+    let address_space = match (flags & CLONE_VM != 0, address_space) {
+        (true, address_space) => address_space,
+        (false, Some(address_space)) => {
+            let child = address_space.lock().fork().ok_or(Errno::ENOMEM)?;
+            Some(Arc::new(Mutex::new(child)))
+        }
+        (false, None) => return Err(Errno::EINVAL),
+    };
+    let files = match flags & CLONE_FILES {
+        0 => Arc::new(Mutex::new(files.lock().clone())),
+        _ => files,
+    };
+    let signal_actions = match flags & CLONE_SIGHAND {
+        0 => Arc::new(Mutex::new(signal_actions.lock().clone())),
+        _ => signal_actions,
+    };
+
+    let mut registers = mut_context.register_context.clone();
+    registers.x[A0] = 0;
+    if stack != 0 {
+        registers.x[SP] = stack;
+    }
+    if flags & CLONE_SETTLS != 0 {
+        registers.x[TP] = tls;
+    }
+    let context = TaskContext {
+        registers,
+        sepc: mut_context.sepc + 4,
+        sstatus: mut_context.sstatus.0,
+    };
+    let child_space = address_space.clone();
+    let mut child = Task::new_clone(context, address_space, files, cwd, signal_actions);
+    child.signals.blocked = blocked;
+    let id = task::spawn_clone(child, flags & CLONE_THREAD != 0);
+
+    // The child exists by now, so a bad address leaves the ID unwritten rather than failing.
+    let tid = (id.0 as i32).to_le_bytes();
+    if flags & CLONE_PARENT_SETTID != 0 {
+        let _ = copy_to_user(mut_context, parent_tid, &tid);
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        match (flags & CLONE_VM != 0, child_space) {
+            (false, Some(child_space)) => {
+                let _ = child_space.lock().write(child_tid, &tid);
+            }
+            _ => {
+                let _ = copy_to_user(mut_context, child_tid, &tid);
+            }
+        }
+    }
+    if flags & CLONE_VFORK != 0 {
+        task::wait_for_vfork(id);
+    }
+    Ok(id.0)
+}
+
+/// Read a NULL-terminated array of string pointers, like `argv`, from user memory.
+///
+/// - A NULL array is empty.
+/// - The strings, with their NULs, may take up to [`ARG_MAX`] bytes.
+fn read_user_strings(
+    mut_context: &ExceptionMutContext,
+    addr: usize,
+) -> Result<Vec<Vec<u8>>, Errno> {
+    let mut strings = Vec::new();
+    if addr == 0 {
+        return Ok(strings);
+    }
+    let mut total = 0;
+    for index in 0usize.. {
+        let mut pointer = [0; 8];
+        let pointer_addr = index
+            .checked_mul(8)
+            .and_then(|offset| addr.checked_add(offset))
+            .ok_or(Errno::EFAULT)?;
+        copy_from_user(mut_context, pointer_addr, &mut pointer)?;
+        let pointer = usize::from_le_bytes(pointer);
+        if pointer == 0 {
+            break;
+        }
+        let string = read_user_cstring(mut_context, pointer, ARG_MAX - total, Errno::E2BIG)?;
+        total += string.len() + 1;
+        strings.push(string);
+    }
+    Ok(strings)
+}
+
+/// `execve(pathname, argv, envp)`
+///
+/// - On success the task resumes at the new program's entry with every other register zero.
+pub fn execve(
+    mut_context: &mut ExceptionMutContext,
+    path: usize,
+    argv: usize,
+    envp: usize,
+) -> SyscallResult {
+    let path = read_user_path(mut_context, path)?;
+    let argv = read_user_strings(mut_context, argv)?;
+    let envp = read_user_strings(mut_context, envp)?;
+    let start = match path.starts_with('/') {
+        true => None,
+        false => task::with_current(|task| task.cwd.clone()),
+    };
+    let image = elf::load(start, &path, &argv, &envp)?;
+    task::exec_current(image.address_space);
+
+    let registers = &mut mut_context.register_context.x;
+    registers.fill(0);
+    registers[SP] = image.sp;
+    mut_context.sepc = image.entry;
+    Err(Errno::EJUSTRETURN)
+}
+
+/// `wait4(pid, wstatus, options, rusage)`
+///
+/// - `pid` -1 and 0 both wait for any child; there are no process groups besides the one
+///   every process is in. Waiting for another group finds no child.
+/// - `WUNTRACED` also reports a child that stopped, and `WCONTINUED` one resumed by `SIGCONT`,
+///   each once.
+/// - `rusage` comes back zeroed.
+pub fn wait4(
+    mut_context: &ExceptionMutContext,
+    pid: usize,
+    wstatus: usize,
+    options: usize,
+    rusage: usize,
+) -> SyscallResult {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pid = match pid as isize {
+        -1 | 0 => None,
+        pid if pid > 0 => Some(TaskId(pid as usize)),
+        _ => return Err(Errno::ECHILD),
+    };
+    let wait_for = WaitFor {
+        stopped: options & WUNTRACED != 0,
+        continued: options & WCONTINUED != 0,
+    };
+    let (id, status) = loop {
+        match task::reap_child(pid, wait_for) {
+            ChildState::None => return Err(Errno::ECHILD),
+            ChildState::Running if options & WNOHANG != 0 => return Ok(0),
+            ChildState::Running => {
+                if task::block_for_child(pid, wait_for) {
+                    return Err(Errno::ERESTARTSYS);
+                }
+            }
+            ChildState::Exited(id, reason) => break (id, reason.wait_status()),
+            // As `WIFSTOPPED` and `WIFCONTINUED` decode them.
+            ChildState::Stopped(id, signo) => break (id, signo << 8 | 0x7f),
+            ChildState::Continued(id) => break (id, 0xffff),
+        }
+    };
+    if wstatus != 0 {
+        copy_to_user(mut_context, wstatus, &status.to_le_bytes())?;
+    }
+    if rusage != 0 {
+        copy_to_user(mut_context, rusage, &[0; RUSAGE_SIZE])?;
+    }
+    Ok(id.0)
+}
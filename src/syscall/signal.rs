@@ -0,0 +1,180 @@
+use crate::{
+    exception::ExceptionMutContext,
+    task::{
+        self,
+        signal::{
+            self, SendError, SigAction, SigInfo, SigSet, FRAME_SIZE, NSIG, SIGKILL, SIGSEGV,
+            SIGSTOP, SI_TKILL, SI_USER,
+        },
+        ExitReason, TaskId,
+    },
+};
+
+use super::{copy_from_user, copy_to_user, Errno, SyscallResult};
+
+const SP: usize = 2;
+
+/// `sigset_t` as the kernel takes it: 64 bits.
+const SIGSET_SIZE: usize = 8;
+/// `struct sigaction` on RV64: `sa_handler`, `sa_flags` and `sa_mask`, with no `sa_restorer`.
+const SIGACTION_SIZE: usize = 24;
+
+/// `rt_sigprocmask` operations.
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// A signal number from user space; 0 only where it checks a target.
+fn signal_number(signo: usize, allow_zero: bool) -> Result<u32, Errno> {
+    match signo {
+        0 if allow_zero => Ok(0),
+        signo if (1..=NSIG as usize).contains(&signo) => Ok(signo as u32),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn current_tgid() -> TaskId {
+    task::with_current(|task| task.tgid)
+}
+
+/// `kill(pid, sig)`
+///
+/// - `pid` 0 and -1 reach every user process but init, -1 leaving out the caller too; there are
+///   no process groups besides the one every process is in.
+pub fn kill(pid: usize, signo: usize) -> SyscallResult {
+    let signo = signal_number(signo, true)?;
+    let info = SigInfo::user(signo, SI_USER, current_tgid());
+    match pid as isize {
+        pid if pid > 0 => signal::send_to_process(TaskId(pid as usize), info),
+        0 => signal::send_to_all(info, true),
+        -1 => signal::send_to_all(info, false),
+        _ => Err(SendError::NotFound),
+    }?;
+    Ok(0)
+}
+
+/// `tkill(tid, sig)`
+pub fn tkill(tid: usize, signo: usize) -> SyscallResult {
+    let signo = signal_number(signo, true)?;
+    if tid as isize <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let info = SigInfo::user(signo, SI_TKILL, current_tgid());
+    signal::send_to_task(TaskId(tid), None, info)?;
+    Ok(0)
+}
+
+/// `tgkill(tgid, tid, sig)`
+pub fn tgkill(tgid: usize, tid: usize, signo: usize) -> SyscallResult {
+    let signo = signal_number(signo, true)?;
+    if tgid as isize <= 0 || tid as isize <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let info = SigInfo::user(signo, SI_TKILL, current_tgid());
+    signal::send_to_task(TaskId(tid), Some(TaskId(tgid)), info)?;
+    Ok(0)
+}
+
+/// `rt_sigaction(sig, act, oldact, sigsetsize)`
+///
+/// - There is no alternate signal stack, so `SA_ONSTACK` changes nothing; handlers always get
+///   the `siginfo` and `ucontext` arguments, with or without `SA_SIGINFO`.
+pub fn rt_sigaction(
+    mut_context: &ExceptionMutContext,
+    signo: usize,
+    act: usize,
+    oldact: usize,
+    sigsetsize: usize,
+) -> SyscallResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let signo = signal_number(signo, false)?;
+    let old = match act {
+        0 => task::with_current(|task| task.signal_actions.lock().get(signo)),
+        _ if signo == SIGKILL || signo == SIGSTOP => return Err(Errno::EINVAL),
+        _ => {
+            let mut bytes = [0; SIGACTION_SIZE];
+            copy_from_user(mut_context, act, &mut bytes)?;
+            let word = |index: usize| {
+                u64::from_le_bytes(bytes[index * 8..index * 8 + 8].try_into().unwrap())
+            };
+            let action = SigAction {
+                handler: word(0) as usize,
+                flags: word(1) as usize,
+                mask: SigSet(word(2)).remove(SigSet::UNBLOCKABLE),
+            };
+            signal::set_action(signo, action)
+        }
+    };
+    if oldact != 0 {
+        let mut bytes = [0; SIGACTION_SIZE];
+        bytes[..8].copy_from_slice(&old.handler.to_le_bytes());
+        bytes[8..16].copy_from_slice(&old.flags.to_le_bytes());
+        bytes[16..].copy_from_slice(&old.mask.0.to_le_bytes());
+        copy_to_user(mut_context, oldact, &bytes)?;
+    }
+    Ok(0)
+}
+
+/// `rt_sigprocmask(how, set, oldset, sigsetsize)`
+///
+/// - `SIGKILL` and `SIGSTOP` are left out of the mask silently.
+pub fn rt_sigprocmask(
+    mut_context: &ExceptionMutContext,
+    how: usize,
+    set: usize,
+    oldset: usize,
+    sigsetsize: usize,
+) -> SyscallResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let old = match set {
+        0 => task::with_current(|task| task.signals.blocked),
+        _ => {
+            if !matches!(how, SIG_BLOCK | SIG_UNBLOCK | SIG_SETMASK) {
+                return Err(Errno::EINVAL);
+            }
+            let mut bytes = [0; SIGSET_SIZE];
+            copy_from_user(mut_context, set, &mut bytes)?;
+            let set = SigSet(u64::from_le_bytes(bytes));
+            signal::update_blocked(|blocked| match how {
+                SIG_BLOCK => blocked | set,
+                SIG_UNBLOCK => blocked.remove(set),
+                _ => set,
+            })
+        }
+    };
+    if oldset != 0 {
+        copy_to_user(mut_context, oldset, &old.0.to_le_bytes())?;
+    }
+    Ok(0)
+}
+
+/// `rt_sigpending(set, sigsetsize)`
+pub fn rt_sigpending(
+    mut_context: &ExceptionMutContext,
+    set: usize,
+    sigsetsize: usize,
+) -> SyscallResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let pending = task::with_current(|task| task.signals.pending());
+    copy_to_user(mut_context, set, &pending.0.to_le_bytes())?;
+    Ok(0)
+}
+
+/// `rt_sigreturn()`: return from a signal handler through the frame at `sp`.
+///
+/// - A frame that cannot be read kills the process with `SIGSEGV`.
+pub fn rt_sigreturn(mut_context: &mut ExceptionMutContext) -> SyscallResult {
+    let mut frame = [0; FRAME_SIZE];
+    let sp = mut_context.register_context.x[SP];
+    match copy_from_user(mut_context, sp, &mut frame) {
+        Ok(()) => signal::restore_frame(mut_context, &frame),
+        Err(_) => task::exit_group(ExitReason::Signal(SIGSEGV, None)),
+    }
+    Err(Errno::EJUSTRETURN)
+}
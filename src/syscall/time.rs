@@ -0,0 +1,116 @@
+use core::time::Duration;
+
+use crate::{
+    clock::{self, Instant},
+    exception::ExceptionMutContext,
+    task::{self, Sleep},
+};
+
+use super::{copy_from_user, copy_to_user, Errno, SyscallResult};
+
+/// `clockid_t` values.
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// `struct timespec` on RV64.
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+impl Timespec {
+    const SIZE: usize = 16;
+
+    fn read(mut_context: &ExceptionMutContext, addr: usize) -> Result<Timespec, Errno> {
+        let mut bytes = [0; Timespec::SIZE];
+        copy_from_user(mut_context, addr, &mut bytes)?;
+        Ok(Timespec {
+            tv_sec: i64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            tv_nsec: i64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        })
+    }
+
+    fn write(&self, mut_context: &ExceptionMutContext, addr: usize) -> Result<(), Errno> {
+        let mut bytes = [0; Timespec::SIZE];
+        bytes[..8].copy_from_slice(&self.tv_sec.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.tv_nsec.to_le_bytes());
+        copy_to_user(mut_context, addr, &bytes)
+    }
+
+    fn to_duration(&self) -> Result<Duration, Errno> {
+        if self.tv_sec < 0 || !(0..NANOS_PER_SEC).contains(&self.tv_nsec) {
+            return Err(Errno::EINVAL);
+        }
+        Ok(Duration::new(self.tv_sec as u64, self.tv_nsec as u32))
+    }
+}
+
+impl From<Duration> for Timespec {
+    fn from(duration: Duration) -> Self {
+        Timespec {
+            tv_sec: duration.as_secs() as i64,
+            tv_nsec: duration.subsec_nanos() as i64,
+        }
+    }
+}
+
+/// `clock_gettime(clockid, tp)`
+pub fn clock_gettime(
+    mut_context: &ExceptionMutContext,
+    clock_id: usize,
+    tp: usize,
+) -> SyscallResult {
+    let now = match clock_id {
+        CLOCK_REALTIME => clock::realtime(),
+        CLOCK_MONOTONIC => Instant::now().since_boot(),
+        _ => return Err(Errno::EINVAL),
+    };
+    Timespec::from(now).write(mut_context, tp)?;
+    Ok(0)
+}
+
+/// `gettimeofday(tv, tz)`
+///
+/// - The kernel keeps UTC, so a non-null `tz` reads as zero minutes west, without DST.
+pub fn gettimeofday(mut_context: &ExceptionMutContext, tv: usize, tz: usize) -> SyscallResult {
+    let now = clock::realtime();
+    if tv != 0 {
+        // `struct timeval` has microseconds where `struct timespec` has nanoseconds.
+        let mut timeval = [0; 16];
+        timeval[..8].copy_from_slice(&(now.as_secs() as i64).to_le_bytes());
+        timeval[8..].copy_from_slice(&(now.subsec_micros() as i64).to_le_bytes());
+        copy_to_user(mut_context, tv, &timeval)?;
+    }
+    if tz != 0 {
+        copy_to_user(mut_context, tz, &[0; 8])?;
+    }
+    Ok(0)
+}
+
+/// `nanosleep(req, rem)`
+///
+/// - The task blocks on a timer, restarting the call each time it is woken until the timer
+///   fires.
+/// - A signal handler cuts the sleep short with `EINTR`, even under `SA_RESTART`, and the time
+///   left goes to a non-null `rem`; see [`interrupt_nanosleep`].
+pub fn nanosleep(mut_context: &ExceptionMutContext, req: usize, rem: usize) -> SyscallResult {
+    let duration = Timespec::read(mut_context, req)?.to_duration()?;
+    if clock::ticks(duration) == 0 || task::sleep(duration, rem) {
+        return Ok(0);
+    }
+    Err(Errno::ERESTARTSYS)
+}
+
+/// End `sleep` for a signal handler about to run, returning the error `nanosleep` fails with.
+pub fn interrupt_nanosleep(mut_context: &ExceptionMutContext, sleep: Sleep) -> Errno {
+    sleep.timer.cancel();
+    if sleep.rem != 0 {
+        let left = sleep.deadline.duration_since(Instant::now());
+        if Timespec::from(left).write(mut_context, sleep.rem).is_err() {
+            return Errno::EFAULT;
+        }
+    }
+    Errno::EINTR
+}
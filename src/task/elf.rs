@@ -0,0 +1,313 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::{
+    clock::{self, Instant},
+    fs::{self, Dentry, FileType, FsError, Inode},
+    mm::{
+        address_space::{AddressSpace, VmaError, USER_END},
+        page_ceil,
+        page_table::PteFlags,
+        PAGE_SIZE,
+    },
+};
+
+/// The most bytes of arguments and environment strings, with their NULs, a program gets.
+pub const ARG_MAX: usize = 128 * 1024;
+
+/// Where a position-independent executable is loaded.
+const PIE_BASE: usize = 0x1_0000_0000;
+const STACK_TOP: usize = USER_END;
+const STACK_SIZE: usize = 16 * PAGE_SIZE;
+const STACK_MAX_SIZE: usize = 8 * 1024 * 1024;
+/// The page that signal handlers return to, just below the stack's limit.
+pub const SIGRETURN_TRAMPOLINE: usize = STACK_TOP - STACK_MAX_SIZE - PAGE_SIZE;
+/// `li a7, 139` (`rt_sigreturn`); `ecall`
+const SIGRETURN_CODE: [u32; 2] = [0x08b0_0893, 0x0000_0073];
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// Auxiliary vector keys, from `elf.h`.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_SECURE: usize = 23;
+const AT_RANDOM: usize = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    Fs(FsError),
+    /// Not a regular file with an execute bit.
+    PermissionDenied,
+    /// Not a 64-bit little-endian RISC-V executable without an interpreter, or one whose
+    /// segments do not fit user space.
+    NotExecutable,
+    /// The arguments and environment exceed [`ARG_MAX`].
+    ArgumentsTooLong,
+    OutOfMemory,
+}
+
+impl From<FsError> for ElfError {
+    fn from(error: FsError) -> Self {
+        ElfError::Fs(error)
+    }
+}
+
+impl From<VmaError> for ElfError {
+    fn from(error: VmaError) -> Self {
+        match error {
+            VmaError::OutOfRange | VmaError::Overlap => ElfError::NotExecutable,
+            VmaError::OutOfMemory => ElfError::OutOfMemory,
+        }
+    }
+}
+
+/// A loaded program, ready to run from `entry` with its stack pointer at `sp`.
+pub struct Image {
+    pub address_space: AddressSpace,
+    pub entry: usize,
+    pub sp: usize,
+}
+
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    file_size: usize,
+    mem_size: usize,
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+        };
+        ProgramHeader {
+            kind: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            flags: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            offset: u64_at(8),
+            vaddr: u64_at(16),
+            file_size: u64_at(32),
+            mem_size: u64_at(40),
+        }
+    }
+
+    fn pte_flags(&self) -> PteFlags {
+        let mut flags = PteFlags::EMPTY;
+        for (bit, flag) in [
+            (PF_R, PteFlags::R),
+            (PF_W, PteFlags::W),
+            (PF_X, PteFlags::X),
+        ] {
+            if self.flags & bit != 0 {
+                flags = flags | flag;
+            }
+        }
+        flags
+    }
+}
+
+/// Load the executable at `path` into a new address space, with `argv` and `envp` on its stack.
+///
+/// - Statically linked executables only; a `PT_INTERP` segment is [`ElfError::NotExecutable`].
+/// - Only the headers and the segments are read from the file. Segments are mapped eagerly;
+///   `.bss` past their last page is zero-filled on first touch.
+/// - A page at [`SIGRETURN_TRAMPOLINE`] holds the code that signal handlers return to.
+pub fn load(
+    start: Option<Arc<Dentry>>,
+    path: &str,
+    argv: &[Vec<u8>],
+    envp: &[Vec<u8>],
+) -> Result<Image, ElfError> {
+    let dentry = fs::lookup(start, path, true)?;
+    let inode = dentry.inode();
+    let metadata = inode.metadata()?;
+    if metadata.file_type != FileType::Regular || metadata.mode & 0o111 == 0 {
+        return Err(ElfError::PermissionDenied);
+    }
+    let file_size = metadata.size as usize;
+
+    let mut ehdr = [0; EHDR_SIZE];
+    read_exact(inode.as_ref(), 0, &mut ehdr)?;
+    if ehdr[..4] != *b"\x7fELF" || ehdr[4] != ELFCLASS64 || ehdr[5] != ELFDATA2LSB {
+        return Err(ElfError::NotExecutable);
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes(ehdr[offset..offset + 2].try_into().unwrap());
+    let u64_at =
+        |offset: usize| u64::from_le_bytes(ehdr[offset..offset + 8].try_into().unwrap()) as usize;
+    let base = match u16_at(16) {
+        ET_EXEC => 0,
+        ET_DYN => PIE_BASE,
+        _ => return Err(ElfError::NotExecutable),
+    };
+    let phoff = u64_at(32);
+    let phnum = u16_at(56) as usize;
+    if u16_at(18) != EM_RISCV
+        || u16_at(54) as usize != PHDR_SIZE
+        || phoff
+            .checked_add(phnum * PHDR_SIZE)
+            .is_none_or(|end| end > file_size)
+    {
+        return Err(ElfError::NotExecutable);
+    }
+    let mut phdrs = vec![0; phnum * PHDR_SIZE];
+    read_exact(inode.as_ref(), phoff, &mut phdrs)?;
+    let headers: Vec<ProgramHeader> = phdrs
+        .chunks_exact(PHDR_SIZE)
+        .map(ProgramHeader::parse)
+        .collect();
+
+    let mut address_space = AddressSpace::new().ok_or(ElfError::OutOfMemory)?;
+    let mut phdr = 0;
+    for header in &headers {
+        match header.kind {
+            PT_INTERP => return Err(ElfError::NotExecutable),
+            PT_PHDR => {
+                phdr = base + header.vaddr;
+                continue;
+            }
+            PT_LOAD => {}
+            _ => continue,
+        }
+        if header.file_size > header.mem_size
+            || header
+                .offset
+                .checked_add(header.file_size)
+                .is_none_or(|end| end > file_size)
+        {
+            return Err(ElfError::NotExecutable);
+        }
+        if phdr == 0 && (header.offset..header.offset + header.file_size).contains(&phoff) {
+            phdr = base + header.vaddr + (phoff - header.offset);
+        }
+        let start = base
+            .checked_add(header.vaddr)
+            .ok_or(ElfError::NotExecutable)?;
+        let end = start
+            .checked_add(header.mem_size)
+            .ok_or(ElfError::NotExecutable)?;
+        // The file data, and the zeros that follow it up to the end of its last page.
+        let data_end = page_ceil(start + header.file_size).min(end);
+        let mut data = vec![0; data_end - start];
+        read_exact(inode.as_ref(), header.offset, &mut data[..header.file_size])?;
+        if !data.is_empty() {
+            address_space.map_data(start, &data, header.pte_flags())?;
+        }
+        if data_end < end {
+            address_space.map_anonymous(data_end, end - data_end, header.pte_flags())?;
+        }
+    }
+    let entry = base + u64_at(24);
+    let trampoline: Vec<u8> = SIGRETURN_CODE
+        .iter()
+        .flat_map(|instruction| instruction.to_le_bytes())
+        .collect();
+    address_space.map_data(SIGRETURN_TRAMPOLINE, &trampoline, PteFlags::R | PteFlags::X)?;
+
+    let auxv = [
+        (AT_PHDR, phdr),
+        (AT_PHENT, PHDR_SIZE),
+        (AT_PHNUM, phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_ENTRY, entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_SECURE, 0),
+    ];
+    let sp = build_stack(&mut address_space, argv, envp, &auxv)?;
+    Ok(Image {
+        address_space,
+        entry,
+        sp,
+    })
+}
+
+/// Fill `buf` from byte `offset` of the executable.
+///
+/// - A file that ends first, having shrunk since its size was checked, is not executable.
+fn read_exact(inode: &dyn Inode, offset: usize, buf: &mut [u8]) -> Result<(), ElfError> {
+    let mut len = 0;
+    while len < buf.len() {
+        match inode.read_at((offset + len) as u64, &mut buf[len..])? {
+            0 => return Err(ElfError::NotExecutable),
+            read => len += read,
+        }
+    }
+    Ok(())
+}
+
+/// Map the stack and lay out what `_start` expects on it, returning the stack pointer.
+///
+/// - From the top: 16 bytes for `AT_RANDOM`, the strings, then `argc`, the `argv` and `envp`
+///   pointer arrays with their NULLs, and the auxiliary vector ending in `AT_NULL`.
+fn build_stack(
+    address_space: &mut AddressSpace,
+    argv: &[Vec<u8>],
+    envp: &[Vec<u8>],
+    auxv: &[(usize, usize)],
+) -> Result<usize, ElfError> {
+    let strings_len: usize = argv.iter().chain(envp).map(|string| string.len() + 1).sum();
+    if strings_len > ARG_MAX {
+        return Err(ElfError::ArgumentsTooLong);
+    }
+    // Not secret: there is no entropy source, only the time.
+    let random = (clock::realtime().as_nanos() ^ Instant::now().ticks() as u128).to_le_bytes();
+    let random_addr = STACK_TOP - random.len();
+    let strings_start = random_addr - strings_len;
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + 2);
+    let sp = (strings_start - words * 8) & !15;
+
+    let mut stack = vec![0; STACK_TOP - sp];
+    let mut words_out = Vec::with_capacity(words);
+    words_out.push(argv.len());
+    let mut string_addr = strings_start;
+    for strings in [argv, envp] {
+        for string in strings {
+            let offset = string_addr - sp;
+            stack[offset..offset + string.len()].copy_from_slice(string);
+            words_out.push(string_addr);
+            string_addr += string.len() + 1;
+        }
+        words_out.push(0);
+    }
+    for (key, value) in auxv.iter().chain(&[(AT_RANDOM, random_addr), (AT_NULL, 0)]) {
+        words_out.extend([*key, *value]);
+    }
+    for (index, word) in words_out.iter().enumerate() {
+        stack[index * 8..index * 8 + 8].copy_from_slice(&word.to_le_bytes());
+    }
+    stack[random_addr - sp..].copy_from_slice(&random);
+
+    address_space.map_stack(
+        STACK_TOP,
+        page_ceil(STACK_TOP - sp).max(STACK_SIZE),
+        STACK_MAX_SIZE,
+    )?;
+    address_space
+        .write(sp, &stack)
+        .map_err(|_| ElfError::OutOfMemory)?;
+    Ok(sp)
+}
@@ -0,0 +1,799 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{arch::asm, mem, time::Duration};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+pub mod elf;
+pub mod signal;
+mod wait_queue;
+
+pub use wait_queue::WaitQueue;
+
+use signal::{SigInfo, SignalActions, Signals, CLD_EXITED, SIGCHLD};
+
+use crate::{
+    clock::Instant,
+    exception::{
+        are_interrupts_enabled, fault::FaultReport, without_interrupts, ExceptionMutContext,
+        RegisterContext,
+    },
+    fs::{Dentry, FdTable},
+    mm::{address_space::AddressSpace, page_table},
+    supervisor_print,
+    timer::Timer,
+    Spp, Sstatus,
+};
+
+const SP: usize = 2;
+
+/// The boot thread, which idles in `main` once there is nothing else to run.
+pub const IDLE: TaskId = TaskId(0);
+
+const FLAT_STACK_SIZE: usize = 4096 * 4;
+
+/// `sip.SSIP`, raised by a hart on itself to yield.
+const SSIP: usize = 1 << 1;
+
+/// How long a task runs before a waiting one preempts it.
+const TIME_SLICE: Duration = Duration::from_secs(1);
+
+/// Parents in `wait4`, woken whenever a task exits.
+static CHILD_EXIT: WaitQueue = WaitQueue::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(pub usize);
+
+/// What `__restore` needs to resume a task.
+#[derive(Debug, Clone)]
+pub struct TaskContext {
+    pub registers: RegisterContext,
+    pub sepc: usize,
+    pub sstatus: usize,
+}
+
+#[derive(Debug)]
+pub enum ExitReason {
+    /// `exit` or `exit_group` with a status.
+    Exit(i32),
+    /// Killed by a signal, with the report of the fault that raised it, if any.
+    Signal(u32, Option<Box<FaultReport>>),
+}
+
+impl ExitReason {
+    /// The status `wait4` reports, as the `W*` macros decode it.
+    pub fn wait_status(&self) -> u32 {
+        match self {
+            ExitReason::Exit(status) => (*status as u32 & 0xff) << 8,
+            ExitReason::Signal(signo, _) => *signo & 0x7f,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TaskState {
+    Ready,
+    Running,
+    /// Waiting on a [`WaitQueue`].
+    Blocked,
+    /// Stopped by a signal until `SIGCONT`.
+    Stopped,
+    /// A process's first task stays exited until its parent reaps it.
+    Exited(ExitReason),
+}
+
+impl TaskState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskState::Ready => "ready",
+            TaskState::Running => "running",
+            TaskState::Blocked => "blocked",
+            TaskState::Stopped => "stopped",
+            TaskState::Exited(_) => "exited",
+        }
+    }
+}
+
+/// A `nanosleep` in progress, kept while the call restarts each time the task is woken.
+#[derive(Debug)]
+pub struct Sleep {
+    pub deadline: Instant,
+    /// Wakes the task at `deadline`.
+    pub timer: Timer,
+    /// Where the caller wants the time left if a signal handler cuts the sleep short.
+    pub rem: usize,
+}
+
+/// Per-task counters of kernel work done on the task's behalf.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaskStats {
+    /// Misaligned loads and stores emulated by the fault handler.
+    pub misaligned_emulations: usize,
+    /// Illegal instructions emulated by the hooks in `exception::illegal`.
+    pub emulated_instructions: usize,
+}
+
+pub struct Task {
+    /// The process: the ID of its first task, which the threads it clones share.
+    pub tgid: TaskId,
+    /// The process that reaps this one; `None` for tasks the kernel spawned, and orphans when
+    /// there is no init.
+    pub parent: Option<TaskId>,
+    /// The parent that `vfork` suspended until this task calls `execve` or exits.
+    vfork_parent: Option<TaskId>,
+    pub state: TaskState,
+    /// Signal dispositions, shared by the threads of a process.
+    pub signal_actions: Arc<Mutex<SignalActions>>,
+    pub signals: Signals,
+    /// The `nanosleep` the task is in.
+    pub sleep: Option<Sleep>,
+    /// What a blocking `write` wrote before it blocked, kept while the call restarts.
+    pub written: usize,
+    /// On a process's first task: the stop or continue `wait4` has yet to report.
+    pub job_event: Option<JobEvent>,
+    pub context: TaskContext,
+    pub stats: TaskStats,
+    pub address_space: Option<Arc<Mutex<AddressSpace>>>,
+    pub files: Arc<Mutex<FdTable>>,
+    /// The working directory; `None` means the root.
+    pub cwd: Option<Arc<Dentry>>,
+    /// Stack of a kernel thread or of a task running without an address space.
+    _stack: Option<Vec<u8>>,
+}
+
+impl Task {
+    fn new(
+        entry: usize,
+        sp: usize,
+        mode: Spp,
+        address_space: Option<AddressSpace>,
+        stack: Option<Vec<u8>>,
+    ) -> Self {
+        let sstatus: usize;
+        unsafe {
+            asm!("csrr {}, sstatus", out(reg) sstatus);
+        }
+        let mut sstatus = Sstatus(sstatus);
+        sstatus.set_mode_before_exception(mode);
+        sstatus.set_interrupt_enabled_before_exception(true);
+
+        let mut registers = RegisterContext { x: [0; 32] };
+        registers.x[SP] = sp;
+
+        Task {
+            tgid: IDLE,
+            parent: None,
+            vfork_parent: None,
+            state: TaskState::Ready,
+            signal_actions: Arc::new(Mutex::new(SignalActions::new())),
+            signals: Signals::default(),
+            sleep: None,
+            written: 0,
+            job_event: None,
+            context: TaskContext {
+                registers,
+                sepc: entry,
+                sstatus: sstatus.0,
+            },
+            stats: TaskStats::default(),
+            address_space: address_space.map(|space| Arc::new(Mutex::new(space))),
+            files: Arc::new(Mutex::new(FdTable::with_console())),
+            cwd: None,
+            _stack: stack,
+        }
+    }
+
+    /// A user task running directly on physical memory, like `user_pit`.
+    pub fn new_flat(entry: usize) -> Self {
+        let stack = vec![0; FLAT_STACK_SIZE];
+        let sp = stack.as_ptr() as usize + FLAT_STACK_SIZE;
+        Task::new(entry, sp, Spp::User, None, Some(stack))
+    }
+
+    /// A user task running in its own address space.
+    pub fn new_user(entry: usize, sp: usize, address_space: AddressSpace) -> Self {
+        Task::new(entry, sp, Spp::User, Some(address_space), None)
+    }
+
+    /// A kernel thread: supervisor code with interrupts enabled, which may sleep on a
+    /// [`WaitQueue`].
+    pub fn new_kernel(entry: fn() -> !) -> Self {
+        let stack = vec![0; FLAT_STACK_SIZE];
+        let sp = stack.as_ptr() as usize + FLAT_STACK_SIZE;
+        Task::new(entry as usize, sp, Spp::Supervisor, None, Some(stack))
+    }
+
+    /// A task cloned from the current one, resuming from `context`, for [`spawn_clone`].
+    pub fn new_clone(
+        context: TaskContext,
+        address_space: Option<Arc<Mutex<AddressSpace>>>,
+        files: Arc<Mutex<FdTable>>,
+        cwd: Option<Arc<Dentry>>,
+        signal_actions: Arc<Mutex<SignalActions>>,
+    ) -> Self {
+        Task {
+            tgid: IDLE,
+            parent: None,
+            vfork_parent: None,
+            state: TaskState::Ready,
+            signal_actions,
+            signals: Signals::default(),
+            sleep: None,
+            written: 0,
+            job_event: None,
+            context,
+            stats: TaskStats::default(),
+            address_space,
+            files,
+            cwd,
+            _stack: None,
+        }
+    }
+
+    fn satp(&self) -> usize {
+        match &self.address_space {
+            Some(space) => space.lock().satp(),
+            None => 0,
+        }
+    }
+}
+
+struct Scheduler {
+    tasks: BTreeMap<TaskId, Task>,
+    ready: VecDeque<TaskId>,
+    current: TaskId,
+    next_id: usize,
+    need_resched: bool,
+    /// The process that adopts orphans.
+    init: Option<TaskId>,
+}
+
+/// A change in whether a process runs, other than its exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobEvent {
+    /// Stopped by the signal.
+    Stopped(u32),
+    /// Resumed by `SIGCONT`.
+    Continued,
+}
+
+/// Which [`JobEvent`]s `wait4` reports besides exits.
+#[derive(Debug, Clone, Copy)]
+pub struct WaitFor {
+    pub stopped: bool,
+    pub continued: bool,
+}
+
+/// A child process as `wait4` sees it.
+#[derive(Debug)]
+pub enum ChildState {
+    /// No child matches.
+    None,
+    /// A child matches, but none has anything to report.
+    Running,
+    /// A child exited and was reaped.
+    Exited(TaskId, ExitReason),
+    /// A child stopped by the signal.
+    Stopped(TaskId, u32),
+    /// A stopped child was resumed.
+    Continued(TaskId),
+}
+
+impl Scheduler {
+    fn allocate_id(&mut self) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Make a blocked task ready again.
+    fn wake(&mut self, id: TaskId) {
+        let Some(task) = self.tasks.get_mut(&id) else {
+            return;
+        };
+        if !matches!(task.state, TaskState::Blocked) {
+            return;
+        }
+        task.state = TaskState::Ready;
+        self.ready.push_back(id);
+        self.need_resched = true;
+    }
+
+    /// Whether a task of process `tgid` other than its first one has yet to exit.
+    fn has_live_threads(&self, tgid: TaskId) -> bool {
+        self.tasks.iter().any(|(id, task)| {
+            task.tgid == tgid && *id != tgid && !matches!(task.state, TaskState::Exited(_))
+        })
+    }
+
+    /// Whether the process `tgid` ended: its first task exited, and every other one.
+    fn has_ended(&self, tgid: TaskId) -> bool {
+        matches!(self.tasks[&tgid].state, TaskState::Exited(_)) && !self.has_live_threads(tgid)
+    }
+
+    /// Whether a child process of the current one matches `pid`, and one that ended or has an
+    /// event in `wait_for` to report.
+    fn find_child(&self, pid: Option<TaskId>, wait_for: WaitFor) -> (bool, Option<TaskId>) {
+        let tgid = self.tasks[&self.current].tgid;
+        let mut children = self.tasks.iter().filter(|(id, task)| {
+            task.parent == Some(tgid) && task.tgid == **id && pid.is_none_or(|pid| pid == **id)
+        });
+        let mut has_child = false;
+        let found = children.find(|(id, task)| {
+            has_child = true;
+            self.has_ended(**id)
+                || match task.job_event {
+                    Some(JobEvent::Stopped(_)) => wait_for.stopped,
+                    Some(JobEvent::Continued) => wait_for.continued,
+                    None => false,
+                }
+        });
+        (has_child, found.map(|(id, _)| *id))
+    }
+
+    /// Hand the children of `parents` to init, and let go of the zombies nobody will reap.
+    fn reparent(&mut self, parents: &[TaskId]) -> Vec<Task> {
+        if self.init.is_some_and(|init| parents.contains(&init)) {
+            self.init = None;
+        }
+        for task in self.tasks.values_mut() {
+            if task.parent.is_some_and(|parent| parents.contains(&parent)) {
+                task.parent = self.init;
+            }
+        }
+        let unreaped: Vec<TaskId> = self
+            .tasks
+            .iter()
+            .filter(|(id, task)| {
+                **id != self.current
+                    && task.parent.is_none()
+                    && matches!(task.state, TaskState::Exited(_))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        unreaped
+            .iter()
+            .map(|id| self.tasks.remove(id).unwrap())
+            .collect()
+    }
+
+    /// Take the tasks of `tgid` other than the current one off the hart for good.
+    fn remove_threads(&mut self, tgid: TaskId) -> Vec<(TaskId, Task)> {
+        let current = self.current;
+        let threads: Vec<TaskId> = self
+            .tasks
+            .iter()
+            .filter(|(id, task)| task.tgid == tgid && **id != current)
+            .map(|(id, _)| *id)
+            .collect();
+        self.ready.retain(|id| !threads.contains(id));
+        threads
+            .iter()
+            .map(|id| (*id, self.tasks.remove(id).unwrap()))
+            .collect()
+    }
+}
+
+lazy_static! {
+    static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+        tasks: BTreeMap::new(),
+        ready: VecDeque::new(),
+        current: IDLE,
+        next_id: IDLE.0 + 1,
+        need_resched: false,
+        init: None,
+    });
+    /// Armed only while a task waits for the hart, so an idle hart takes no ticks.
+    static ref TIME_SLICE_TIMER: Mutex<Option<Timer>> = Mutex::new(None);
+}
+
+/// Adopt the running boot thread as the idle task.
+///
+/// - Its context is captured the first time it is switched out.
+pub fn init() {
+    let idle = Task {
+        tgid: IDLE,
+        parent: None,
+        vfork_parent: None,
+        state: TaskState::Running,
+        signal_actions: Arc::new(Mutex::new(SignalActions::new())),
+        signals: Signals::default(),
+        sleep: None,
+        written: 0,
+        job_event: None,
+        context: TaskContext {
+            registers: RegisterContext { x: [0; 32] },
+            sepc: 0,
+            sstatus: 0,
+        },
+        stats: TaskStats::default(),
+        address_space: None,
+        files: Arc::new(Mutex::new(FdTable::new())),
+        cwd: None,
+        _stack: None,
+    };
+    SCHEDULER.lock().tasks.insert(IDLE, idle);
+}
+
+/// Start `task` as a process of its own with no parent.
+pub fn spawn(mut task: Task) -> TaskId {
+    let mut scheduler = SCHEDULER.lock();
+    let id = scheduler.allocate_id();
+    task.tgid = id;
+    scheduler.tasks.insert(id, task);
+    scheduler.ready.push_back(id);
+    update_time_slice(&scheduler);
+    id
+}
+
+/// Start `child`, cloned from the current task, as a child process or, with `is_thread`, as a
+/// thread of the current process.
+pub fn spawn_clone(mut child: Task, is_thread: bool) -> TaskId {
+    let mut scheduler = SCHEDULER.lock();
+    let id = scheduler.allocate_id();
+    let current = &scheduler.tasks[&scheduler.current];
+    (child.tgid, child.parent) = match is_thread {
+        true => (current.tgid, current.parent),
+        false => (id, Some(current.tgid)),
+    };
+    scheduler.tasks.insert(id, child);
+    scheduler.ready.push_back(id);
+    update_time_slice(&scheduler);
+    id
+}
+
+/// Make `id` the init process, which adopts the children of processes that exit.
+pub fn set_init(id: TaskId) {
+    SCHEDULER.lock().init = Some(id);
+}
+
+/// - Safe with interrupts on: an interrupt taken while the scheduler is locked would spin on it
+///   in `schedule`.
+pub fn current_id() -> TaskId {
+    without_interrupts(|| SCHEDULER.lock().current)
+}
+
+/// Whether the caller may sleep on a [`WaitQueue`]: a kernel thread with interrupts on, rather
+/// than the idle task or a trap handler, which have to poll.
+pub fn can_sleep() -> bool {
+    are_interrupts_enabled() && current_id() != IDLE
+}
+
+/// Visit every task with the current one flagged.
+///
+/// - Returns `false` without calling `f` if the scheduler is locked, e.g. by the code that a
+///   panic or a breakpoint interrupted.
+pub fn for_each_task(mut f: impl FnMut(TaskId, &Task, bool)) -> bool {
+    let Some(scheduler) = SCHEDULER.try_lock() else {
+        return false;
+    };
+    for (id, task) in &scheduler.tasks {
+        f(*id, task, *id == scheduler.current);
+    }
+    true
+}
+
+/// - `f` must not call back into the scheduler.
+pub fn with_current<R>(f: impl FnOnce(&mut Task) -> R) -> R {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    f(scheduler.tasks.get_mut(&current).unwrap())
+}
+
+/// - `f` must not call back into the scheduler.
+pub fn with_task<R>(id: TaskId, f: impl FnOnce(&mut Task) -> R) -> Option<R> {
+    SCHEDULER.lock().tasks.get_mut(&id).map(f)
+}
+
+/// Switch away from the current task at the end of this exception.
+pub fn request_reschedule() {
+    SCHEDULER.lock().need_resched = true;
+}
+
+/// Give up the hart from a kernel thread.
+///
+/// - Raises a software interrupt on this hart, as `ecall` from S-mode goes to the firmware.
+/// - Returns once the thread is scheduled again, right away if nothing else is ready.
+pub fn yield_now() {
+    unsafe {
+        asm!("csrs sip, {}", in(reg) SSIP);
+    }
+}
+
+/// Mark the current task blocked and switch away at the end of this exception, or at the next
+/// interrupt for a kernel thread.
+///
+/// - Interrupts must be off, so that a wake-up cannot come between the caller's check and this.
+fn block_current() -> TaskId {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    assert_ne!(current, IDLE, "The idle task cannot block");
+    scheduler.tasks.get_mut(&current).unwrap().state = TaskState::Blocked;
+    scheduler.need_resched = true;
+    current
+}
+
+/// Block the current task until `deadline`.
+///
+/// - A user task sleeps from the end of this exception; a kernel thread must yield next.
+pub fn block_until(deadline: Instant) {
+    without_interrupts(|| {
+        let id = block_current();
+        Timer::at(deadline, move || wake(id));
+    });
+}
+
+/// Sleep the current user task for `duration` from the first of the calls that restart the sleep.
+///
+/// - Returns `true` once the time has passed; otherwise the task blocks, and the call restarts
+///   when it is woken.
+/// - A signal handler ends the sleep early, through [`signal::deliver`].
+pub fn sleep(duration: Duration, rem: usize) -> bool {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        let task = scheduler.tasks.get_mut(&current).unwrap();
+        match &task.sleep {
+            Some(sleep) if !sleep.timer.is_pending() => {
+                task.sleep = None;
+                return true;
+            }
+            // Woken by a signal that did not interrupt the sleep.
+            Some(_) => (),
+            None => {
+                let deadline = Instant::now() + duration;
+                task.sleep = Some(Sleep {
+                    deadline,
+                    timer: Timer::at(deadline, move || wake(current)),
+                    rem,
+                });
+            }
+        }
+        drop(scheduler);
+        block_current();
+        false
+    })
+}
+
+/// Make a blocked task ready again.
+fn wake(id: TaskId) {
+    SCHEDULER.lock().wake(id);
+}
+
+/// Preempt the running task periodically if another one is ready.
+fn update_time_slice(scheduler: &Scheduler) {
+    let mut timer = TIME_SLICE_TIMER.lock();
+    match (scheduler.ready.is_empty(), timer.is_some()) {
+        (false, false) => {
+            *timer = Some(Timer::every(TIME_SLICE, || {
+                supervisor_print!(".");
+                request_reschedule();
+            }));
+        }
+        (true, true) => {
+            timer.take().unwrap().cancel();
+        }
+        _ => (),
+    }
+}
+
+/// Terminate the current task; it never returns to user mode.
+///
+/// - The first task of a process stays as a zombie until its parent reaps it with `wait4`; a
+///   thread goes right away.
+/// - A process ends with its last task, even if its first one exited before: only then do its
+///   children go to the init process, its parent get `SIGCHLD`, and `wait4` see it.
+pub fn exit_current(reason: ExitReason) {
+    exit(reason, false);
+}
+
+/// Terminate every task of the current process, which reports `reason`.
+pub fn exit_group(reason: ExitReason) {
+    exit(reason, true);
+}
+
+fn exit(reason: ExitReason, is_group: bool) {
+    // Dropped once the scheduler is unlocked, as closing a pipe end wakes tasks.
+    let mut released = Vec::new();
+    let mut leader_files = None;
+    let (files, vfork_parent, parent) = {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        assert_ne!(current, IDLE, "The idle task cannot exit");
+        let tgid = scheduler.tasks[&current].tgid;
+        let mut reason = Some(reason);
+        if is_group {
+            for (id, mut task) in scheduler.remove_threads(tgid) {
+                if id != tgid {
+                    released.push(task);
+                    continue;
+                }
+                // The first task reports for the process, whichever task ends it. The current
+                // task still holds the address space it shares.
+                task.state = TaskState::Exited(reason.take().unwrap());
+                task.address_space = None;
+                task.cwd = None;
+                leader_files = Some(mem::replace(
+                    &mut task.files,
+                    Arc::new(Mutex::new(FdTable::new())),
+                ));
+                scheduler.tasks.insert(id, task);
+            }
+        }
+
+        let task = scheduler.tasks.get_mut(&current).unwrap();
+        task.state = TaskState::Exited(reason.unwrap_or(ExitReason::Exit(0)));
+        let files = mem::replace(&mut task.files, Arc::new(Mutex::new(FdTable::new())));
+        let vfork_parent = task.vfork_parent.take();
+        scheduler.need_resched = true;
+
+        // The process ends with its last task; until then an exited first task only holds its
+        // place.
+        let parent = match scheduler.has_live_threads(tgid) {
+            true => None,
+            false => {
+                let parent = scheduler.tasks[&tgid].parent;
+                released.extend(scheduler.reparent(&[tgid]));
+                parent.map(|parent| (parent, tgid))
+            }
+        };
+        (files, vfork_parent, parent)
+    };
+    drop((files, leader_files, released));
+    if let Some(parent) = vfork_parent {
+        wake(parent);
+    }
+    if let Some((parent, child)) = parent {
+        let _ = signal::send_to_process(parent, SigInfo::user(SIGCHLD, CLD_EXITED, child));
+    }
+    CHILD_EXIT.wake_all();
+}
+
+/// Replace the current process's memory with `address_space`, as `execve` does.
+///
+/// - The other tasks of the process go, and the current one takes over the process's ID.
+/// - The file descriptor table is unshared, and its close-on-exec descriptors closed.
+/// - Signal handlers go back to the default, and the dispositions are unshared.
+/// - A parent suspended by `vfork` resumes.
+pub fn exec_current(address_space: AddressSpace) {
+    let (released, old_space, files, vfork_parent) = {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        let tgid = scheduler.tasks[&current].tgid;
+        let released = scheduler.remove_threads(tgid);
+        let mut task = scheduler.tasks.remove(&current).unwrap();
+
+        let address_space = Arc::new(Mutex::new(address_space));
+        page_table::activate(address_space.lock().satp());
+        // Freed only now that `satp` no longer points to it.
+        let old_space = task.address_space.replace(address_space);
+        task._stack = None;
+        let mut files = task.files.lock().clone();
+        files.close_on_exec();
+        let files = mem::replace(&mut task.files, Arc::new(Mutex::new(files)));
+        let signal_actions = task.signal_actions.lock().for_exec();
+        task.signal_actions = Arc::new(Mutex::new(signal_actions));
+        let vfork_parent = task.vfork_parent.take();
+
+        scheduler.tasks.insert(tgid, task);
+        scheduler.current = tgid;
+        (released, old_space, files, vfork_parent)
+    };
+    drop((released, old_space, files));
+    if let Some(parent) = vfork_parent {
+        wake(parent);
+    }
+}
+
+/// Suspend the current task until `child` calls `execve` or exits, as `vfork` does.
+///
+/// - The task sleeps from the end of this exception.
+pub fn wait_for_vfork(child: TaskId) {
+    without_interrupts(|| {
+        let parent = block_current();
+        if let Some(task) = SCHEDULER.lock().tasks.get_mut(&child) {
+            task.vfork_parent = Some(parent);
+        }
+    });
+}
+
+/// Reap an exited child process of the current one, `pid` or any with `None`, or take the
+/// event in `wait_for` it has to report.
+pub fn reap_child(pid: Option<TaskId>, wait_for: WaitFor) -> ChildState {
+    let mut scheduler = SCHEDULER.lock();
+    let id = match scheduler.find_child(pid, wait_for) {
+        (false, _) => return ChildState::None,
+        (true, None) => return ChildState::Running,
+        (true, Some(id)) => id,
+    };
+    if !scheduler.has_ended(id) {
+        return match scheduler.tasks.get_mut(&id).unwrap().job_event.take() {
+            Some(JobEvent::Stopped(signo)) => ChildState::Stopped(id, signo),
+            Some(JobEvent::Continued) => ChildState::Continued(id),
+            None => unreachable!(),
+        };
+    }
+    let task = scheduler.tasks.remove(&id).unwrap();
+    drop(scheduler);
+    let TaskState::Exited(reason) = task.state else {
+        unreachable!()
+    };
+    ChildState::Exited(id, reason)
+}
+
+/// Block the current task until a child process matching `pid` exits or has an event in
+/// `wait_for`, from a system call that restarts once woken.
+///
+/// - Returns `false` if one has already, or none matches.
+pub fn block_for_child(pid: Option<TaskId>, wait_for: WaitFor) -> bool {
+    CHILD_EXIT.block_if(|| matches!(SCHEDULER.lock().find_child(pid, wait_for), (true, None)))
+}
+
+/// Swap the interrupted task out of `mut_context` for the next ready one, if a switch is due.
+///
+/// - The `RegisterContext` saved by `__exception_entry` is overwritten in place, so the next
+///   task resumes through `__restore` like any other return from an exception.
+pub fn schedule(mut_context: &mut ExceptionMutContext) {
+    let mut scheduler = SCHEDULER.lock();
+    if !scheduler.need_resched {
+        return;
+    }
+    scheduler.need_resched = false;
+
+    let current = scheduler.current;
+    let is_running = matches!(scheduler.tasks[&current].state, TaskState::Running);
+    let is_exited = matches!(scheduler.tasks[&current].state, TaskState::Exited(_));
+    let next = match scheduler.ready.pop_front() {
+        Some(next) => next,
+        None if is_running => {
+            update_time_slice(&scheduler);
+            return;
+        }
+        None => IDLE,
+    };
+
+    // Save the interrupted task.
+    let task = scheduler.tasks.get_mut(&current).unwrap();
+    task.context = TaskContext {
+        registers: mut_context.register_context.clone(),
+        sepc: mut_context.sepc,
+        sstatus: mut_context.sstatus.0,
+    };
+    if is_running {
+        task.state = TaskState::Ready;
+        if current != IDLE {
+            scheduler.ready.push_back(current);
+        }
+    }
+
+    // Resume the next task.
+    let task = scheduler.tasks.get_mut(&next).unwrap();
+    task.state = TaskState::Running;
+    *mut_context.register_context = task.context.registers.clone();
+    mut_context.sepc = task.context.sepc;
+    mut_context.sstatus = Sstatus(task.context.sstatus);
+    page_table::activate(task.satp());
+    scheduler.current = next;
+    update_time_slice(&scheduler);
+
+    // The address space of an exited task is only freed once `satp` no longer points to it.
+    if is_exited {
+        let is_kept =
+            scheduler.tasks[&current].parent.is_some() || scheduler.has_live_threads(current);
+        let task = scheduler.tasks.get_mut(&current).unwrap();
+        if task.tgid == current && is_kept {
+            // A zombie until its parent reaps it, or a placeholder until its threads exit.
+            task.address_space = None;
+            task.cwd = None;
+            task._stack = None;
+        } else {
+            scheduler.tasks.remove(&current);
+        }
+    }
+}
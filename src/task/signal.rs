@@ -0,0 +1,669 @@
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use core::{mem, ops::BitOr};
+
+use crate::{
+    exception::{
+        fault::FaultReport,
+        instruction::{prepare, write_bytes},
+        ExceptionMutContext,
+    },
+    mm::address_space::Access,
+    supervisor_print, supervisor_println,
+    syscall::{self, Errno},
+    Spp, Sstatus,
+};
+
+use super::{
+    elf::SIGRETURN_TRAMPOLINE, exit_group, ExitReason, JobEvent, Scheduler, Task, TaskId,
+    TaskState, CHILD_EXIT, SCHEDULER,
+};
+
+/// Signal numbers, from `asm-generic/signal.h`.
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGSTKFLT: u32 = 16;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGURG: u32 = 23;
+pub const SIGXCPU: u32 = 24;
+pub const SIGXFSZ: u32 = 25;
+pub const SIGVTALRM: u32 = 26;
+pub const SIGPROF: u32 = 27;
+pub const SIGWINCH: u32 = 28;
+pub const SIGIO: u32 = 29;
+pub const SIGPWR: u32 = 30;
+pub const SIGSYS: u32 = 31;
+/// Signals are numbered from 1 to `NSIG`; those past 31 are real-time signals.
+pub const NSIG: u32 = 64;
+
+/// `sa_handler` values that are not handlers.
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// `sa_flags`.
+pub const SA_RESTART: usize = 0x1000_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// `si_code` values.
+pub const SI_USER: i32 = 0;
+pub const SI_KERNEL: i32 = 0x80;
+pub const SI_TKILL: i32 = -6;
+pub const ILL_ILLOPC: i32 = 1;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const BUS_ADRALN: i32 = 1;
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// `struct siginfo`.
+const SIGINFO_SIZE: usize = 128;
+/// Where `uc_sigmask` and `uc_mcontext` sit in `struct ucontext` on RV64.
+const SIGMASK_OFFSET: usize = 40;
+const MCONTEXT_OFFSET: usize = 176;
+/// `struct ucontext`: up to `uc_mcontext`, its 32 registers and the unused floating-point state.
+const UCONTEXT_SIZE: usize = MCONTEXT_OFFSET + 32 * 8 + 528;
+/// `struct rt_sigframe`: the `siginfo` a handler gets, then the `ucontext` it returns through.
+pub const FRAME_SIZE: usize = SIGINFO_SIZE + UCONTEXT_SIZE;
+/// `ss_flags` of a task with no alternate signal stack.
+const SS_DISABLE: u32 = 2;
+
+const RA: usize = 1;
+const SP: usize = 2;
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+
+/// A set of signals, bit `n - 1` for signal `n`, as `sigset_t` lays it out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    pub const EMPTY: SigSet = SigSet(0);
+    /// `SIGKILL` and `SIGSTOP` can be neither blocked nor caught.
+    pub const UNBLOCKABLE: SigSet = SigSet(1 << (SIGKILL - 1) | 1 << (SIGSTOP - 1));
+
+    pub fn of(signo: u32) -> SigSet {
+        SigSet(1 << (signo - 1))
+    }
+
+    pub fn contains(&self, signo: u32) -> bool {
+        self.0 & SigSet::of(signo).0 != 0
+    }
+
+    pub fn remove(self, other: SigSet) -> SigSet {
+        SigSet(self.0 & !other.0)
+    }
+}
+
+impl BitOr for SigSet {
+    type Output = SigSet;
+
+    fn bitor(self, rhs: SigSet) -> SigSet {
+        SigSet(self.0 | rhs.0)
+    }
+}
+
+/// What `rt_sigaction` sets for a signal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    /// A handler's address, [`SIG_DFL`] or [`SIG_IGN`].
+    pub handler: usize,
+    pub flags: usize,
+    /// Blocked on top of the task's mask while the handler runs.
+    pub mask: SigSet,
+}
+
+/// What a signal does to a task that neither catches nor ignores it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate; there are no core dumps, so only a fault report is printed, if any.
+    Core,
+    Ignore,
+    /// Stop every task of the process until `SIGCONT`.
+    Stop,
+    /// Resume a stopped process, which happens as the signal is sent.
+    Continue,
+}
+
+pub fn default_action(signo: u32) -> DefaultAction {
+    match signo {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::Core,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+fn is_stop(signo: u32) -> bool {
+    default_action(signo) == DefaultAction::Stop
+}
+
+/// What a handler gets in `siginfo_t` about a signal.
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo: u32,
+    pub code: i32,
+    /// The sender's PID, or the faulting address for a fault.
+    pub value: usize,
+}
+
+impl SigInfo {
+    /// A signal from `kill`, or with `code` [`SI_TKILL`] from `tkill`, sent by process `pid`.
+    pub fn user(signo: u32, code: i32, pid: TaskId) -> SigInfo {
+        SigInfo {
+            signo,
+            code,
+            value: pid.0,
+        }
+    }
+
+    /// A signal the kernel raises, like `SIGPIPE`.
+    pub fn kernel(signo: u32) -> SigInfo {
+        SigInfo {
+            signo,
+            code: SI_KERNEL,
+            value: 0,
+        }
+    }
+
+    fn encode(&self) -> [u8; SIGINFO_SIZE] {
+        let mut bytes = [0; SIGINFO_SIZE];
+        bytes[0..4].copy_from_slice(&self.signo.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.code.to_le_bytes());
+        // `si_pid` and a zero `si_uid`, or `si_addr`.
+        bytes[16..24].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+}
+
+/// The dispositions of every signal, shared by the threads of a process.
+#[derive(Debug, Clone)]
+pub struct SignalActions([SigAction; NSIG as usize]);
+
+impl SignalActions {
+    pub fn new() -> Self {
+        SignalActions([SigAction::default(); NSIG as usize])
+    }
+
+    pub fn get(&self, signo: u32) -> SigAction {
+        self.0[signo as usize - 1]
+    }
+
+    /// What `execve` keeps: ignored signals stay ignored, and handlers go back to the default.
+    pub fn for_exec(&self) -> Self {
+        let mut actions = SignalActions::new();
+        for (action, old) in actions.0.iter_mut().zip(&self.0) {
+            if old.handler == SIG_IGN {
+                action.handler = SIG_IGN;
+            }
+        }
+        actions
+    }
+
+    /// Whether sending `signo` would do nothing.
+    fn is_ignored(&self, signo: u32) -> bool {
+        match self.get(signo).handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signo) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+}
+
+impl Default for SignalActions {
+    fn default() -> Self {
+        SignalActions::new()
+    }
+}
+
+/// The signal state of one task.
+#[derive(Debug, Default)]
+pub struct Signals {
+    /// Signals held back from delivery.
+    pub blocked: SigSet,
+    /// Sent but not yet delivered, each with what the first sender said; standard signals do not
+    /// queue.
+    pending: BTreeMap<u32, SigInfo>,
+    /// The report of the fault that raised a pending signal, printed if it kills the task.
+    fault: Option<Box<FaultReport>>,
+    /// The task blocked in a system call that runs again when it resumes.
+    is_restarting: bool,
+}
+
+impl Signals {
+    pub fn pending(&self) -> SigSet {
+        self.pending
+            .keys()
+            .fold(SigSet::EMPTY, |set, signo| set | SigSet::of(*signo))
+    }
+
+    /// The lowest pending signal that is not blocked.
+    fn take_deliverable(&mut self) -> Option<SigInfo> {
+        let signo = *self
+            .pending
+            .keys()
+            .find(|signo| !self.blocked.contains(**signo))?;
+        self.pending.remove(&signo)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// No such task or process.
+    NotFound,
+    /// The target is a kernel thread.
+    NotPermitted,
+}
+
+fn is_user(task: &Task) -> bool {
+    Sstatus(task.context.sstatus).mode_before_exception() == Spp::User
+}
+
+/// Make `signo` pending on task `id`, waking it to take the signal if it may.
+///
+/// - `SIGCONT` resumes a stopped process and drops its pending stop signals; a stop signal drops a
+///   pending `SIGCONT`.
+/// - A signal that is ignored and not blocked is dropped.
+fn send(scheduler: &mut Scheduler, id: TaskId, info: SigInfo) {
+    let signo = info.signo;
+    let tgid = scheduler.tasks[&id].tgid;
+    if signo == SIGCONT || is_stop(signo) {
+        let mut resumed = Vec::new();
+        for (thread, task) in scheduler.tasks.iter_mut() {
+            if task.tgid != tgid {
+                continue;
+            }
+            if signo != SIGCONT {
+                task.signals.pending.remove(&SIGCONT);
+                continue;
+            }
+            task.signals.pending.retain(|signo, _| !is_stop(*signo));
+            if matches!(task.state, TaskState::Stopped) {
+                task.state = TaskState::Ready;
+                resumed.push(*thread);
+            }
+        }
+        if !resumed.is_empty() {
+            scheduler.ready.extend(resumed);
+            scheduler.need_resched = true;
+            report_to_parent(scheduler, tgid, JobEvent::Continued);
+        }
+    }
+
+    let task = scheduler.tasks.get_mut(&id).unwrap();
+    let is_blocked = task.signals.blocked.contains(signo);
+    if !is_blocked && task.signal_actions.lock().is_ignored(signo) {
+        return;
+    }
+    task.signals.pending.entry(signo).or_insert(info);
+    let should_wake = match task.state {
+        TaskState::Blocked => !is_blocked,
+        TaskState::Stopped => signo == SIGKILL,
+        _ => false,
+    };
+    if should_wake {
+        task.state = TaskState::Ready;
+        scheduler.ready.push_back(id);
+        scheduler.need_resched = true;
+    }
+}
+
+/// The live task of process `tgid` to take `signo`: one that does not block it, if any.
+fn process_target(scheduler: &Scheduler, tgid: TaskId, signo: u32) -> Option<TaskId> {
+    let mut threads = scheduler
+        .tasks
+        .iter()
+        .filter(|(_, task)| task.tgid == tgid && !matches!(task.state, TaskState::Exited(_)));
+    let first = threads.clone().next().map(|(id, _)| *id);
+    threads
+        .find(|(_, task)| !task.signals.blocked.contains(signo))
+        .map(|(id, _)| *id)
+        .or(first)
+}
+
+/// Send `info` to process `tgid`.
+///
+/// - Signal 0 sends nothing, but still checks the target.
+/// - A zombie takes signals and does nothing with them.
+pub fn send_to_process(tgid: TaskId, info: SigInfo) -> Result<(), SendError> {
+    let mut scheduler = SCHEDULER.lock();
+    let leader = scheduler
+        .tasks
+        .get(&tgid)
+        .filter(|task| task.tgid == tgid)
+        .ok_or(SendError::NotFound)?;
+    if !is_user(leader) {
+        return Err(SendError::NotPermitted);
+    }
+    if info.signo == 0 {
+        return Ok(());
+    }
+    if let Some(id) = process_target(&scheduler, tgid, info.signo) {
+        send(&mut scheduler, id, info);
+    }
+    Ok(())
+}
+
+/// Send `info` to every user process but init, and but the current one unless `include_self`.
+pub fn send_to_all(info: SigInfo, include_self: bool) -> Result<(), SendError> {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.tasks[&scheduler.current].tgid;
+    let processes: Vec<TaskId> = scheduler
+        .tasks
+        .iter()
+        .filter(|(id, task)| {
+            task.tgid == **id
+                && is_user(task)
+                && Some(**id) != scheduler.init
+                && (include_self || **id != current)
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    if processes.is_empty() {
+        return Err(SendError::NotFound);
+    }
+    if info.signo == 0 {
+        return Ok(());
+    }
+    for tgid in processes {
+        if let Some(id) = process_target(&scheduler, tgid, info.signo) {
+            send(&mut scheduler, id, info);
+        }
+    }
+    Ok(())
+}
+
+/// Send `info` to task `tid`, which must belong to process `tgid` if given.
+pub fn send_to_task(tid: TaskId, tgid: Option<TaskId>, info: SigInfo) -> Result<(), SendError> {
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler
+        .tasks
+        .get(&tid)
+        .filter(|task| tgid.is_none_or(|tgid| tgid == task.tgid))
+        .filter(|task| !matches!(task.state, TaskState::Exited(_)))
+        .ok_or(SendError::NotFound)?;
+    if !is_user(task) {
+        return Err(SendError::NotPermitted);
+    }
+    if info.signo != 0 {
+        send(&mut scheduler, tid, info);
+    }
+    Ok(())
+}
+
+/// Send a signal the kernel raises to the current task.
+pub fn raise(info: SigInfo) {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    send(&mut scheduler, current, info);
+}
+
+/// Raise the signal for an unresolved fault of the current task.
+///
+/// - A fault cannot be blocked or ignored: the signal is unblocked and gets the default action
+///   then, which prints `report` as it terminates the process.
+/// - Returns whether the signal gets that default action, rather than a handler.
+pub fn raise_fault(signo: u32, code: i32, report: FaultReport) -> bool {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    let task = scheduler.tasks.get_mut(&current).unwrap();
+    let mut actions = task.signal_actions.lock();
+    if task.signals.blocked.contains(signo) || actions.get(signo).handler == SIG_IGN {
+        actions.0[signo as usize - 1] = SigAction::default();
+        task.signals.blocked = task.signals.blocked.remove(SigSet::of(signo));
+    }
+    let is_fatal = actions.get(signo).handler == SIG_DFL;
+    drop(actions);
+    let info = SigInfo {
+        signo,
+        code,
+        value: report.stval,
+    };
+    task.signals.fault = Some(Box::new(report));
+    send(&mut scheduler, current, info);
+    is_fatal
+}
+
+/// Set the action for `signo` in the current process, returning the old one.
+///
+/// - Ignoring a signal drops it where it is pending.
+pub fn set_action(signo: u32, action: SigAction) -> SigAction {
+    let mut scheduler = SCHEDULER.lock();
+    let current = &scheduler.tasks[&scheduler.current];
+    let tgid = current.tgid;
+    let mut actions = current.signal_actions.lock();
+    let old = actions.get(signo);
+    actions.0[signo as usize - 1] = action;
+    let is_ignored = actions.is_ignored(signo);
+    drop(actions);
+    if is_ignored {
+        for task in scheduler.tasks.values_mut() {
+            if task.tgid == tgid {
+                task.signals.pending.remove(&signo);
+            }
+        }
+    }
+    old
+}
+
+/// Replace the current task's mask with what `f` makes of it, returning the old one.
+///
+/// - `SIGKILL` and `SIGSTOP` stay unblocked.
+pub fn update_blocked(f: impl FnOnce(SigSet) -> SigSet) -> SigSet {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    let signals = &mut scheduler.tasks.get_mut(&current).unwrap().signals;
+    let old = signals.blocked;
+    signals.blocked = f(old).remove(SigSet::UNBLOCKABLE);
+    old
+}
+
+/// Note that the current task blocked in a system call that runs again when it resumes, so that
+/// a handler without [`SA_RESTART`] makes it fail with `EINTR` instead.
+pub fn set_restarting() {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    scheduler
+        .tasks
+        .get_mut(&current)
+        .unwrap()
+        .signals
+        .is_restarting = true;
+}
+
+/// Keep `event` for the parent of process `tgid` to `wait4` for, and send the parent `SIGCHLD`.
+fn report_to_parent(scheduler: &mut Scheduler, tgid: TaskId, event: JobEvent) {
+    let Some(leader) = scheduler.tasks.get_mut(&tgid) else {
+        return;
+    };
+    leader.job_event = Some(event);
+    let Some(parent) = leader.parent else {
+        return;
+    };
+    let code = match event {
+        JobEvent::Stopped(_) => CLD_STOPPED,
+        JobEvent::Continued => CLD_CONTINUED,
+    };
+    if let Some(id) = process_target(scheduler, parent, SIGCHLD) {
+        send(scheduler, id, SigInfo::user(SIGCHLD, code, tgid));
+    }
+    CHILD_EXIT.wake_all_in(scheduler);
+}
+
+/// Stop every task of process `tgid` for `signo`.
+fn stop_process(scheduler: &mut Scheduler, tgid: TaskId, signo: u32) {
+    for task in scheduler.tasks.values_mut() {
+        if task.tgid == tgid && !matches!(task.state, TaskState::Exited(_)) {
+            task.state = TaskState::Stopped;
+        }
+    }
+    let tasks = &scheduler.tasks;
+    scheduler.ready.retain(|id| tasks[id].tgid != tgid);
+    scheduler.need_resched = true;
+    report_to_parent(scheduler, tgid, JobEvent::Stopped(signo));
+}
+
+/// Act on the pending signals of the user task about to resume from `mut_context`.
+///
+/// - Ignored signals are dropped.
+/// - A handler runs on the task's stack from a signal frame holding the interrupted registers;
+///   it returns through `rt_sigreturn` from a trampoline mapped by the ELF loader.
+/// - Returns `true` if the task stopped or exited, so another one must be switched in.
+pub fn deliver(mut_context: &mut ExceptionMutContext) -> bool {
+    if mut_context.sstatus.mode_before_exception() != Spp::User {
+        return false;
+    }
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    let task = scheduler.tasks.get_mut(&current).unwrap();
+    if !matches!(task.state, TaskState::Running) {
+        return false;
+    }
+    let is_restarting = mem::take(&mut task.signals.is_restarting);
+    let actions = task.signal_actions.clone();
+    let (info, action) = loop {
+        let Some(info) = task.signals.take_deliverable() else {
+            return false;
+        };
+        let action = actions.lock().get(info.signo);
+        match (action.handler, default_action(info.signo)) {
+            (SIG_IGN, _) | (SIG_DFL, DefaultAction::Ignore | DefaultAction::Continue) => {}
+            _ => break (info, action),
+        }
+    };
+
+    if action.handler == SIG_DFL {
+        if is_stop(info.signo) {
+            task.signals.is_restarting = is_restarting;
+            let tgid = task.tgid;
+            stop_process(&mut scheduler, tgid, info.signo);
+            return true;
+        }
+        let report = task.signals.fault.take();
+        drop(scheduler);
+        if let Some(report) = &report {
+            supervisor_println!("Task {:?} terminated", current);
+            supervisor_println!("{}", report);
+        }
+        exit_group(ExitReason::Signal(info.signo, report));
+        return true;
+    }
+
+    let mask = task.signals.blocked;
+    let mut blocked = mask | action.mask;
+    if action.flags & SA_NODEFER == 0 {
+        blocked = blocked | SigSet::of(info.signo);
+    }
+    task.signals.blocked = blocked.remove(SigSet::UNBLOCKABLE);
+    task.signals.fault = None;
+    if action.flags & SA_RESETHAND != 0 {
+        actions.lock().0[info.signo as usize - 1] = SigAction::default();
+    }
+    let sleep = task.sleep.take();
+    let written = mem::take(&mut task.written);
+    drop(scheduler);
+
+    if let Some(sleep) = sleep {
+        let errno = syscall::interrupt_nanosleep(mut_context, sleep);
+        mut_context.register_context.x[A0] = errno.to_return_value();
+        mut_context.sepc += 4;
+    } else if written != 0 {
+        // A write cut short returns what it wrote, whatever `SA_RESTART` says.
+        mut_context.register_context.x[A0] = written;
+        mut_context.sepc += 4;
+    } else if is_restarting && action.flags & SA_RESTART == 0 {
+        mut_context.register_context.x[A0] = Errno::EINTR.to_return_value();
+        mut_context.sepc += 4;
+    }
+    // A stack pointer too low for the frame fails as writing it would.
+    let frame = mut_context.register_context.x[SP]
+        .checked_sub(FRAME_SIZE)
+        .map(|frame| frame & !15);
+    let bytes = encode_frame(mut_context, &info, mask);
+    let Some(frame) =
+        frame.filter(|frame| prepare(mut_context, *frame, bytes.len(), Access::Write).is_ok())
+    else {
+        exit_group(ExitReason::Signal(SIGSEGV, None));
+        return true;
+    };
+    write_bytes(mut_context, frame, &bytes);
+
+    let registers = &mut mut_context.register_context.x;
+    registers[A0] = info.signo as usize;
+    registers[A1] = frame;
+    registers[A2] = frame + SIGINFO_SIZE;
+    registers[RA] = SIGRETURN_TRAMPOLINE;
+    registers[SP] = frame;
+    mut_context.sepc = action.handler;
+    false
+}
+
+/// `struct rt_sigframe` for the interrupted registers in `mut_context` and the mask to restore.
+fn encode_frame(mut_context: &ExceptionMutContext, info: &SigInfo, mask: SigSet) -> Vec<u8> {
+    let mut frame = vec![0; FRAME_SIZE];
+    frame[..SIGINFO_SIZE].copy_from_slice(&info.encode());
+    let ucontext = &mut frame[SIGINFO_SIZE..];
+    // `uc_stack.ss_flags`
+    ucontext[24..28].copy_from_slice(&SS_DISABLE.to_le_bytes());
+    ucontext[SIGMASK_OFFSET..SIGMASK_OFFSET + 8].copy_from_slice(&mask.0.to_le_bytes());
+    // `sc_regs`: `pc` in place of `x0`, then `x1` to `x31`.
+    let registers = &mut_context.register_context.x;
+    for (index, chunk) in ucontext[MCONTEXT_OFFSET..MCONTEXT_OFFSET + 32 * 8]
+        .chunks_exact_mut(8)
+        .enumerate()
+    {
+        let value = match index {
+            0 => mut_context.sepc,
+            _ => registers[index],
+        };
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    frame
+}
+
+/// Resume from the signal frame `frame` that a handler returned through, as `rt_sigreturn` does.
+///
+/// - The registers and the mask come from the `ucontext`, which the handler may have changed;
+///   the task stays in user mode.
+pub fn restore_frame(mut_context: &mut ExceptionMutContext, frame: &[u8; FRAME_SIZE]) {
+    let ucontext = &frame[SIGINFO_SIZE..];
+    let mask = u64::from_le_bytes(
+        ucontext[SIGMASK_OFFSET..SIGMASK_OFFSET + 8]
+            .try_into()
+            .unwrap(),
+    );
+    update_blocked(|_| SigSet(mask));
+    let registers = &mut mut_context.register_context.x;
+    for (index, chunk) in ucontext[MCONTEXT_OFFSET..MCONTEXT_OFFSET + 32 * 8]
+        .chunks_exact(8)
+        .enumerate()
+    {
+        let value = usize::from_le_bytes(chunk.try_into().unwrap());
+        match index {
+            0 => mut_context.sepc = value,
+            _ => registers[index] = value,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use alloc::collections::VecDeque;
+
+use spin::Mutex;
+
+use crate::exception::without_interrupts;
+
+use super::{block_current, wake, yield_now, Scheduler, TaskId};
+
+/// Kernel threads sleeping until an event, e.g. a keypress.
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<TaskId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Put the current kernel thread to sleep if `should_sleep` holds.
+    ///
+    /// - `should_sleep` runs with interrupts off, so the event cannot be missed between the check
+    ///   and the sleep.
+    /// - Returns after a wake-up; callers re-check their condition, as another thread may have
+    ///   consumed the event first.
+    pub fn sleep_if(&self, should_sleep: impl FnOnce() -> bool) {
+        without_interrupts(|| {
+            if self.block_if(should_sleep) {
+                // Taken as soon as interrupts are back on, and switches away.
+                yield_now();
+            }
+        });
+    }
+
+    /// Block the current task on the queue if `should_block` holds, returning whether it did.
+    ///
+    /// - For system calls: the task sleeps from the end of the exception, and restarts the call
+    ///   once woken.
+    pub fn block_if(&self, should_block: impl FnOnce() -> bool) -> bool {
+        without_interrupts(|| {
+            if !should_block() {
+                return false;
+            }
+            let id = block_current();
+            self.waiters.lock().push_back(id);
+            true
+        })
+    }
+
+    /// Wake every sleeping thread.
+    pub fn wake_all(&self) {
+        without_interrupts(|| {
+            for id in self.waiters.lock().drain(..) {
+                wake(id);
+            }
+        });
+    }
+
+    /// [`WaitQueue::wake_all`] for a caller that holds the scheduler.
+    pub(super) fn wake_all_in(&self, scheduler: &mut Scheduler) {
+        without_interrupts(|| {
+            for id in self.waiters.lock().drain(..) {
+                scheduler.wake(id);
+            }
+        });
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        WaitQueue::new()
+    }
+}
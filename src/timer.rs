@@ -0,0 +1,257 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use spin::{Mutex, Once};
+
+use crate::{
+    clock::{self, Instant},
+    device_tree,
+    exception::without_interrupts,
+    sbi_call,
+};
+
+/// A deadline the comparator never reaches, which also clears a pending timer interrupt.
+const NEVER: u64 = u64::MAX;
+
+/// How often [`measure_overhead`] programs each comparator.
+const OVERHEAD_ITERATIONS: u32 = 1000;
+
+/// How the hart's timer comparator is programmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparator {
+    /// `Extension::SetTimer`, an `ecall` into the firmware, which writes `mtimecmp`.
+    Sbi,
+    /// The Sstc extension's `stimecmp` CSR, written directly.
+    Sstc,
+}
+
+impl Comparator {
+    fn set(self, deadline: u64) {
+        match self {
+            Comparator::Sbi => sbi_call::set_timer(deadline).expect("Failed to set timer"),
+            // `stimecmp`, by number, as the assembler only knows the name with Sstc enabled.
+            Comparator::Sstc => unsafe {
+                asm!("csrw 0x14d, {}", in(reg) deadline);
+            },
+        }
+    }
+}
+
+static COMPARATOR: Once<Comparator> = Once::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static TIMERS: Mutex<Timers> = Mutex::new(Timers {
+    queue: BTreeMap::new(),
+    deadlines: BTreeMap::new(),
+    programmed: NEVER,
+});
+
+type Callback = Box<dyn FnMut() + Send>;
+
+struct Entry {
+    period: Option<u64>,
+    callback: Callback,
+}
+
+/// Software timers multiplexed onto the hart's single comparator.
+struct Timers {
+    /// Ordered by deadline, then by creation.
+    queue: BTreeMap<(u64, u64), Entry>,
+    /// The deadline of every live timer, including one whose callback is running.
+    deadlines: BTreeMap<u64, u64>,
+    /// What the comparator was last set to.
+    programmed: u64,
+}
+
+impl Timers {
+    fn earliest(&self) -> u64 {
+        self.queue
+            .keys()
+            .next()
+            .map_or(NEVER, |(deadline, _)| *deadline)
+    }
+
+    /// Point the comparator at the earliest deadline, or turn it off if there is none.
+    fn program(&mut self) {
+        let deadline = self.earliest();
+        if deadline != self.programmed {
+            comparator().set(deadline);
+            self.programmed = deadline;
+        }
+    }
+}
+
+/// A handle to a software timer.
+///
+/// - Dropping the handle leaves the timer running; [`Timer::cancel`] stops it.
+/// - Callbacks run in the timer interrupt, with interrupts off, and must not block.
+#[derive(Debug)]
+pub struct Timer {
+    id: u64,
+}
+
+impl Timer {
+    /// Run `callback` once at `deadline`.
+    pub fn at(deadline: Instant, callback: impl FnMut() + Send + 'static) -> Timer {
+        Timer::insert(deadline.ticks(), None, Box::new(callback))
+    }
+
+    /// Run `callback` once after `duration`.
+    pub fn after(duration: Duration, callback: impl FnMut() + Send + 'static) -> Timer {
+        Timer::at(Instant::now() + duration, callback)
+    }
+
+    /// Run `callback` every `period`, starting one `period` from now.
+    ///
+    /// - A period shorter than a tick is rounded up to one.
+    pub fn every(period: Duration, callback: impl FnMut() + Send + 'static) -> Timer {
+        let period = clock::ticks(period).max(1);
+        let deadline = Instant::now().ticks().saturating_add(period);
+        Timer::insert(deadline, Some(period), Box::new(callback))
+    }
+
+    fn insert(deadline: u64, period: Option<u64>, callback: Callback) -> Timer {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            timers
+                .queue
+                .insert((deadline, id), Entry { period, callback });
+            timers.deadlines.insert(id, deadline);
+            timers.program();
+        });
+        Timer { id }
+    }
+
+    /// Whether the callback is still due to run, at least once more.
+    pub fn is_pending(&self) -> bool {
+        without_interrupts(|| TIMERS.lock().deadlines.contains_key(&self.id))
+    }
+
+    /// Stop the timer; returns `false` if a one-shot timer already fired.
+    ///
+    /// - A periodic timer cancelled from its own callback does not run again.
+    pub fn cancel(self) -> bool {
+        without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            let Some(deadline) = timers.deadlines.remove(&self.id) else {
+                return false;
+            };
+            timers.queue.remove(&(deadline, self.id));
+            timers.program();
+            true
+        })
+    }
+}
+
+/// Whether every hart in the device tree implements Sstc.
+///
+/// - Looks in `riscv,isa-extensions`, or failing that in the `_`-separated multi-letter
+///   extensions of `riscv,isa`, like `rv64imafdc_zicsr_sstc`.
+fn has_sstc() -> bool {
+    let Some(cpus) = device_tree::get().and_then(|tree| tree.find_path("/cpus")) else {
+        return false;
+    };
+    let mut cpus = cpus
+        .children()
+        .filter(|cpu| cpu.property_str("device_type") == Some("cpu"))
+        .peekable();
+    cpus.peek().is_some()
+        && cpus.all(|cpu| {
+            if let Some(mut extensions) = cpu.property_strings("riscv,isa-extensions") {
+                return extensions.any(|extension| extension == "sstc");
+            }
+            cpu.property_str("riscv,isa")
+                .is_some_and(|isa| isa.split('_').skip(1).any(|extension| extension == "sstc"))
+        })
+}
+
+/// Pick the comparator: `stimecmp` if the harts have Sstc, the firmware otherwise.
+///
+/// - The firmware must have set `menvcfg.STCE`, as OpenSBI does when it finds Sstc.
+pub fn init() {
+    COMPARATOR.call_once(|| match has_sstc() {
+        true => Comparator::Sstc,
+        false => Comparator::Sbi,
+    });
+}
+
+pub fn comparator() -> Comparator {
+    *COMPARATOR.get().unwrap_or(&Comparator::Sbi)
+}
+
+/// The average time to program the comparator once, as every tick does.
+#[derive(Debug, Clone, Copy)]
+pub struct Overhead {
+    pub sbi: Duration,
+    /// `None` without Sstc.
+    pub sstc: Option<Duration>,
+}
+
+/// Time both ways of programming the comparator.
+///
+/// - Runs with interrupts off and leaves the comparator as the timers need it.
+pub fn measure_overhead() -> Overhead {
+    let measure = |comparator: Comparator| {
+        let start = Instant::now();
+        for _ in 0..OVERHEAD_ITERATIONS {
+            comparator.set(NEVER);
+        }
+        start.elapsed() / OVERHEAD_ITERATIONS
+    };
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let overhead = Overhead {
+            sbi: measure(Comparator::Sbi),
+            sstc: (comparator() == Comparator::Sstc).then(|| measure(Comparator::Sstc)),
+        };
+        // Both comparators now say never.
+        timers.programmed = 0;
+        timers.program();
+        overhead
+    })
+}
+
+/// Run the callbacks of the expired timers and arm the comparator for the next one.
+///
+/// - Called from the supervisor timer interrupt.
+pub fn handle_interrupt() {
+    // The interrupt stays pending until the comparator moves.
+    TIMERS.lock().programmed = 0;
+    loop {
+        let expired = {
+            let mut timers = TIMERS.lock();
+            match timers.queue.first_entry() {
+                Some(entry) if entry.key().0 <= Instant::now().ticks() => {
+                    Some(entry.remove_entry())
+                }
+                _ => None,
+            }
+        };
+        let Some(((deadline, id), mut entry)) = expired else {
+            break;
+        };
+
+        // Unlocked, so that callbacks can start and cancel timers.
+        (entry.callback)();
+
+        let mut timers = TIMERS.lock();
+        match entry.period {
+            // Skip the periods missed, e.g. while stopped in the debugger, rather than firing
+            // them back to back.
+            Some(period) if timers.deadlines.contains_key(&id) => {
+                let missed = Instant::now().ticks().saturating_sub(deadline) / period;
+                let deadline = deadline + (missed + 1) * period;
+                timers.queue.insert((deadline, id), entry);
+                timers.deadlines.insert(id, deadline);
+            }
+            _ => {
+                timers.deadlines.remove(&id);
+            }
+        }
+    }
+    TIMERS.lock().program();
+}